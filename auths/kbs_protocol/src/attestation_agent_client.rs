@@ -7,8 +7,13 @@
 //! replaced with a client lib inside attestation-agent.
 
 use anyhow::*;
+use async_trait::async_trait;
 use kbs_types::Tee;
-use tonic::transport::Channel;
+use tokio::net::UnixStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+use crate::evidence_provider::EvidenceProvider;
 
 use self::attestation_agent::{
     attestation_agent_service_client::AttestationAgentServiceClient, GetAttesterTypeRequest,
@@ -22,22 +27,64 @@ mod attestation_agent {
     tonic::include_proto!("attestation_agent");
 }
 
-const AA_POD_DOMAIN: &str = "http://attestation-agent";
+/// Default endpoint of the attestation-agent.
+pub const AA_POD_DOMAIN: &str = "http://attestation-agent";
+
+/// Scheme of the endpoints of Unix domain sockets.
+const UNIX_SOCKET_SCHEME: &str = "unix://";
+
+/// Get the path of the Unix domain socket if the `endpoint` is one, s.t. the
+/// endpoint is either `unix://<path>` or an absolute path.
+fn unix_socket_path(endpoint: &str) -> Option<&str> {
+    match endpoint.strip_prefix(UNIX_SOCKET_SCHEME) {
+        Some(path) => Some(path),
+        None if endpoint.starts_with('/') => Some(endpoint),
+        None => None,
+    }
+}
 
 pub struct Client {
     inner: AttestationAgentServiceClient<Channel>,
 }
 
 impl Client {
-    pub async fn new() -> Result<Self> {
-        let inner = AttestationAgentServiceClient::connect(AA_POD_DOMAIN).await?;
+    /// Connect to the attestation-agent listening on `endpoint`. The endpoint
+    /// can be a URI like [`AA_POD_DOMAIN`] or `http://127.0.0.1:50002`, or a
+    /// Unix domain socket like `unix:///run/attestation-agent.sock` or
+    /// `/run/attestation-agent.sock`.
+    pub async fn new(endpoint: &str) -> Result<Self> {
+        let channel = match unix_socket_path(endpoint) {
+            Some(path) => {
+                let path = path.to_string();
+
+                // The URI is not used to connect but only required by the Endpoint.
+                Endpoint::try_from("http://[::]:50051")?
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        UnixStream::connect(path.clone())
+                    }))
+                    .await
+            }
+            None => {
+                Endpoint::from_shared(endpoint.to_string())
+                    .with_context(|| format!("illegal attestation-agent endpoint {endpoint}"))?
+                    .connect()
+                    .await
+            }
+        }
+        .with_context(|| format!("connect to attestation-agent {endpoint}"))?;
+
+        let inner = AttestationAgentServiceClient::new(channel);
         Ok(Self { inner })
     }
+}
 
-    pub async fn detect_tee_type(&mut self) -> Result<Tee> {
+#[async_trait]
+impl EvidenceProvider for Client {
+    async fn get_tee_type(&self) -> Result<Tee> {
         let req = tonic::Request::new(GetAttesterTypeRequest {});
         let res = self
             .inner
+            .clone()
             .get_attester_type(req)
             .await
             .context("call GetAttesterType API failed")?
@@ -47,13 +94,14 @@ impl Client {
         Ok(typ)
     }
 
-    pub async fn get_evidence(&mut self, challenge: String) -> Result<Vec<u8>> {
+    async fn get_evidence(&self, challenge: String) -> Result<Vec<u8>> {
         let req = tonic::Request::new(GetEvidenceRequest { challenge });
         let res = self
             .inner
+            .clone()
             .get_evidence(req)
             .await
-            .context("call GetAttesterType API failed")?
+            .context("call GetEvidence API failed")?
             .into_inner();
         Ok(res.evidence)
     }
@@ -63,6 +111,77 @@ impl Client {
 mod tests {
     use kbs_types::Tee;
     use rstest::rstest;
+    use tokio::net::UnixListener;
+    use tokio_stream::wrappers::UnixListenerStream;
+    use tonic::{transport::Server, Request, Response, Status};
+
+    use super::{
+        attestation_agent::{
+            attestation_agent_service_server::{
+                AttestationAgentService, AttestationAgentServiceServer,
+            },
+            GetAttesterTypeRequest, GetAttesterTypeResponse, GetEvidenceRequest,
+            GetEvidenceResponse,
+        },
+        unix_socket_path, Client,
+    };
+    use crate::evidence_provider::EvidenceProvider;
+
+    struct FakeAttestationAgent;
+
+    #[tonic::async_trait]
+    impl AttestationAgentService for FakeAttestationAgent {
+        async fn get_evidence(
+            &self,
+            request: Request<GetEvidenceRequest>,
+        ) -> Result<Response<GetEvidenceResponse>, Status> {
+            let evidence = request.into_inner().challenge.into_bytes();
+            Ok(Response::new(GetEvidenceResponse { evidence }))
+        }
+
+        async fn get_attester_type(
+            &self,
+            _request: Request<GetAttesterTypeRequest>,
+        ) -> Result<Response<GetAttesterTypeResponse>, Status> {
+            Ok(Response::new(GetAttesterTypeResponse {
+                r#type: "\"sample\"".into(),
+            }))
+        }
+    }
+
+    #[rstest]
+    #[case("unix:///run/aa.sock", Some("/run/aa.sock"))]
+    #[case("/run/aa.sock", Some("/run/aa.sock"))]
+    #[case("http://127.0.0.1:50002", None)]
+    #[case("http://attestation-agent", None)]
+    fn parse_unix_socket_path(#[case] endpoint: &str, #[case] expected: Option<&str>) {
+        assert_eq!(unix_socket_path(endpoint), expected);
+    }
+
+    #[rstest]
+    #[case("unix://")]
+    #[case("")]
+    #[tokio::test]
+    async fn connect_unix_socket(#[case] scheme: &str) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let path = dir.path().join("aa.sock");
+        let listener = UnixListener::bind(&path).expect("bind unix socket failed");
+        tokio::spawn(
+            Server::builder()
+                .add_service(AttestationAgentServiceServer::new(FakeAttestationAgent))
+                .serve_with_incoming(UnixListenerStream::new(listener)),
+        );
+
+        let endpoint = format!("{scheme}{}", path.display());
+        let client = Client::new(&endpoint).await.expect("connect failed");
+        let tee = client.get_tee_type().await.expect("detect tee failed");
+        assert_eq!(tee, Tee::Sample);
+        let evidence = client
+            .get_evidence("challenge".into())
+            .await
+            .expect("get evidence failed");
+        assert_eq!(evidence, b"challenge");
+    }
 
     #[rstest]
     #[case("\"sgx\"", Tee::Sgx)]
@@ -3,51 +3,93 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::*;
-use async_recursion::async_recursion;
 use base64::Engine;
-use crypto::{rust::rsa::PaddingMode, WrapType};
-use kbs_types::{Attestation, Challenge, ErrorInformation, Request, Response, Tee};
-use log::info;
+use crypto::jwe::Jwe;
+use kbs_types::{Challenge, ErrorInformation, Request, Response, Tee};
+use log::{info, warn};
 use resource_uri::ResourceUri;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha384};
-use zeroize::Zeroizing;
+use tokio::sync::{Mutex, RwLock};
 
-use crate::{attestation_agent_client, tee_pubkey::TeeKeyPair, KBS_PROTOCOL_VERSION};
+use crate::{
+    evidence_provider::EvidenceProvider,
+    tee_pubkey::{LegacyProtectedHeader, TeeKeyAlgorithm, TeeKeyPair, TeePubKey},
+    token::Token,
+    Error, KBS_PROTOCOL_VERSION,
+};
 
-const KBS_REQ_TIMEOUT_SEC: u64 = 60;
+/// Default timeout of the requests to the KBS.
+pub const KBS_REQ_TIMEOUT_SEC: u64 = 60;
 
 pub const KBS_URL_PREFIX: &str = "kbs/v0";
 
+/// The attestation token is refreshed by a new handshake if it expires
+/// within this time, to avoid the token expiring during a request.
+const TOKEN_REFRESH_MARGIN_SEC: u64 = 30;
+
+/// Key of the `extra-params` of the auth request listing the offered TEE key
+/// algorithms
+const TEE_KEY_ALGORITHMS_PARAM: &str = "tee-key-algorithms";
+
+/// Key of the `extra-params` of the challenge naming the TEE key algorithm
+/// selected by the KBS
+const TEE_KEY_ALGORITHM_PARAM: &str = "tee-key-algorithm";
+
+/// A session with the KBS established by a handshake.
+struct Session {
+    /// KBS Host URL
+    kbs_host_url: String,
+
+    /// The asymmetric key pair inside the TEE
+    tee_key: TeeKeyPair,
+
+    /// Attestation token got from the handshake
+    token: Token,
+}
+
 /// This Handshaker is used to connect to the remote KBS. Also, it will call
-/// the local attestation-agent to gather enough evidence for handshake.
+/// the evidence provider, e.g. the local attestation-agent, to gather enough
+/// evidence for handshake.
+///
+/// A Handshaker can be shared by concurrent requests. Only the handshakes
+/// are serialized, and the requests waiting for a re-attestation reuse the
+/// session it establishes.
 pub struct Handshaker {
     /// TEE Type
     tee: Tee,
 
-    /// The asymmetric key pair inside the TEE
-    pub tee_key: Option<TeeKeyPair>,
+    /// Used to get the evidence
+    evidence_provider: Arc<dyn EvidenceProvider>,
 
-    /// Used to connect to the local attestation-agent to get the evidence
-    aa_client: attestation_agent_client::Client,
+    /// The TEE key algorithms offered to the KBS in order of preference
+    tee_key_algorithms: Vec<TeeKeyAlgorithm>,
 
     /// Http client
     http_client: reqwest::Client,
 
-    /// KBS Host URL
-    kbs_host_url: Option<String>,
+    /// The session of the last handshake
+    session: RwLock<Option<Arc<Session>>>,
+
+    /// Held during a handshake, because the KBS tracks the handshake by the
+    /// cookie shared by all the requests of the http client.
+    handshake_lock: Mutex<()>,
 }
 
 impl Handshaker {
-    pub async fn new() -> Result<Handshaker> {
-        // Create a connection to the attestation-agent
-        let mut aa_client = attestation_agent_client::Client::new().await?;
-
+    /// Create a new Handshaker. The evidence is got from `evidence_provider`,
+    /// e.g. an [`crate::attestation_agent_client::Client`] connecting to the
+    /// local attestation-agent. `timeout` is the timeout of each request to
+    /// the KBS.
+    pub async fn new(
+        evidence_provider: Arc<dyn EvidenceProvider>,
+        timeout: Duration,
+    ) -> Result<Handshaker> {
         // Detect TEE type of the current platform.
-        let tee = aa_client.detect_tee_type().await?;
+        let tee = evidence_provider.get_tee_type().await?;
 
         let http_client = reqwest::Client::builder()
             .cookie_store(true)
@@ -55,20 +97,28 @@ impl Handshaker {
                 "attestation-agent-kbs-client/{}",
                 env!("CARGO_PKG_VERSION")
             ))
-            .timeout(Duration::from_secs(KBS_REQ_TIMEOUT_SEC))
+            .timeout(timeout)
             .build()
             .map_err(|e| anyhow!("Build KBS http client failed: {:?}", e))?;
 
         Ok(Handshaker {
             tee,
-            tee_key: None,
-            aa_client,
+            evidence_provider,
+            tee_key_algorithms: TeeKeyAlgorithm::defaults(),
             http_client,
-            kbs_host_url: None,
+            session: RwLock::new(None),
+            handshake_lock: Mutex::new(()),
         })
     }
 
-    async fn generate_evidence(&mut self, nonce: String, tee_key: Vec<&[u8]>) -> Result<String> {
+    /// Set the TEE key algorithms offered to the KBS in order of preference.
+    /// All the algorithms are offered by default.
+    pub fn with_tee_key_algorithms(mut self, algorithms: Vec<TeeKeyAlgorithm>) -> Self {
+        self.tee_key_algorithms = algorithms;
+        self
+    }
+
+    async fn generate_evidence(&self, nonce: String, tee_key: Vec<&[u8]>) -> Result<String> {
         let mut hasher = Sha384::new();
         let engine = base64::engine::general_purpose::STANDARD;
         hasher.update(nonce.as_bytes());
@@ -78,7 +128,7 @@ impl Handshaker {
         let ehd = engine.encode(hasher.finalize());
 
         let tee_evidence = self
-            .aa_client
+            .evidence_provider
             .get_evidence(ehd)
             .await
             .map_err(|e| anyhow!("Get TEE evidence failed: {:?}", e))
@@ -87,26 +137,59 @@ impl Handshaker {
         Ok(tee_evidence)
     }
 
-    pub async fn handshake(&mut self, kbs_host_url: String) -> Result<String> {
+    /// Attest to the KBS of `kbs_host_url` and return the attestation token.
+    /// The token is kept and used by the following resource requests.
+    pub async fn handshake(&self, kbs_host_url: String) -> Result<String> {
+        let _guard = self.handshake_lock.lock().await;
+        let session = self.attest(kbs_host_url).await?;
+        Ok(session.token.content.clone())
+    }
+
+    /// Attest to the KBS again, unless another request has already done so
+    /// since `stale` session was got.
+    async fn reattest(&self, stale: &Arc<Session>) -> Result<Arc<Session>> {
+        let _guard = self.handshake_lock.lock().await;
+        if let Some(current) = self.session.read().await.as_ref() {
+            if !Arc::ptr_eq(current, stale) {
+                return Ok(current.clone());
+            }
+        }
+
+        self.attest(stale.kbs_host_url.clone()).await
+    }
+
+    /// Run the KBS attestation protocol. Must be called with the
+    /// `handshake_lock` held.
+    async fn attest(&self, kbs_host_url: String) -> Result<Arc<Session>> {
         let request = Request {
             version: KBS_PROTOCOL_VERSION.into(),
             tee: self.tee.clone(),
-            extra_params: String::new(),
+            extra_params: serde_json::json!({
+                TEE_KEY_ALGORITHMS_PARAM: self.tee_key_algorithms,
+            })
+            .to_string(),
         };
 
-        let challenge = self
+        let auth_response = self
             .http_client
             .post(format!("{kbs_host_url}/{KBS_URL_PREFIX}/auth"))
             .header("Content-Type", "application/json")
             .json(&request)
             .send()
-            .await?
+            .await
+            .map_err(Error::from)?;
+        if !auth_response.status().is_success() {
+            return Err(status_error(auth_response, "auth").await.into());
+        }
+        let challenge = auth_response
             .json::<Challenge>()
-            .await?;
+            .await
+            .map_err(Error::from)?;
 
-        let tee_keypair = TeeKeyPair::new()?;
+        let algorithm = self.tee_key_algorithm(&challenge.extra_params)?;
+        let tee_keypair = TeeKeyPair::new(algorithm)?;
         let tee_pubkey = tee_keypair.export_pubkey()?;
-        let materials = vec![tee_pubkey.k_mod.as_bytes(), tee_pubkey.k_exp.as_bytes()];
+        let materials = tee_pubkey.materials();
 
         let tee_evidence = self.generate_evidence(challenge.nonce, materials).await?;
         let attestation = Attestation {
@@ -119,95 +202,482 @@ impl Handshaker {
             .header("Content-Type", "application/json")
             .json(&attestation)
             .send()
-            .await?;
+            .await
+            .map_err(Error::from)?;
 
         match attest_response.status() {
             reqwest::StatusCode::OK => {
-                self.tee_key = Some(tee_keypair);
-                self.kbs_host_url = Some(kbs_host_url);
-                let token = attest_response.json::<serde_json::Value>().await?["token"].to_string();
-                Ok(token)
+                let response = attest_response
+                    .json::<AttestationResponse>()
+                    .await
+                    .map_err(Error::from)?;
+                let token = Token::new(response.token.clone()).unwrap_or_else(|e| {
+                    warn!("attestation token is not a JWT, its expiry is unknown: {e:#}");
+                    Token::opaque(response.token)
+                });
+                let session = Arc::new(Session {
+                    kbs_host_url,
+                    tee_key: tee_keypair,
+                    token,
+                });
+                *self.session.write().await = Some(session.clone());
+                Ok(session)
             }
             reqwest::StatusCode::UNAUTHORIZED => {
-                let error_info = attest_response.json::<ErrorInformation>().await?;
-                bail!("KBS attest unauthorized, Error Info: {:?}", error_info)
-            }
-            _ => {
-                bail!(
-                    "KBS Server Internal Failed, Response: {:?}",
-                    attest_response.text().await?
-                )
+                let error_info = attest_response
+                    .json::<ErrorInformation>()
+                    .await
+                    .map_err(Error::from)?;
+                Err(Error::Unauthorized(format!(
+                    "KBS attest unauthorized, Error Info: {error_info:?}"
+                ))
+                .into())
             }
+            _ => Err(status_error(attest_response, "attest").await.into()),
         }
     }
 
-    fn decrypt_response(&self, response: Response) -> Result<Vec<u8>> {
-        // deserialize the jose header and check that the key type matches
-        let protected: ProtectedHeader = serde_json::from_str(&response.protected)?;
-        if protected.alg != PaddingMode::PKCS1v15.as_ref() {
-            bail!("Algorithm mismatch for wrapped key.");
-        }
+    /// Get the TEE key algorithm selected by the KBS from the `extra_params`
+    /// of the challenge. The KBSes not selecting one only support `RSA1_5`.
+    fn tee_key_algorithm(&self, extra_params: &str) -> Result<TeeKeyAlgorithm> {
+        let selected = serde_json::from_str::<serde_json::Value>(extra_params)
+            .ok()
+            .and_then(|mut params| params.get_mut(TEE_KEY_ALGORITHM_PARAM).map(|v| v.take()));
+        let algorithm = match selected {
+            Some(selected) => serde_json::from_value(selected).map_err(|e| {
+                Error::InvalidResponse(format!("KBS selects unknown TEE key algorithm: {e}"))
+            })?,
+            None => TeeKeyAlgorithm::Rsa1_5,
+        };
 
-        let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
-        // unwrap the wrapped key
-        let wrapped_symkey: Vec<u8> = decoder.decode(response.encrypted_key)?;
-        let symkey: Vec<u8> = self
-            .tee_key
-            .as_ref()
-            .ok_or_else(|| anyhow!("Handshake not called before!"))?
-            .decrypt(PaddingMode::PKCS1v15, wrapped_symkey)?;
+        if !self.tee_key_algorithms.contains(&algorithm) {
+            return Err(Error::InvalidResponse(format!(
+                "KBS uses TEE key algorithm {}, which is not offered",
+                algorithm.as_ref()
+            ))
+            .into());
+        }
 
-        let iv = decoder.decode(response.iv)?;
-        let ciphertext = decoder.decode(response.ciphertext)?;
+        Ok(algorithm)
+    }
 
-        let plaintext = crypto::decrypt(Zeroizing::new(symkey), ciphertext, iv, protected.enc)?;
+    fn decrypt_response(&self, session: &Session, response: Response) -> Result<Vec<u8>> {
+        // The KBSes not negotiating the TEE key algorithm give the protected
+        // header in plain JSON, which is never valid base64url, and append
+        // the tag to the ciphertext.
+        if response.protected.starts_with('{') {
+            return decrypt_legacy_response(session, response);
+        }
 
-        Ok(plaintext)
+        let jwe = Jwe {
+            protected: response.protected,
+            encrypted_key: response.encrypted_key,
+            iv: response.iv,
+            ciphertext: response.ciphertext,
+            tag: response.tag,
+            ..Default::default()
+        };
+        session.tee_key.decrypt(&jwe)
     }
 
-    #[async_recursion]
-    pub async fn get(&mut self, resource_url: ResourceUri, retry: bool) -> Result<Vec<u8>> {
-        let kbs_host_url = self
-            .kbs_host_url
-            .as_ref()
+    /// Get the resource of `resource_url` from the KBS. If `retry` is set,
+    /// attest to the KBS again and retry once when the request is
+    /// unauthorized.
+    pub async fn get(&self, resource_url: ResourceUri, retry: bool) -> Result<Vec<u8>> {
+        let mut session = self
+            .session
+            .read()
+            .await
+            .clone()
             .ok_or_else(|| anyhow!("Handshake not called before!"))?;
-        let url = format!(
-            "{kbs_host_url}/{KBS_URL_PREFIX}/{}",
-            resource_url.resource_path()
-        );
 
-        let res = self.http_client.get(url).send().await?;
-        match res.status() {
-            reqwest::StatusCode::OK => {
-                let response = res.json::<Response>().await?;
-                let payload_data = self.decrypt_response(response)?;
-                Ok(payload_data)
-            }
-            reqwest::StatusCode::UNAUTHORIZED => {
-                if !retry {
-                    bail!("Unauthorized request.");
+        let refresh_margin = jwt_simple::prelude::Duration::from_secs(TOKEN_REFRESH_MARGIN_SEC);
+        if session.token.expires_within(refresh_margin) {
+            info!("attestation token expires soon, auth again.");
+            session = self.reattest(&session).await?;
+        }
+
+        let mut retry = retry;
+        loop {
+            let url = resource_api_url(&session.kbs_host_url, &resource_url);
+
+            let res = self
+                .http_client
+                .get(url)
+                .bearer_auth(&session.token.content)
+                .send()
+                .await
+                .map_err(Error::from)?;
+            match res.status() {
+                reqwest::StatusCode::OK => {
+                    let response = res.json::<Response>().await.map_err(Error::from)?;
+                    let payload_data = self.decrypt_response(&session, response).map_err(|e| {
+                        Error::InvalidResponse(format!("decrypt the resource: {e:#}"))
+                    })?;
+                    return Ok(payload_data);
                 }
-                info!("retry to auth again.");
-                self.handshake(kbs_host_url.clone()).await?;
-                self.get(resource_url, false).await
-            }
-            reqwest::StatusCode::NOT_FOUND => {
-                bail!("KBS resource Not Found (Error 404)")
-            }
-            _ => {
-                bail!(
-                    "KBS Server Internal Failed, Response: {:?}",
-                    res.text().await?
-                )
+                reqwest::StatusCode::UNAUTHORIZED => {
+                    if !retry {
+                        bail!(Error::Unauthorized("Unauthorized request.".into()));
+                    }
+                    info!("retry to auth again.");
+                    session = self.reattest(&session).await?;
+                    retry = false;
+                }
+                reqwest::StatusCode::NOT_FOUND => {
+                    bail!(Error::ResourceNotFound(format!(
+                        "KBS resource {} Not Found (Error 404)",
+                        resource_url.resource_path()
+                    )))
+                }
+                _ => return Err(status_error(res, "resource").await.into()),
             }
         }
     }
 }
 
+/// Decrypt a response of the KBSes not negotiating the TEE key algorithm.
+fn decrypt_legacy_response(session: &Session, response: Response) -> Result<Vec<u8>> {
+    let protected: LegacyProtectedHeader = serde_json::from_str(&response.protected)?;
+
+    let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let encrypted_key = decoder.decode(response.encrypted_key)?;
+    let symkey = session
+        .tee_key
+        .decrypt_legacy_key(&protected, encrypted_key)?;
+
+    let iv = decoder.decode(response.iv)?;
+    let ciphertext = decoder.decode(response.ciphertext)?;
+
+    crypto::decrypt(symkey, ciphertext, iv, &[], protected.enc)
+}
+
+/// The error of the KBS responding an unexpected status to the request of
+/// the `endpoint`.
+async fn status_error(response: reqwest::Response, endpoint: &str) -> Error {
+    let status = response.status();
+    let message = format!(
+        "KBS Server Internal Failed at {endpoint} with {status}, Response: {:?}",
+        response.text().await.unwrap_or_default()
+    );
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            Error::Unauthorized(message)
+        }
+        _ if status.is_server_error() => Error::Unavailable(message),
+        _ => Error::InvalidResponse(message),
+    }
+}
+
+/// The URL of the resource API of the KBS of `kbs_host_url` to get the
+/// `resource` from.
+fn resource_api_url(kbs_host_url: &str, resource: &ResourceUri) -> String {
+    format!(
+        "{kbs_host_url}/{KBS_URL_PREFIX}/resource/{}",
+        resource.resource_path()
+    )
+}
+
 #[derive(Deserialize)]
-struct ProtectedHeader {
-    // enryption algorithm for encrypted key
-    alg: String,
-    // encryption algorithm for payload
-    enc: WrapType,
+struct AttestationResponse {
+    token: String,
+}
+
+/// The attestation request. Unlike the one of `kbs_types`, the TEE public
+/// key can be an EC key.
+#[derive(Serialize)]
+struct Attestation {
+    #[serde(rename = "tee-pubkey")]
+    tee_pubkey: TeePubKey,
+    #[serde(rename = "tee-evidence")]
+    tee_evidence: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem::discriminant, sync::Arc, time::Duration};
+
+    use crypto::jwe::ContentEncryption;
+    use hyper::StatusCode;
+    use mock_kbs::{Endpoint, MockKbs};
+    use resource_uri::ResourceUri;
+    use rstest::rstest;
+
+    use crate::{evidence_provider::SampleProvider, tee_pubkey::TeeKeyAlgorithm, Error};
+
+    use super::{resource_api_url, Handshaker};
+
+    const RESOURCE_PATH: &str = "default/key/1";
+
+    async fn handshaker(kbs: &MockKbs) -> Handshaker {
+        kbs.set_resource(RESOURCE_PATH, b"secret".to_vec());
+        Handshaker::new(Arc::new(SampleProvider), Duration::from_secs(5))
+            .await
+            .expect("create handshaker failed")
+    }
+
+    fn resource_uri(path: &str) -> ResourceUri {
+        ResourceUri::try_from(&format!("kbs:///{path}")[..]).expect("parse resource uri failed")
+    }
+
+    #[test]
+    fn resource_api_path() {
+        assert_eq!(
+            resource_api_url("http://127.0.0.1:8080", &resource_uri(RESOURCE_PATH)),
+            "http://127.0.0.1:8080/kbs/v0/resource/default/key/1"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_resource() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        let handshaker = handshaker(&kbs).await;
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+        let resource = handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(resource, b"secret");
+        assert_eq!(kbs.requests(Endpoint::Attest), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_get_resource() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        let handshaker = Arc::new(handshaker(&kbs).await);
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let handshaker = handshaker.clone();
+                tokio::spawn(async move { handshaker.get(resource_uri(RESOURCE_PATH), true).await })
+            })
+            .collect();
+        for task in tasks {
+            let resource = task
+                .await
+                .expect("join task failed")
+                .expect("get resource failed");
+            assert_eq!(resource, b"secret");
+        }
+        assert_eq!(kbs.requests(Endpoint::Attest), 1);
+        assert_eq!(kbs.requests(Endpoint::Resource), 8);
+    }
+
+    #[tokio::test]
+    async fn reauth_on_unauthorized() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        let handshaker = handshaker(&kbs).await;
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+
+        kbs.fail_next(Endpoint::Resource, StatusCode::UNAUTHORIZED);
+        let resource = handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(resource, b"secret");
+        assert_eq!(kbs.requests(Endpoint::Attest), 2);
+        assert_eq!(kbs.requests(Endpoint::Resource), 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_expiring_token() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        // The token expires within the refresh margin, thus each request
+        // leads to a new handshake.
+        kbs.set_token_lifetime(10);
+        let handshaker = handshaker(&kbs).await;
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+        handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(kbs.requests(Endpoint::Attest), 2);
+        assert_eq!(kbs.requests(Endpoint::Resource), 1);
+    }
+
+    #[tokio::test]
+    async fn opaque_token() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_opaque_tokens(true);
+        let handshaker = handshaker(&kbs).await;
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+        let resource = handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(resource, b"secret");
+        assert_eq!(kbs.requests(Endpoint::Attest), 1);
+
+        // Without a known expiry, the token is only refreshed when rejected.
+        kbs.fail_next(Endpoint::Resource, StatusCode::UNAUTHORIZED);
+        handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(kbs.requests(Endpoint::Attest), 2);
+    }
+
+    #[rstest]
+    #[case(Some(&["EC-P256"][..]), TeeKeyAlgorithm::defaults(), false)]
+    #[case(Some(&["EC-P384"][..]), TeeKeyAlgorithm::defaults(), true)]
+    #[case(Some(&["RSA-OAEP-256"][..]), TeeKeyAlgorithm::defaults(), false)]
+    #[case(Some(&["EC-P256", "RSA1_5"][..]), vec![TeeKeyAlgorithm::Rsa1_5], false)]
+    #[case(None, TeeKeyAlgorithm::defaults(), false)]
+    #[tokio::test]
+    async fn negotiate_tee_key(
+        #[case] supported: Option<&[&str]>,
+        #[case] offered: Vec<TeeKeyAlgorithm>,
+        #[case] direct_key_agreement: bool,
+    ) {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_tee_key_algorithms(supported);
+        kbs.set_direct_key_agreement(direct_key_agreement);
+        let handshaker = handshaker(&kbs).await.with_tee_key_algorithms(offered);
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+        let resource = handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(resource, b"secret");
+    }
+
+    #[rstest]
+    #[case(TeeKeyAlgorithm::EcP256)]
+    #[case(TeeKeyAlgorithm::RsaOaep256)]
+    #[tokio::test]
+    async fn cbc_hs512_response(#[case] algorithm: TeeKeyAlgorithm) {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_content_encryption(ContentEncryption::A256CbcHs512);
+        let handshaker = handshaker(&kbs)
+            .await
+            .with_tee_key_algorithms(vec![algorithm]);
+        handshaker
+            .handshake(kbs.url())
+            .await
+            .expect("handshake failed");
+        let resource = handshaker
+            .get(resource_uri(RESOURCE_PATH), true)
+            .await
+            .expect("get resource failed");
+        assert_eq!(resource, b"secret");
+    }
+
+    #[tokio::test]
+    async fn legacy_kbs_without_rsa1_5() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_tee_key_algorithms(None);
+        let handshaker = handshaker(&kbs)
+            .await
+            .with_tee_key_algorithms(vec![TeeKeyAlgorithm::EcP256]);
+        let err = handshaker
+            .handshake(kbs.url())
+            .await
+            .expect_err("handshake should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::InvalidResponse(_))
+        ));
+        assert_eq!(kbs.requests(Endpoint::Attest), 0);
+    }
+
+    #[rstest]
+    #[case(
+        Endpoint::Auth,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        RESOURCE_PATH,
+        "",
+        Error::Unavailable(String::new())
+    )]
+    #[case(
+        Endpoint::Attest,
+        StatusCode::UNAUTHORIZED,
+        RESOURCE_PATH,
+        "unauthorized",
+        Error::Unauthorized(String::new())
+    )]
+    #[case(
+        Endpoint::Attest,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        RESOURCE_PATH,
+        "Internal Failed",
+        Error::Unavailable(String::new())
+    )]
+    #[case(
+        Endpoint::Resource,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        RESOURCE_PATH,
+        "Internal Failed",
+        Error::Unavailable(String::new())
+    )]
+    #[case(
+        Endpoint::Resource,
+        StatusCode::OK,
+        "default/key/2",
+        "Not Found",
+        Error::ResourceNotFound(String::new())
+    )]
+    #[tokio::test]
+    async fn kbs_failures(
+        #[case] endpoint: Endpoint,
+        #[case] status: StatusCode,
+        #[case] path: &str,
+        #[case] expected: &str,
+        #[case] kind: Error,
+    ) {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        let handshaker = handshaker(&kbs).await;
+        if status != StatusCode::OK {
+            kbs.fail_next(endpoint, status);
+        }
+
+        let res = match handshaker.handshake(kbs.url()).await {
+            Ok(_) => handshaker.get(resource_uri(path), true).await,
+            Err(e) => Err(e),
+        };
+        let err = res.expect_err("request should fail");
+        assert!(
+            err.to_string().contains(expected),
+            "`{err}` does not contain `{expected}`"
+        );
+        let error = err.downcast_ref::<Error>().expect("error is not typed");
+        assert_eq!(discriminant(error), discriminant(&kind));
+    }
+
+    #[tokio::test]
+    async fn kbs_unreachable() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        let handshaker = handshaker(&kbs).await;
+        // A port just released, where nothing listens.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind failed");
+        let addr = listener.local_addr().expect("get address failed");
+        drop(listener);
+
+        let err = handshaker
+            .handshake(format!("http://{addr}"))
+            .await
+            .expect_err("handshake should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Unavailable(_))
+        ));
+    }
 }
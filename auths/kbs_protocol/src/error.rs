@@ -0,0 +1,42 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Errors of the KBS protocol
+
+use thiserror::Error;
+
+/// The kinds of failures to talk with a KBS that the callers may handle
+/// differently. They are returned inside [`anyhow::Error`], and can be found
+/// by downcasting it.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The KBS cannot be reached, or is temporarily failing.
+    #[error("{0}")]
+    Unavailable(String),
+
+    /// The KBS rejects the evidence, or the request after attestation.
+    #[error("{0}")]
+    Unauthorized(String),
+
+    #[error("{0}")]
+    ResourceNotFound(String),
+
+    /// The response of the KBS breaks the protocol.
+    #[error("{0}")]
+    InvalidResponse(String),
+
+    /// The resource URI names a KBS which is not allowed to be connected.
+    #[error("{0}")]
+    UnknownKbs(String),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        match e.is_decode() {
+            true => Self::InvalidResponse(e.to_string()),
+            false => Self::Unavailable(e.to_string()),
+        }
+    }
+}
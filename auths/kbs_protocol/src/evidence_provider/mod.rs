@@ -0,0 +1,23 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Providers of the TEE evidence used to attest to the KBS.
+
+use anyhow::*;
+use async_trait::async_trait;
+use kbs_types::Tee;
+
+pub mod sample;
+pub use sample::SampleProvider;
+
+#[async_trait]
+pub trait EvidenceProvider: Send + Sync {
+    /// Detect the TEE type of the current platform.
+    async fn get_tee_type(&self) -> Result<Tee>;
+
+    /// Get the evidence of the TEE. The `challenge` is the base64 encoded
+    /// digest which should be included as the report data of the evidence.
+    async fn get_evidence(&self, challenge: String) -> Result<Vec<u8>>;
+}
@@ -0,0 +1,66 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! A built-in evidence provider of [`Tee::Sample`], which does not require
+//! any TEE hardware or attestation-agent. It is only for testing, and the
+//! KBS should never release secrets to a sample TEE in production.
+
+use anyhow::*;
+use async_trait::async_trait;
+use kbs_types::Tee;
+use serde::{Deserialize, Serialize};
+
+use super::EvidenceProvider;
+
+/// SVN of the sample TEE
+const SAMPLE_SVN: &str = "1";
+
+/// Evidence of the sample TEE, in the format expected by the sample verifier
+/// of the KBS.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SampleQuote {
+    pub svn: String,
+    pub report_data: String,
+}
+
+#[derive(Default)]
+pub struct SampleProvider;
+
+#[async_trait]
+impl EvidenceProvider for SampleProvider {
+    async fn get_tee_type(&self) -> Result<Tee> {
+        Ok(Tee::Sample)
+    }
+
+    async fn get_evidence(&self, challenge: String) -> Result<Vec<u8>> {
+        let quote = SampleQuote {
+            svn: SAMPLE_SVN.into(),
+            report_data: challenge,
+        };
+        serde_json::to_vec(&quote).context("serialize sample quote")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kbs_types::Tee;
+
+    use crate::evidence_provider::EvidenceProvider;
+
+    use super::{SampleProvider, SampleQuote};
+
+    #[tokio::test]
+    async fn sample_evidence() {
+        let provider = SampleProvider;
+        assert_eq!(provider.get_tee_type().await.unwrap(), Tee::Sample);
+
+        let evidence = provider
+            .get_evidence("report data".into())
+            .await
+            .expect("get evidence failed");
+        let quote: SampleQuote = serde_json::from_slice(&evidence).expect("parse quote failed");
+        assert_eq!(quote.report_data, "report data");
+    }
+}
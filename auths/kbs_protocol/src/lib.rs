@@ -5,6 +5,10 @@
 
 pub mod attestation_agent_client;
 pub mod client;
+pub mod error;
+pub use error::Error;
+pub mod evidence_provider;
 pub mod tee_pubkey;
+pub mod token;
 
 pub const KBS_PROTOCOL_VERSION: &str = "0.1.0";
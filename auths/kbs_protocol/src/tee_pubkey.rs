@@ -6,38 +6,195 @@
 use anyhow::*;
 
 use base64::Engine;
-use crypto::rust::{
-    rsa::{PaddingMode, RSAKeyPair},
-    traits::PublicKeyParts,
+use crypto::{
+    jwe::{DecryptionKey, Jwe, KeyAlgorithm},
+    Curve, ECKeyPair, PaddingMode, RSAKeyPair, WrapType,
 };
-use kbs_types::TeePubKey;
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, EnumString};
+use zeroize::Zeroizing;
+
+/// Algorithms of the TEE keys, by which the KBS encrypts the resources to
+/// the TEE. The KBS selects one of the algorithms offered by the client, or
+/// uses `RSA1_5` if it does not negotiate the algorithm.
+#[derive(EnumString, AsRefStr, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeeKeyAlgorithm {
+    /// 2048-bit RSA key, to which the content key is encrypted with PKCS#1
+    /// v1.5 padding. It is deprecated, but the only algorithm of the KBSes
+    /// not negotiating the algorithm.
+    #[strum(serialize = "RSA1_5")]
+    #[serde(rename = "RSA1_5")]
+    Rsa1_5,
+
+    /// 2048-bit RSA key, to which the content key is encrypted with OAEP
+    /// using SHA-256.
+    #[strum(serialize = "RSA-OAEP-256")]
+    #[serde(rename = "RSA-OAEP-256")]
+    RsaOaep256,
+
+    /// P-256 key, with which the content key, or the key wrapping it, is
+    /// agreed by ECDH-ES.
+    #[strum(serialize = "EC-P256")]
+    #[serde(rename = "EC-P256")]
+    EcP256,
+
+    /// P-384 key, used like `EC-P256`.
+    #[strum(serialize = "EC-P384")]
+    #[serde(rename = "EC-P384")]
+    EcP384,
+}
+
+impl TeeKeyAlgorithm {
+    /// The algorithms offered by default in order of preference. The EC keys
+    /// are preferred as they are much faster to generate.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            TeeKeyAlgorithm::EcP256,
+            TeeKeyAlgorithm::EcP384,
+            TeeKeyAlgorithm::RsaOaep256,
+            TeeKeyAlgorithm::Rsa1_5,
+        ]
+    }
+}
+
+/// The TEE public key in JWK format, sent to the KBS with the evidence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kty")]
+pub enum TeePubKey {
+    /// Unlike JWK, the modulus and exponent are in standard base64, as the
+    /// KBSes not negotiating the algorithm expect.
+    #[serde(rename = "RSA")]
+    Rsa {
+        alg: String,
+        #[serde(rename = "n")]
+        k_mod: String,
+        #[serde(rename = "e")]
+        k_exp: String,
+    },
+
+    /// The coordinates are in base64url without padding.
+    #[serde(rename = "EC")]
+    Ec {
+        alg: String,
+        crv: String,
+        x: String,
+        y: String,
+    },
+}
+
+impl TeePubKey {
+    /// The key materials bound to the evidence with the nonce.
+    pub fn materials(&self) -> Vec<&[u8]> {
+        match self {
+            TeePubKey::Rsa { k_mod, k_exp, .. } => vec![k_mod.as_bytes(), k_exp.as_bytes()],
+            TeePubKey::Ec { x, y, .. } => vec![x.as_bytes(), y.as_bytes()],
+        }
+    }
+}
+
+/// The protected header of the responses of the KBSes not negotiating the
+/// TEE key algorithm. It is plain JSON instead of base64url, and is not
+/// authenticated.
+#[derive(Deserialize)]
+pub(crate) struct LegacyProtectedHeader {
+    /// Algorithm to encrypt the content key
+    pub alg: String,
+
+    /// Algorithm to encrypt the payload with the content key
+    pub enc: WrapType,
+}
+
+enum KeyPair {
+    Rsa(Box<RSAKeyPair>),
+    Ec(ECKeyPair),
+}
 
 pub struct TeeKeyPair {
-    keypair: RSAKeyPair,
+    algorithm: TeeKeyAlgorithm,
+    keypair: KeyPair,
 }
 
 impl TeeKeyPair {
-    pub fn new() -> Result<Self> {
-        Ok(Self {
-            keypair: RSAKeyPair::new()?,
-        })
+    pub fn new(algorithm: TeeKeyAlgorithm) -> Result<Self> {
+        let keypair = match algorithm {
+            TeeKeyAlgorithm::Rsa1_5 | TeeKeyAlgorithm::RsaOaep256 => {
+                KeyPair::Rsa(Box::new(RSAKeyPair::new()?))
+            }
+            TeeKeyAlgorithm::EcP256 => KeyPair::Ec(ECKeyPair::new(Curve::P256)?),
+            TeeKeyAlgorithm::EcP384 => KeyPair::Ec(ECKeyPair::new(Curve::P384)?),
+        };
+
+        Ok(Self { algorithm, keypair })
     }
 
     /// Export TEE public key as specific structure.
     pub fn export_pubkey(&self) -> Result<TeePubKey> {
-        let engine = base64::engine::general_purpose::STANDARD;
-        let k_mod = engine.encode(self.keypair.public_key.n().to_bytes_be());
-        let k_exp = engine.encode(self.keypair.public_key.e().to_bytes_be());
-
-        Ok(TeePubKey {
-            alg: PaddingMode::PKCS1v15.as_ref().to_string(),
-            k_mod,
-            k_exp,
-        })
+        match &self.keypair {
+            KeyPair::Rsa(keypair) => {
+                let engine = base64::engine::general_purpose::STANDARD;
+                let public_key = keypair.public_key();
+                let alg = match self.algorithm {
+                    TeeKeyAlgorithm::RsaOaep256 => PaddingMode::OAEP256,
+                    _ => PaddingMode::PKCS1v15,
+                };
+                Ok(TeePubKey::Rsa {
+                    alg: alg.as_ref().to_string(),
+                    k_mod: engine.encode(public_key.n()),
+                    k_exp: engine.encode(public_key.e()),
+                })
+            }
+            KeyPair::Ec(keypair) => {
+                let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+                let public_key = keypair.public_key();
+                Ok(TeePubKey::Ec {
+                    alg: KeyAlgorithm::EcdhEsA256Kw.as_ref().to_string(),
+                    crv: public_key.curve().as_ref().to_string(),
+                    x: engine.encode(public_key.x()),
+                    y: engine.encode(public_key.y()),
+                })
+            }
+        }
+    }
+
+    /// Decrypt the `jwe` encrypted to the key pair. Its `alg` must match
+    /// the algorithm of the key pair, while both `ECDH-ES` and
+    /// `ECDH-ES+A256KW` are accepted by the EC key pairs.
+    pub fn decrypt(&self, jwe: &Jwe) -> Result<Vec<u8>> {
+        let alg = jwe.header()?.alg;
+        let matched = match self.algorithm {
+            TeeKeyAlgorithm::Rsa1_5 => alg == KeyAlgorithm::Rsa1_5,
+            TeeKeyAlgorithm::RsaOaep256 => alg == KeyAlgorithm::RsaOaep256,
+            TeeKeyAlgorithm::EcP256 | TeeKeyAlgorithm::EcP384 => {
+                matches!(alg, KeyAlgorithm::EcdhEs | KeyAlgorithm::EcdhEsA256Kw)
+            }
+        };
+        if !matched {
+            bail!("Algorithm mismatch for wrapped key.");
+        }
+
+        match &self.keypair {
+            KeyPair::Rsa(keypair) => jwe.decrypt(DecryptionKey::Rsa(keypair)),
+            KeyPair::Ec(keypair) => jwe.decrypt(DecryptionKey::Ec(keypair)),
+        }
     }
 
-    #[inline]
-    pub fn decrypt(&self, mode: PaddingMode, cipher_text: Vec<u8>) -> Result<Vec<u8>> {
-        self.keypair.decrypt(mode, cipher_text)
+    /// Decrypt the `encrypted_key` of a response of the KBSes not
+    /// negotiating the TEE key algorithm, which can only be `RSA1_5`.
+    pub(crate) fn decrypt_legacy_key(
+        &self,
+        header: &LegacyProtectedHeader,
+        encrypted_key: Vec<u8>,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let KeyPair::Rsa(keypair) = &self.keypair else {
+            bail!("Algorithm mismatch for wrapped key.");
+        };
+        if self.algorithm != TeeKeyAlgorithm::Rsa1_5 || header.alg != KeyAlgorithm::Rsa1_5.as_ref()
+        {
+            bail!("Algorithm mismatch for wrapped key.");
+        }
+
+        Ok(Zeroizing::new(
+            keypair.decrypt(PaddingMode::PKCS1v15, encrypted_key)?,
+        ))
     }
 }
@@ -0,0 +1,99 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Attestation token issued by the KBS after a successful attestation.
+
+use anyhow::*;
+use base64::Engine;
+use jwt_simple::{
+    claims::{JWTClaims, NoCustomClaims},
+    prelude::{Clock, Duration, UnixTimeStamp},
+};
+
+/// The token is usually a JWT, but may be opaque. It is sent to the KBS as
+/// a Bearer token when requesting resources.
+#[derive(Clone, Debug)]
+pub struct Token {
+    /// The encoded token
+    pub content: String,
+
+    /// When the token expires. `None` means the token never expires.
+    pub expires_at: Option<UnixTimeStamp>,
+}
+
+impl Token {
+    /// Parse the claims of the given JWT. The signature is not verified,
+    /// because the token is only verified by the KBS which issues it.
+    pub fn new(content: String) -> Result<Self> {
+        let claims = content
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| anyhow!("illegal JWT format"))?;
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(claims)
+            .context("decode JWT claims")?;
+        let claims: JWTClaims<NoCustomClaims> =
+            serde_json::from_slice(&claims).context("parse JWT claims")?;
+
+        Ok(Self {
+            content,
+            expires_at: claims.expires_at,
+        })
+    }
+
+    /// A token which is not a JWT. Its expiry is unknown, so it is only
+    /// refreshed when the KBS rejects it.
+    pub fn opaque(content: String) -> Self {
+        Self {
+            content,
+            expires_at: None,
+        }
+    }
+
+    /// Whether the token is expired or will expire in `margin`.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Clock::now_since_epoch() + margin >= expires_at,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jwt_simple::prelude::*;
+    use rstest::rstest;
+
+    use super::Token;
+
+    #[rstest]
+    #[case(Duration::from_secs(600), Duration::from_secs(10), false)]
+    #[case(Duration::from_secs(5), Duration::from_secs(10), true)]
+    fn expiry(#[case] valid_for: Duration, #[case] margin: Duration, #[case] expired: bool) {
+        let key = HS256Key::generate();
+        let claims = Claims::create(valid_for);
+        let jwt = key.authenticate(claims).expect("create jwt failed");
+
+        let token = Token::new(jwt.clone()).expect("parse token failed");
+        assert_eq!(token.content, jwt);
+        assert_eq!(token.expires_within(margin), expired);
+    }
+
+    #[test]
+    fn no_expiry() {
+        let key = HS256Key::generate();
+        let mut claims = Claims::create(Duration::from_secs(600));
+        claims.expires_at = None;
+        let jwt = key.authenticate(claims).expect("create jwt failed");
+
+        let token = Token::new(jwt).expect("parse token failed");
+        assert!(!token.expires_within(Duration::from_days(365)));
+    }
+
+    #[test]
+    fn illegal_token() {
+        assert!(Token::new("\"not a jwt\"".into()).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Errors of the crypto operations
+
+use thiserror::Error;
+
+/// Failures of the crypto operations caused by their inputs. They are
+/// returned inside [`anyhow::Error`], and can be found by downcasting it.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("key of {actual} bytes given, but {expected} bytes expected")]
+    InvalidKeyLength { expected: usize, actual: usize },
+
+    #[error("iv of {actual} bytes given, but {expected} bytes expected")]
+    InvalidIvLength { expected: usize, actual: usize },
+
+    /// Associated data is given to a [`crate::WrapType`] which cannot
+    /// authenticate it.
+    #[error("{0} does not support associated data")]
+    AadUnsupported(String),
+
+    /// The ciphertext is corrupted, or encrypted by another key.
+    #[error("{0}")]
+    DecryptionFailed(String),
+
+    /// The algorithm is not implemented by the enabled backends, e.g. XC20P
+    /// with only feature `openssl`.
+    #[error("{0} is not supported by the enabled crypto backend")]
+    Unsupported(String),
+}
@@ -0,0 +1,666 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! JSON Web Encryption of <https://datatracker.ietf.org/doc/html/rfc7516>
+//! with a single recipient, i.e. the flattened JSON serialization and the
+//! compact serialization.
+//!
+//! The supported `alg`s are `RSA1_5`, `RSA-OAEP`, `RSA-OAEP-256`,
+//! `ECDH-ES`, `ECDH-ES+A256KW` and `A256KW`, and the supported `enc`s are
+//! `A256GCM`, `A256CBC-HS512`, and the ones of the AEAD [`crate::WrapType`]s
+//! not registered for JOSE.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use zeroize::Zeroizing;
+
+#[cfg(feature = "openssl")]
+use crate::native::{aes256cbchs512, aes256gcm, chacha20poly1305};
+
+#[cfg(all(feature = "rust-crypto", feature = "openssl"))]
+use crate::rust::{aes256gcmsiv, xchacha20poly1305};
+
+#[cfg(all(feature = "rust-crypto", not(feature = "openssl")))]
+use crate::rust::{aes256cbchs512, aes256gcm, aes256gcmsiv, chacha20poly1305, xchacha20poly1305};
+
+use crate::{
+    concat_kdf, unwrap_key, wrap_key, Curve, ECKeyPair, ECPublicKey, Error, PaddingMode,
+    RSAKeyPair, RSAPublicKey,
+};
+
+/// Length of the keys wrapping the content keys by `A256KW`
+const KEK_LENGTH: usize = 32;
+
+/// Algorithms to encrypt or agree the content key, i.e. `alg` of
+/// <https://datatracker.ietf.org/doc/html/rfc7518#section-4.1>.
+#[derive(EnumString, AsRefStr, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    /// Not recommended, but used by the KBSes not negotiating the TEE key
+    /// algorithm.
+    #[strum(serialize = "RSA1_5")]
+    #[serde(rename = "RSA1_5")]
+    Rsa1_5,
+
+    #[strum(serialize = "RSA-OAEP")]
+    #[serde(rename = "RSA-OAEP")]
+    RsaOaep,
+
+    #[strum(serialize = "RSA-OAEP-256")]
+    #[serde(rename = "RSA-OAEP-256")]
+    RsaOaep256,
+
+    /// The agreed key is the content key.
+    #[strum(serialize = "ECDH-ES")]
+    #[serde(rename = "ECDH-ES")]
+    EcdhEs,
+
+    /// The agreed key wraps the content key by `A256KW`.
+    #[strum(serialize = "ECDH-ES+A256KW")]
+    #[serde(rename = "ECDH-ES+A256KW")]
+    EcdhEsA256Kw,
+
+    #[strum(serialize = "A256KW")]
+    #[serde(rename = "A256KW")]
+    A256Kw,
+}
+
+/// Algorithms to encrypt the payload with the content key, i.e. `enc` of
+/// <https://datatracker.ietf.org/doc/html/rfc7518#section-5.1>.
+#[derive(EnumString, AsRefStr, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncryption {
+    #[strum(serialize = "A256GCM")]
+    #[serde(rename = "A256GCM")]
+    A256Gcm,
+
+    #[strum(serialize = "A256CBC-HS512")]
+    #[serde(rename = "A256CBC-HS512")]
+    A256CbcHs512,
+
+    /// ChaCha20-Poly1305 of draft-amringer-jose-chacha
+    #[strum(serialize = "C20P")]
+    #[serde(rename = "C20P")]
+    C20P,
+
+    /// XChaCha20-Poly1305 of draft-amringer-jose-chacha
+    #[strum(serialize = "XC20P")]
+    #[serde(rename = "XC20P")]
+    XC20P,
+
+    /// AES-GCM-SIV, named like [`crate::WrapType::Aes256GcmSiv`]
+    #[strum(serialize = "A256GCM-SIV")]
+    #[serde(rename = "A256GCM-SIV")]
+    A256GcmSiv,
+}
+
+impl ContentEncryption {
+    /// Whether the enabled backends implement the `enc`, like
+    /// [`crate::WrapType::is_supported`].
+    pub fn is_supported(&self) -> bool {
+        cfg!(feature = "rust-crypto")
+            || !matches!(
+                self,
+                ContentEncryption::XC20P | ContentEncryption::A256GcmSiv
+            )
+    }
+
+    fn check_supported(&self) -> Result<()> {
+        if !self.is_supported() {
+            return Err(Error::Unsupported(self.as_ref().to_string()).into());
+        }
+
+        Ok(())
+    }
+
+    /// Length in bytes of the content keys
+    pub fn key_length(&self) -> usize {
+        match self {
+            ContentEncryption::A256CbcHs512 => 64,
+            _ => 32,
+        }
+    }
+
+    fn iv_length(&self) -> usize {
+        match self {
+            ContentEncryption::A256CbcHs512 => 16,
+            ContentEncryption::XC20P => 24,
+            _ => 12,
+        }
+    }
+
+    fn tag_length(&self) -> usize {
+        match self {
+            ContentEncryption::A256CbcHs512 => 32,
+            _ => 16,
+        }
+    }
+}
+
+/// An EC public key in JWK format, the ephemeral public key of `ECDH-ES`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct EcJwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub y: String,
+}
+
+impl EcJwk {
+    fn new(public_key: &ECPublicKey) -> Self {
+        let encoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        Self {
+            kty: "EC".to_string(),
+            crv: public_key.curve().as_ref().to_string(),
+            x: encoder.encode(public_key.x()),
+            y: encoder.encode(public_key.y()),
+        }
+    }
+
+    fn public_key(&self) -> Result<ECPublicKey> {
+        if self.kty != "EC" {
+            bail!("epk of kty {} given, but EC expected", self.kty);
+        }
+
+        let curve: Curve = self
+            .crv
+            .parse()
+            .map_err(|_| anyhow!("unsupported curve {} of epk", self.crv))?;
+        let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        Ok(ECPublicKey::new(
+            curve,
+            decoder.decode(&self.x).context("decode x of epk")?,
+            decoder.decode(&self.y).context("decode y of epk")?,
+        ))
+    }
+}
+
+/// The JOSE header, i.e. the union of the protected header, the shared
+/// unprotected header and the per-recipient unprotected header.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JoseHeader {
+    pub alg: KeyAlgorithm,
+
+    pub enc: ContentEncryption,
+
+    /// Ephemeral public key of `ECDH-ES`
+    pub epk: Option<EcJwk>,
+
+    /// Agreement PartyUInfo of `ECDH-ES` in base64url
+    #[serde(default)]
+    pub apu: String,
+
+    /// Agreement PartyVInfo of `ECDH-ES` in base64url
+    #[serde(default)]
+    pub apv: String,
+
+    pub kid: Option<String>,
+
+    /// Extensions that must be understood. As none is supported, a header
+    /// with `crit` is rejected.
+    pub crit: Option<Vec<String>>,
+}
+
+/// The key to decrypt a [`Jwe`], which must suit its `alg`.
+pub enum DecryptionKey<'a> {
+    /// For `RSA1_5`, `RSA-OAEP` and `RSA-OAEP-256`
+    Rsa(&'a RSAKeyPair),
+
+    /// For `ECDH-ES` and `ECDH-ES+A256KW`
+    Ec(&'a ECKeyPair),
+
+    /// The 32-byte key of `A256KW`
+    Symmetric(&'a [u8]),
+}
+
+/// The key to encrypt a [`Jwe`] to, which must suit the `alg`.
+pub enum EncryptionKey<'a> {
+    /// For `RSA1_5`, `RSA-OAEP` and `RSA-OAEP-256`
+    Rsa(&'a RSAPublicKey),
+
+    /// For `ECDH-ES` and `ECDH-ES+A256KW`
+    Ec(&'a ECPublicKey),
+
+    /// The 32-byte key of `A256KW`
+    Symmetric(&'a [u8]),
+}
+
+/// A JWE in the flattened JSON serialization. All the fields are in
+/// base64url without padding, except the unprotected headers.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Jwe {
+    pub protected: String,
+
+    /// The shared unprotected header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unprotected: Option<Map<String, Value>>,
+
+    /// The per-recipient unprotected header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<Map<String, Value>>,
+
+    /// Empty for `ECDH-ES`
+    #[serde(default)]
+    pub encrypted_key: String,
+
+    pub iv: String,
+
+    pub ciphertext: String,
+
+    pub tag: String,
+
+    /// Additional authenticated data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aad: Option<String>,
+}
+
+impl Jwe {
+    /// Parse the compact serialization, i.e. the five base64url parts
+    /// joined by dots.
+    pub fn from_compact(compact: &str) -> Result<Self> {
+        let [protected, encrypted_key, iv, ciphertext, tag]: [&str; 5] = compact
+            .split('.')
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| anyhow!("compact JWE must have 5 parts"))?;
+
+        Ok(Self {
+            protected: protected.to_string(),
+            encrypted_key: encrypted_key.to_string(),
+            iv: iv.to_string(),
+            ciphertext: ciphertext.to_string(),
+            tag: tag.to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// The compact serialization, which cannot carry the unprotected
+    /// headers and the additional authenticated data.
+    pub fn to_compact(&self) -> Result<String> {
+        if self.unprotected.is_some() || self.header.is_some() || self.aad.is_some() {
+            bail!("JWE with unprotected header or aad has no compact serialization");
+        }
+
+        Ok([
+            &self.protected[..],
+            &self.encrypted_key,
+            &self.iv,
+            &self.ciphertext,
+            &self.tag,
+        ]
+        .join("."))
+    }
+
+    /// The JOSE header. A parameter given by more than one of the headers
+    /// is rejected.
+    pub fn header(&self) -> Result<JoseHeader> {
+        let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let protected = decoder
+            .decode(&self.protected)
+            .context("decode protected header")?;
+        let mut header: Map<String, Value> =
+            serde_json::from_slice(&protected).context("parse protected header")?;
+
+        for unprotected in [&self.unprotected, &self.header].into_iter().flatten() {
+            for (name, value) in unprotected {
+                if header.insert(name.clone(), value.clone()).is_some() {
+                    bail!("duplicated header parameter {name}");
+                }
+            }
+        }
+
+        let header: JoseHeader =
+            serde_json::from_value(Value::Object(header)).context("parse JOSE header")?;
+        if header.crit.is_some() {
+            bail!("unsupported critical header parameters");
+        }
+
+        Ok(header)
+    }
+
+    /// Decrypt the payload with the `key`.
+    ///
+    /// Corrupted content fails with [`Error::DecryptionFailed`], a content
+    /// key of wrong length with [`Error::InvalidKeyLength`], and an `enc`
+    /// the backends lack with [`Error::Unsupported`].
+    pub fn decrypt(&self, key: DecryptionKey) -> Result<Vec<u8>> {
+        let header = self.header()?;
+        header.enc.check_supported()?;
+        let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let encrypted_key = decoder
+            .decode(&self.encrypted_key)
+            .context("decode encrypted key")?;
+        let iv = decoder.decode(&self.iv).context("decode iv")?;
+        let mut ciphertext = decoder
+            .decode(&self.ciphertext)
+            .context("decode ciphertext")?;
+        let tag = decoder.decode(&self.tag).context("decode tag")?;
+
+        let cek = content_key(&header, key, encrypted_key)?;
+        let enc = header.enc;
+        if cek.len() != enc.key_length() {
+            return Err(Error::InvalidKeyLength {
+                expected: enc.key_length(),
+                actual: cek.len(),
+            }
+            .into());
+        }
+        if iv.len() != enc.iv_length() {
+            return Err(Error::InvalidIvLength {
+                expected: enc.iv_length(),
+                actual: iv.len(),
+            }
+            .into());
+        }
+        if tag.len() != enc.tag_length() {
+            return Err(Error::DecryptionFailed(format!(
+                "tag of {} bytes given, but {} bytes expected",
+                tag.len(),
+                enc.tag_length()
+            ))
+            .into());
+        }
+
+        // the backends take the tag appended to the ciphertext
+        ciphertext.extend_from_slice(&tag);
+        let aad = self.aad();
+        match enc {
+            ContentEncryption::A256Gcm => aes256gcm::decrypt(&ciphertext, &cek, &iv, &aad),
+            ContentEncryption::A256CbcHs512 => {
+                aes256cbchs512::decrypt(&ciphertext, &cek, &iv, &aad)
+            }
+            ContentEncryption::C20P => chacha20poly1305::decrypt(&ciphertext, &cek, &iv, &aad),
+            #[cfg(feature = "rust-crypto")]
+            ContentEncryption::XC20P => xchacha20poly1305::decrypt(&ciphertext, &cek, &iv, &aad),
+            #[cfg(feature = "rust-crypto")]
+            ContentEncryption::A256GcmSiv => aes256gcmsiv::decrypt(&ciphertext, &cek, &iv, &aad),
+            #[cfg(not(feature = "rust-crypto"))]
+            ContentEncryption::XC20P | ContentEncryption::A256GcmSiv => {
+                unreachable!("rejected by ContentEncryption::check_supported")
+            }
+        }
+        .map_err(|e| Error::DecryptionFailed(e.to_string()).into())
+    }
+
+    /// Encrypt the `plaintext` to the `key` with a random content key.
+    pub fn encrypt(
+        key: EncryptionKey,
+        alg: KeyAlgorithm,
+        enc: ContentEncryption,
+        plaintext: &[u8],
+    ) -> Result<Self> {
+        enc.check_supported()?;
+        let mut header = Map::new();
+        header.insert("alg".to_string(), serde_json::to_value(alg)?);
+        header.insert("enc".to_string(), serde_json::to_value(enc)?);
+
+        let mut cek = Zeroizing::new(random_bytes(enc.key_length()));
+        let encrypted_key = match (key, alg) {
+            (EncryptionKey::Rsa(public_key), _) => public_key.encrypt(padding_mode(alg)?, &cek)?,
+            (EncryptionKey::Ec(public_key), KeyAlgorithm::EcdhEs | KeyAlgorithm::EcdhEsA256Kw) => {
+                let ephemeral_key = ECKeyPair::new(public_key.curve())?;
+                let z = ephemeral_key.derive(public_key)?;
+                header.insert(
+                    "epk".to_string(),
+                    serde_json::to_value(EcJwk::new(ephemeral_key.public_key()))?,
+                );
+
+                if alg == KeyAlgorithm::EcdhEs {
+                    cek = concat_kdf(&z, enc.as_ref(), &[], &[], enc.key_length());
+                    Vec::new()
+                } else {
+                    let kek = concat_kdf(&z, alg.as_ref(), &[], &[], KEK_LENGTH);
+                    wrap_key(&kek, &cek)?
+                }
+            }
+            (EncryptionKey::Symmetric(kek), KeyAlgorithm::A256Kw) => wrap_key(kek, &cek)?,
+            _ => bail!("key mismatches the algorithm {}", alg.as_ref()),
+        };
+
+        let encoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let protected = encoder.encode(Value::Object(header).to_string());
+        let iv = random_bytes(enc.iv_length());
+        let aad = protected.as_bytes();
+        let mut ciphertext = match enc {
+            ContentEncryption::A256Gcm => aes256gcm::encrypt(plaintext, &cek, &iv, aad),
+            ContentEncryption::A256CbcHs512 => aes256cbchs512::encrypt(plaintext, &cek, &iv, aad),
+            ContentEncryption::C20P => chacha20poly1305::encrypt(plaintext, &cek, &iv, aad),
+            #[cfg(feature = "rust-crypto")]
+            ContentEncryption::XC20P => xchacha20poly1305::encrypt(plaintext, &cek, &iv, aad),
+            #[cfg(feature = "rust-crypto")]
+            ContentEncryption::A256GcmSiv => aes256gcmsiv::encrypt(plaintext, &cek, &iv, aad),
+            #[cfg(not(feature = "rust-crypto"))]
+            ContentEncryption::XC20P | ContentEncryption::A256GcmSiv => {
+                unreachable!("rejected by ContentEncryption::check_supported")
+            }
+        }?;
+        let tag = ciphertext.split_off(ciphertext.len() - enc.tag_length());
+
+        Ok(Self {
+            protected,
+            encrypted_key: encoder.encode(encrypted_key),
+            iv: encoder.encode(iv),
+            ciphertext: encoder.encode(ciphertext),
+            tag: encoder.encode(tag),
+            ..Default::default()
+        })
+    }
+
+    /// The additional authenticated data of the content encryption, which
+    /// authenticates the protected header.
+    fn aad(&self) -> Vec<u8> {
+        match &self.aad {
+            Some(aad) => format!("{}.{aad}", self.protected).into_bytes(),
+            None => self.protected.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Get the content key from the `encrypted_key`, or agree it by `ECDH-ES`.
+fn content_key(
+    header: &JoseHeader,
+    key: DecryptionKey,
+    encrypted_key: Vec<u8>,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let alg = header.alg;
+    match (key, alg) {
+        (DecryptionKey::Rsa(keypair), _) => Ok(Zeroizing::new(
+            keypair.decrypt(padding_mode(alg)?, encrypted_key)?,
+        )),
+        (DecryptionKey::Ec(keypair), KeyAlgorithm::EcdhEs | KeyAlgorithm::EcdhEsA256Kw) => {
+            let epk = header
+                .epk
+                .as_ref()
+                .ok_or_else(|| anyhow!("no epk in the header"))?
+                .public_key()?;
+            let z = keypair.derive(&epk)?;
+            let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+            let apu = decoder.decode(&header.apu).context("decode apu")?;
+            let apv = decoder.decode(&header.apv).context("decode apv")?;
+
+            if alg == KeyAlgorithm::EcdhEs {
+                if !encrypted_key.is_empty() {
+                    bail!("encrypted key given for direct key agreement");
+                }
+
+                let enc = header.enc;
+                return Ok(concat_kdf(&z, enc.as_ref(), &apu, &apv, enc.key_length()));
+            }
+
+            let kek = concat_kdf(&z, alg.as_ref(), &apu, &apv, KEK_LENGTH);
+            unwrap_key(&kek, &encrypted_key)
+        }
+        (DecryptionKey::Symmetric(kek), KeyAlgorithm::A256Kw) => unwrap_key(kek, &encrypted_key),
+        _ => bail!("key mismatches the algorithm {}", alg.as_ref()),
+    }
+}
+
+fn padding_mode(alg: KeyAlgorithm) -> Result<PaddingMode> {
+    match alg {
+        KeyAlgorithm::Rsa1_5 => Ok(PaddingMode::PKCS1v15),
+        KeyAlgorithm::RsaOaep => Ok(PaddingMode::OAEP),
+        KeyAlgorithm::RsaOaep256 => Ok(PaddingMode::OAEP256),
+        _ => bail!("key mismatches the algorithm {}", alg.as_ref()),
+    }
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{Curve, ECKeyPair, Error, RSAKeyPair};
+
+    use super::{ContentEncryption, DecryptionKey, EncryptionKey, Jwe, KeyAlgorithm};
+
+    const KEK: [u8; 32] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31,
+    ];
+
+    /// Encrypted by Python `cryptography` with `KEK`
+    const COMPACT_A256KW_A256GCM: &str = "eyJhbGciOiJBMjU2S1ciLCJlbmMiOiJBMjU2R0NNIn0.AmEV0Ay2rOltniwHTvePRkqhilMrxozs-1EXEJkQ3t2SS26RQjM8Dw.AAECAwQFBgcICQoL.08JOKZ0hrof6M_-m8rr4wIZkTfVfLQ.orQ0_GcVrB89uF0HFgHtZQ";
+
+    /// Encrypted by Python `cryptography` with `KEK`, where the `alg` and
+    /// `kid` are unprotected
+    const FLATTENED_A256KW_A256CBC_HS512: &str = r#"{"protected": "eyJlbmMiOiJBMjU2Q0JDLUhTNTEyIn0", "unprotected": {"kid": "k1"}, "header": {"alg": "A256KW"}, "encrypted_key": "w7qBCtJRDdStUWxCXZmmRXkGLZ86lJzQzf8xCqUFUFS7tVNWD_0TPMIOpONK6kzcpaL8-Sc3Jf0Vga3l8yQPGRZfmDEXRF0q", "iv": "AAECAwQFBgcICQoLDA0ODw", "ciphertext": "IJaU6XGUDm3lanb3THhuk4gd4p7_a6MCoCNu_DUrf1w", "tag": "S08Yv1p7w3XaqoJzFqpcDxIIgNeGeIRCzSmTMQTGixM", "aad": "YWRkaXRpb25hbCBkYXRh"}"#;
+
+    #[test]
+    fn decrypt_compact() {
+        let jwe = Jwe::from_compact(COMPACT_A256KW_A256GCM).expect("parse JWE failed");
+        let plaintext = jwe
+            .decrypt(DecryptionKey::Symmetric(&KEK))
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"Live long and prosper.");
+        assert_eq!(
+            jwe.to_compact().expect("serialize JWE failed"),
+            COMPACT_A256KW_A256GCM
+        );
+    }
+
+    #[test]
+    fn decrypt_flattened() {
+        let jwe: Jwe =
+            serde_json::from_str(FLATTENED_A256KW_A256CBC_HS512).expect("parse JWE failed");
+        let header = jwe.header().expect("parse header failed");
+        assert_eq!(header.alg, KeyAlgorithm::A256Kw);
+        assert_eq!(header.kid.as_deref(), Some("k1"));
+        let plaintext = jwe
+            .decrypt(DecryptionKey::Symmetric(&KEK))
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"Live long and prosper.");
+        jwe.to_compact()
+            .expect_err("JWE with aad has no compact serialization");
+    }
+
+    #[rstest]
+    #[case(KeyAlgorithm::Rsa1_5)]
+    #[case(KeyAlgorithm::RsaOaep)]
+    #[case(KeyAlgorithm::RsaOaep256)]
+    #[case(KeyAlgorithm::EcdhEs)]
+    #[case(KeyAlgorithm::EcdhEsA256Kw)]
+    #[case(KeyAlgorithm::A256Kw)]
+    fn en_decrypt(
+        #[case] alg: KeyAlgorithm,
+        #[values(
+            ContentEncryption::A256Gcm,
+            ContentEncryption::A256CbcHs512,
+            ContentEncryption::C20P,
+            ContentEncryption::XC20P,
+            ContentEncryption::A256GcmSiv
+        )]
+        enc: ContentEncryption,
+    ) {
+        let rsa_key = RSAKeyPair::new().expect("generate key pair failed");
+        let rsa_public_key = rsa_key.public_key();
+        let ec_key = ECKeyPair::new(Curve::P256).expect("generate key pair failed");
+        let (encryption_key, decryption_key) = match alg {
+            KeyAlgorithm::Rsa1_5 | KeyAlgorithm::RsaOaep | KeyAlgorithm::RsaOaep256 => (
+                EncryptionKey::Rsa(&rsa_public_key),
+                DecryptionKey::Rsa(&rsa_key),
+            ),
+            KeyAlgorithm::EcdhEs | KeyAlgorithm::EcdhEsA256Kw => (
+                EncryptionKey::Ec(ec_key.public_key()),
+                DecryptionKey::Ec(&ec_key),
+            ),
+            KeyAlgorithm::A256Kw => (
+                EncryptionKey::Symmetric(&KEK),
+                DecryptionKey::Symmetric(&KEK),
+            ),
+        };
+
+        if !enc.is_supported() {
+            let err = Jwe::encrypt(encryption_key, alg, enc, b"plaintext")
+                .expect_err("encrypt should fail");
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Unsupported(_))
+            ));
+            return;
+        }
+
+        let jwe = Jwe::encrypt(encryption_key, alg, enc, b"plaintext").expect("encrypt failed");
+        let compact = jwe.to_compact().expect("serialize JWE failed");
+        let jwe = Jwe::from_compact(&compact).expect("parse JWE failed");
+        let header = jwe.header().expect("parse header failed");
+        assert_eq!((header.alg, header.enc), (alg, enc));
+        let plaintext = jwe.decrypt(decryption_key).expect("decrypt failed");
+        assert_eq!(plaintext, b"plaintext");
+    }
+
+    /// Tampering any part fails the decryption, as the protected header and
+    /// the aad are authenticated.
+    #[rstest]
+    #[case::protected(|jwe: &mut Jwe| jwe.protected = "eyJlbmMiOiJBMjU2Q0JDLUhTNTEyIiB9".into())]
+    #[case::aad(|jwe: &mut Jwe| jwe.aad = Some("YWRkaXRpb25hbCBkYXRi".into()))]
+    #[case::ciphertext(|jwe: &mut Jwe| jwe.ciphertext.replace_range(..1, "J"))]
+    #[case::tag(|jwe: &mut Jwe| jwe.tag.replace_range(..1, "T"))]
+    #[case::encrypted_key(|jwe: &mut Jwe| jwe.encrypted_key.replace_range(..1, "x"))]
+    fn tampered(#[case] tamper: fn(&mut Jwe)) {
+        let mut jwe: Jwe =
+            serde_json::from_str(FLATTENED_A256KW_A256CBC_HS512).expect("parse JWE failed");
+        tamper(&mut jwe);
+        let err = jwe
+            .decrypt(DecryptionKey::Symmetric(&KEK))
+            .expect_err("decrypt should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DecryptionFailed(_))
+        ));
+    }
+
+    #[rstest]
+    #[case::duplicated(r#"{"alg": "A256KW"}"#, r#"{"alg": "A256KW"}"#)]
+    #[case::crit(r#"{"alg": "A256KW", "crit": ["exp"], "exp": 0}"#, "{}")]
+    #[case::unknown_alg(r#"{"alg": "dir"}"#, "{}")]
+    fn invalid_header(#[case] header: &str, #[case] unprotected: &str) {
+        let mut jwe: Jwe =
+            serde_json::from_str(FLATTENED_A256KW_A256CBC_HS512).expect("parse JWE failed");
+        jwe.header = serde_json::from_str(header).expect("parse header failed");
+        jwe.unprotected = serde_json::from_str(unprotected).expect("parse header failed");
+        jwe.header().expect_err("parse header should fail");
+    }
+
+    #[test]
+    fn key_mismatch() {
+        let ec_key = ECKeyPair::new(Curve::P256).expect("generate key pair failed");
+        let jwe = Jwe::from_compact(COMPACT_A256KW_A256GCM).expect("parse JWE failed");
+        jwe.decrypt(DecryptionKey::Ec(&ec_key))
+            .expect_err("decrypt should fail");
+        Jwe::encrypt(
+            EncryptionKey::Ec(ec_key.public_key()),
+            KeyAlgorithm::RsaOaep,
+            ContentEncryption::A256Gcm,
+            b"plaintext",
+        )
+        .expect_err("encrypt should fail");
+    }
+}
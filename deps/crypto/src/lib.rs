@@ -10,24 +10,38 @@
 //! underlying implementation is used:
 //! - `rust-crypto`: Use purely rust.
 //! - `openssl`: Use openssl. If `rust-crypto` and `openssl` are both
-//! enabled, use `openssl`.
+//!   enabled, use `openssl`, except for XChaCha20-Poly1305 and AES-GCM-SIV,
+//!   which the vendored OpenSSL 1.1.1 lacks. Those fail with
+//!   [`Error::Unsupported`] unless `rust-crypto` is enabled.
 //!
 //! ## Components
 //!
 //! This crate include the following public submodules:
+//! - `error`: Errors caused by the inputs of the operations
+//! - `jwe`: JSON Web Encryption with the algorithms of the KBS responses
 //! - `symmetric`: Symmetric key en/decryption
-//! - `teekey`: Asymmetric key pair used in KBS Attestation Protocol
+//! - `teekey`: Asymmetric key pairs used in KBS Attestation Protocol, and
+//!   the key agreement of ECDH-ES
+//!
+//! The implementations are also public as the `rust` and `native`
+//! submodules. When both features are enabled, both are built, but the
+//! APIs above use `native` where it is implemented.
 
 #[macro_use]
 extern crate strum;
 
 #[cfg(feature = "openssl")]
 pub mod native;
-#[cfg(all(feature = "rust-crypto", not(feature = "openssl")))]
+#[cfg(feature = "rust-crypto")]
 pub mod rust;
 
+pub mod error;
+pub use error::Error;
+
+pub mod jwe;
+
 mod symmetric;
 pub use symmetric::*;
 
-// mod teekey;
-// pub use teekey::*;
+mod teekey;
+pub use teekey::*;
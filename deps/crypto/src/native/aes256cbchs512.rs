@@ -0,0 +1,108 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! This mod implements AES_256_CBC_HMAC_SHA_512, i.e. `A256CBC-HS512` of
+//! <https://datatracker.ietf.org/doc/html/rfc7518#section-5.2.5>.
+
+use anyhow::*;
+use openssl::{
+    hash::MessageDigest,
+    memcmp,
+    pkey::PKey,
+    sign::Signer,
+    symm::{self, Cipher},
+};
+
+/// Length of each of the MAC key and the encryption key, which are the two
+/// halves of the key
+const HALF_KEY_LENGTH: usize = 32;
+
+const TAG_LENGTH: usize = 32;
+
+/// The tag, i.e. the truncated HMAC over the `aad`, `iv`, `ciphertext` and
+/// the bit length of `aad`
+fn tag(mac_key: &[u8], aad: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let mac_key = PKey::hmac(mac_key)?;
+    let mut signer = Signer::new(MessageDigest::sha512(), &mac_key)?;
+    signer.update(aad)?;
+    signer.update(iv)?;
+    signer.update(ciphertext)?;
+    signer.update(&(aad.len() as u64 * 8).to_be_bytes())?;
+    let mut tag = signer.sign_to_vec()?;
+    tag.truncate(TAG_LENGTH);
+    Ok(tag)
+}
+
+/// Decrypt the `encrypted_data`, which is the ciphertext followed by the
+/// tag, authenticating the additional data `aad`.
+pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if key.len() != 2 * HALF_KEY_LENGTH {
+        bail!("Illegal length of key");
+    }
+    if encrypted_data.len() < TAG_LENGTH {
+        bail!("Illegal length of ciphertext");
+    }
+
+    let (mac_key, enc_key) = key.split_at(HALF_KEY_LENGTH);
+    let (ciphertext, expected_tag) = encrypted_data.split_at(encrypted_data.len() - TAG_LENGTH);
+    if !memcmp::eq(&tag(mac_key, aad, iv, ciphertext)?, expected_tag) {
+        bail!("aes-256-cbc-hmac-sha-512 tag mismatch");
+    }
+
+    symm::decrypt(Cipher::aes_256_cbc(), enc_key, Some(iv), ciphertext)
+        .map_err(|e| anyhow!("aes-256-cbc decrypt failed: {e}"))
+}
+
+/// Encrypt the `data` and return the ciphertext followed by the tag, which
+/// also authenticates the additional data `aad`.
+pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if key.len() != 2 * HALF_KEY_LENGTH {
+        bail!("Illegal length of key");
+    }
+
+    let (mac_key, enc_key) = key.split_at(HALF_KEY_LENGTH);
+    let mut ciphertext = symm::encrypt(Cipher::aes_256_cbc(), enc_key, Some(iv), data)
+        .map_err(|e| anyhow!(e.to_string()))?;
+    let tag = tag(mac_key, aad, iv, &ciphertext)?;
+    ciphertext.extend_from_slice(&tag);
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt};
+
+    /// The test vector of Appendix B.3 of RFC 7518
+    #[test]
+    fn en_decrypt() {
+        let key: Vec<u8> = (0..64).collect();
+        let iv = [
+            0x1a, 0xf3, 0x8c, 0x2d, 0xc2, 0xb9, 0x6f, 0xfd, 0xd8, 0x66, 0x94, 0x09, 0x23, 0x41,
+            0xbc, 0x04,
+        ];
+        let plaintext = b"A cipher system must not be required to be secret, and it must be able to fall into the hands of the enemy without inconvenience";
+        let aad = b"The second principle of Auguste Kerckhoffs";
+        let expected = [
+            0x4a, 0xff, 0xaa, 0xad, 0xb7, 0x8c, 0x31, 0xc5, 0xda, 0x4b, 0x1b, 0x59, 0x0d, 0x10,
+            0xff, 0xbd, 0x3d, 0xd8, 0xd5, 0xd3, 0x02, 0x42, 0x35, 0x26, 0x91, 0x2d, 0xa0, 0x37,
+            0xec, 0xbc, 0xc7, 0xbd, 0x82, 0x2c, 0x30, 0x1d, 0xd6, 0x7c, 0x37, 0x3b, 0xcc, 0xb5,
+            0x84, 0xad, 0x3e, 0x92, 0x79, 0xc2, 0xe6, 0xd1, 0x2a, 0x13, 0x74, 0xb7, 0x7f, 0x07,
+            0x75, 0x53, 0xdf, 0x82, 0x94, 0x10, 0x44, 0x6b, 0x36, 0xeb, 0xd9, 0x70, 0x66, 0x29,
+            0x6a, 0xe6, 0x42, 0x7e, 0xa7, 0x5c, 0x2e, 0x08, 0x46, 0xa1, 0x1a, 0x09, 0xcc, 0xf5,
+            0x37, 0x0d, 0xc8, 0x0b, 0xfe, 0xcb, 0xad, 0x28, 0xc7, 0x3f, 0x09, 0xb3, 0xa3, 0xb7,
+            0x5e, 0x66, 0x2a, 0x25, 0x94, 0x41, 0x0a, 0xe4, 0x96, 0xb2, 0xe2, 0xe6, 0x60, 0x9e,
+            0x31, 0xe6, 0xe0, 0x2c, 0xc8, 0x37, 0xf0, 0x53, 0xd2, 0x1f, 0x37, 0xff, 0x4f, 0x51,
+            0x95, 0x0b, 0xbe, 0x26, 0x38, 0xd0, 0x9d, 0xd7, 0xa4, 0x93, 0x09, 0x30, 0x80, 0x6d,
+            0x07, 0x03, 0xb1, 0xf6, 0x4d, 0xd3, 0xb4, 0xc0, 0x88, 0xa7, 0xf4, 0x5c, 0x21, 0x68,
+            0x39, 0x64, 0x5b, 0x20, 0x12, 0xbf, 0x2e, 0x62, 0x69, 0xa8, 0xc5, 0x6a, 0x81, 0x6d,
+            0xbc, 0x1b, 0x26, 0x77, 0x61, 0x95, 0x5b, 0xc5,
+        ];
+        let ciphertext = encrypt(plaintext, &key, &iv, aad).expect("encryption failed");
+        assert_eq!(ciphertext, expected);
+        let plaintext_de = decrypt(&ciphertext, &key, &iv, aad).expect("decryption failed");
+        assert_eq!(plaintext_de, plaintext);
+        decrypt(&ciphertext, &key, &iv, b"other").expect_err("decryption should fail");
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! This mod implements aes-256 key wrap & unwrap.
+
+use anyhow::*;
+use openssl::aes::{unwrap_key, wrap_key, AesKey};
+
+const BLOCK_LENGTH: usize = 8;
+
+pub fn wrap(key: &[u8], kek: &[u8]) -> Result<Vec<u8>> {
+    if key.len() < 2 * BLOCK_LENGTH || !key.len().is_multiple_of(BLOCK_LENGTH) {
+        bail!("Illegal length of key to wrap");
+    }
+
+    let kek = AesKey::new_encrypt(kek).map_err(|_| anyhow!("illegal key wrapping key"))?;
+    let mut wrapped_key = vec![0; key.len() + BLOCK_LENGTH];
+    let length = wrap_key(&kek, None, &mut wrapped_key, key)
+        .map_err(|_| anyhow!("aes-256 key wrap failed"))?;
+    wrapped_key.truncate(length);
+    Ok(wrapped_key)
+}
+
+pub fn unwrap(wrapped_key: &[u8], kek: &[u8]) -> Result<Vec<u8>> {
+    if wrapped_key.len() < 3 * BLOCK_LENGTH || !wrapped_key.len().is_multiple_of(BLOCK_LENGTH) {
+        bail!("Illegal length of wrapped key");
+    }
+
+    let kek = AesKey::new_decrypt(kek).map_err(|_| anyhow!("illegal key wrapping key"))?;
+    let mut key = vec![0; wrapped_key.len() - BLOCK_LENGTH];
+    let length = unwrap_key(&kek, None, &mut key, wrapped_key)
+        .map_err(|_| anyhow!("aes-256 key unwrap failed"))?;
+    key.truncate(length);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unwrap, wrap};
+
+    /// The test vector of section 4.6 of RFC 3394
+    #[test]
+    fn wrap_unwrap() {
+        let kek: Vec<u8> = (0..32).collect();
+        let key = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b,
+            0x0c, 0x0d, 0x0e, 0x0f,
+        ];
+        let expected = [
+            0x28, 0xc9, 0xf4, 0x04, 0xc4, 0xb8, 0x10, 0xf4, 0xcb, 0xcc, 0xb3, 0x5c, 0xfb, 0x87,
+            0xf8, 0x26, 0x3f, 0x57, 0x86, 0xe2, 0xd8, 0x0e, 0xd3, 0x26, 0xcb, 0xc7, 0xf0, 0xe7,
+            0x1a, 0x99, 0xf4, 0x3b, 0xfb, 0x98, 0x8b, 0x9b, 0x7a, 0x02, 0xdd, 0x21,
+        ];
+        let wrapped_key = wrap(&key, &kek).expect("wrap failed");
+        assert_eq!(wrapped_key, expected);
+        let unwrapped_key = unwrap(&wrapped_key, &kek).expect("unwrap failed");
+        assert_eq!(unwrapped_key, key);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! This mod implements chacha20-poly1305 encryption & decryption.
+
+use anyhow::*;
+use openssl::symm::Cipher;
+
+const TAG_LENGTH: usize = 16;
+
+/// Decrypt the `encrypted_data`, which is the ciphertext followed by the
+/// tag, authenticating the additional data `aad`.
+pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Cipher::chacha20_poly1305();
+    if encrypted_data.len() < TAG_LENGTH {
+        bail!("Illegal length of ciphertext");
+    }
+
+    let (data, tag) = encrypted_data.split_at(encrypted_data.len() - TAG_LENGTH);
+    openssl::symm::decrypt_aead(cipher, key, Some(iv), aad, data, tag)
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+/// Encrypt the `data` and return the ciphertext followed by the tag, which
+/// also authenticates the additional data `aad`.
+pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Cipher::chacha20_poly1305();
+    let mut tag = [0u8; TAG_LENGTH];
+    let mut ciphertext = openssl::symm::encrypt_aead(cipher, key, Some(iv), aad, data, &mut tag)
+        .map_err(|e| anyhow!(e.to_string()))?;
+    ciphertext.extend_from_slice(&tag);
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt};
+
+    /// The test vector of section 2.8.2 of RFC 8439
+    #[test]
+    fn en_decrypt() {
+        let key: Vec<u8> = (0x80..0xa0).collect();
+        let iv = [
+            0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let expected = [
+            0xd3, 0x1a, 0x8d, 0x34, 0x64, 0x8e, 0x60, 0xdb, 0x7b, 0x86, 0xaf, 0xbc, 0x53, 0xef,
+            0x7e, 0xc2, 0xa4, 0xad, 0xed, 0x51, 0x29, 0x6e, 0x08, 0xfe, 0xa9, 0xe2, 0xb5, 0xa7,
+            0x36, 0xee, 0x62, 0xd6, 0x3d, 0xbe, 0xa4, 0x5e, 0x8c, 0xa9, 0x67, 0x12, 0x82, 0xfa,
+            0xfb, 0x69, 0xda, 0x92, 0x72, 0x8b, 0x1a, 0x71, 0xde, 0x0a, 0x9e, 0x06, 0x0b, 0x29,
+            0x05, 0xd6, 0xa5, 0xb6, 0x7e, 0xcd, 0x3b, 0x36, 0x92, 0xdd, 0xbd, 0x7f, 0x2d, 0x77,
+            0x8b, 0x8c, 0x98, 0x03, 0xae, 0xe3, 0x28, 0x09, 0x1b, 0x58, 0xfa, 0xb3, 0x24, 0xe4,
+            0xfa, 0xd6, 0x75, 0x94, 0x55, 0x85, 0x80, 0x8b, 0x48, 0x31, 0xd7, 0xbc, 0x3f, 0xf4,
+            0xde, 0xf0, 0x8e, 0x4b, 0x7a, 0x9d, 0xe5, 0x76, 0xd2, 0x65, 0x86, 0xce, 0xc6, 0x4b,
+            0x61, 0x16, 0x1a, 0xe1, 0x0b, 0x59, 0x4f, 0x09, 0xe2, 0x6a, 0x7e, 0x90, 0x2e, 0xcb,
+            0xd0, 0x60, 0x06, 0x91,
+        ];
+        let ciphertext = encrypt(plaintext, &key, &iv, &aad).expect("encryption failed");
+        assert_eq!(ciphertext, expected);
+        let plaintext_de = decrypt(&ciphertext, &key, &iv, &aad).expect("decryption failed");
+        assert_eq!(plaintext_de, plaintext);
+        decrypt(&ciphertext, &key, &iv, b"other").expect_err("decryption should fail");
+    }
+}
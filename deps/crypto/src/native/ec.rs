@@ -0,0 +1,71 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Implementations of the EC TeeKey
+
+use anyhow::*;
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    derive::Deriver,
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{PKey, Private},
+};
+use zeroize::Zeroizing;
+
+use crate::Curve;
+
+#[derive(Clone)]
+pub struct ECKeyPair {
+    curve: Curve,
+    private_key: PKey<Private>,
+}
+
+fn group(curve: Curve) -> Result<EcGroup> {
+    let nid = match curve {
+        Curve::P256 => Nid::X9_62_PRIME256V1,
+        Curve::P384 => Nid::SECP384R1,
+    };
+    Ok(EcGroup::from_curve_name(nid)?)
+}
+
+impl ECKeyPair {
+    pub fn new(curve: Curve) -> Result<ECKeyPair> {
+        let group = group(curve)?;
+        let key = EcKey::generate(&group)?;
+        Ok(ECKeyPair {
+            curve,
+            private_key: PKey::from_ec_key(key)?,
+        })
+    }
+
+    /// The x and y coordinates of the public key in big-endian bytes
+    pub fn coordinates(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let key = self.private_key.ec_key()?;
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        key.public_key()
+            .affine_coordinates(key.group(), &mut x, &mut y, &mut ctx)?;
+
+        let length = self.curve.coordinate_length() as i32;
+        Ok((x.to_vec_padded(length)?, y.to_vec_padded(length)?))
+    }
+
+    /// Agree the shared secret with the public key of coordinates `x` and
+    /// `y` on the same curve by ECDH.
+    pub fn derive(&self, x: &[u8], y: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let group = group(self.curve)?;
+        let x = BigNum::from_slice(x)?;
+        let y = BigNum::from_slice(y)?;
+        let public_key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+            .and_then(PKey::from_ec_key)
+            .map_err(|_| anyhow!("illegal EC public key"))?;
+
+        let mut deriver = Deriver::new(&self.private_key)?;
+        deriver.set_peer(&public_key)?;
+        Ok(Zeroizing::new(deriver.derive_to_vec()?))
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Implementations of the TeeKey
+
+use anyhow::*;
+use openssl::{
+    bn::BigNum,
+    encrypt::{Decrypter, Encrypter},
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    rsa::{Padding, Rsa},
+};
+
+use crate::{Error, PaddingMode};
+
+const RSA_PUBKEY_LENGTH: u32 = 2048;
+
+#[derive(Debug, Clone)]
+pub struct RSAKeyPair {
+    private_key: PKey<Private>,
+}
+
+/// Set the padding of `ctx`, which is an [`Encrypter`] or a [`Decrypter`].
+/// OAEP uses the same digest for the MGF1.
+macro_rules! set_padding {
+    ($ctx:expr, $mode:expr) => {
+        match $mode {
+            PaddingMode::OAEP => {
+                $ctx.set_rsa_padding(Padding::PKCS1_OAEP)?;
+                $ctx.set_rsa_oaep_md(MessageDigest::sha1())?;
+                $ctx.set_rsa_mgf1_md(MessageDigest::sha1())?;
+            }
+            PaddingMode::OAEP256 => {
+                $ctx.set_rsa_padding(Padding::PKCS1_OAEP)?;
+                $ctx.set_rsa_oaep_md(MessageDigest::sha256())?;
+                $ctx.set_rsa_mgf1_md(MessageDigest::sha256())?;
+            }
+            PaddingMode::PKCS1v15 => $ctx.set_rsa_padding(Padding::PKCS1)?,
+        }
+    };
+}
+
+impl RSAKeyPair {
+    pub fn new() -> Result<RSAKeyPair> {
+        let rsa = Rsa::generate(RSA_PUBKEY_LENGTH)?;
+        Ok(RSAKeyPair {
+            private_key: PKey::from_rsa(rsa)?,
+        })
+    }
+
+    /// The modulus of the public key in big-endian bytes
+    pub fn n(&self) -> Vec<u8> {
+        self.rsa().n().to_vec()
+    }
+
+    /// The public exponent in big-endian bytes
+    pub fn e(&self) -> Vec<u8> {
+        self.rsa().e().to_vec()
+    }
+
+    fn rsa(&self) -> Rsa<Private> {
+        self.private_key
+            .rsa()
+            .expect("the key pair is always generated as RSA")
+    }
+
+    pub fn decrypt(&self, mode: PaddingMode, cipher_text: Vec<u8>) -> Result<Vec<u8>> {
+        let mut decrypter = Decrypter::new(&self.private_key)?;
+        set_padding!(decrypter, mode);
+
+        let mut plaintext = vec![0; decrypter.decrypt_len(&cipher_text)?];
+        let length = decrypter
+            .decrypt(&cipher_text, &mut plaintext)
+            .map_err(|e| match mode {
+                PaddingMode::OAEP => {
+                    Error::DecryptionFailed(format!("RSA key decrypt OAEP failed: {e}"))
+                }
+                PaddingMode::OAEP256 => {
+                    Error::DecryptionFailed(format!("RSA key decrypt OAEP-256 failed: {e}"))
+                }
+                PaddingMode::PKCS1v15 => {
+                    Error::DecryptionFailed(format!("RSA key pkcs1v15 decrypt failed: {e}"))
+                }
+            })?;
+        plaintext.truncate(length);
+        Ok(plaintext)
+    }
+}
+
+/// Encrypt `plaintext` with the public key of modulus `n` and public
+/// exponent `e`, both in big-endian bytes.
+pub fn encrypt(n: &[u8], e: &[u8], mode: PaddingMode, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let public_key = Rsa::from_public_components(BigNum::from_slice(n)?, BigNum::from_slice(e)?)
+        .and_then(PKey::from_rsa)
+        .context("illegal RSA public key")?;
+    let mut encrypter = Encrypter::new(&public_key)?;
+    set_padding!(encrypter, mode);
+
+    let mut ciphertext = vec![0; encrypter.encrypt_len(plaintext)?];
+    let length = encrypter.encrypt(plaintext, &mut ciphertext)?;
+    ciphertext.truncate(length);
+    Ok(ciphertext)
+}
@@ -5,26 +5,39 @@
 
 //! This mod implements aes-256-gcm encryption & decryption.
 
-use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, Key, KeyInit, Nonce,
+};
 use anyhow::*;
 
-pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
+/// Decrypt the `encrypted_data`, which is the ciphertext followed by the
+/// tag, authenticating the additional data `aad`.
+pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
     let decrypting_key = Key::<Aes256Gcm>::from_slice(key);
     let cipher = Aes256Gcm::new(decrypting_key);
     let nonce = Nonce::from_slice(iv);
     let plain_text = cipher
-        .decrypt(nonce, encrypted_data)
+        .decrypt(
+            nonce,
+            Payload {
+                msg: encrypted_data,
+                aad,
+            },
+        )
         .map_err(|e| anyhow!("aes-256-gcm decrypt failed: {:?}", e))?;
 
     Ok(plain_text)
 }
 
-pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
+/// Encrypt the `data` and return the ciphertext followed by the tag, which
+/// also authenticates the additional data `aad`.
+pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
     let encrypting_key = Key::<Aes256Gcm>::from_slice(key);
     let cipher = Aes256Gcm::new(encrypting_key);
     let nonce = Nonce::from_slice(iv);
     let ciphertext = cipher
-        .encrypt(nonce, data)
+        .encrypt(nonce, Payload { msg: data, aad })
         .map_err(|e| anyhow!("aes-256-gcm encrypt failed: {:?}", e))?;
 
     Ok(ciphertext)
@@ -37,11 +50,27 @@ mod tests {
     use super::{decrypt, encrypt};
 
     #[rstest]
-    #[case(b"plaintext1", b"0123456789abcdefghijklmnopqrstuv", b"unique nonce")]
-    #[case(b"plaintext2", b"hijklmnopqrstuv0123456789abcdefg", b"unique2nonce")]
-    fn en_decrypt(#[case] plaintext: &[u8], #[case] key: &[u8], #[case] iv: &[u8]) {
-        let ciphertext = encrypt(plaintext, key, iv).expect("encryption failed");
-        let plaintext_de = decrypt(&ciphertext, key, iv).expect("decryption failed");
+    #[case(
+        b"plaintext1",
+        b"0123456789abcdefghijklmnopqrstuv",
+        b"unique nonce",
+        b""
+    )]
+    #[case(
+        b"plaintext2",
+        b"hijklmnopqrstuv0123456789abcdefg",
+        b"unique2nonce",
+        b"aad"
+    )]
+    fn en_decrypt(
+        #[case] plaintext: &[u8],
+        #[case] key: &[u8],
+        #[case] iv: &[u8],
+        #[case] aad: &[u8],
+    ) {
+        let ciphertext = encrypt(plaintext, key, iv, aad).expect("encryption failed");
+        let plaintext_de = decrypt(&ciphertext, key, iv, aad).expect("decryption failed");
         assert_eq!(plaintext, plaintext_de);
+        decrypt(&ciphertext, key, iv, b"other").expect_err("decryption should fail");
     }
 }
@@ -0,0 +1,77 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! This mod implements aes-256-gcm-siv encryption & decryption.
+
+use aes_gcm_siv::{
+    aead::{Aead, Payload},
+    Aes256GcmSiv, Key, KeyInit, Nonce,
+};
+use anyhow::*;
+
+/// Decrypt the `encrypted_data`, which is the ciphertext followed by the
+/// tag, authenticating the additional data `aad`.
+pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(key));
+    let nonce = Nonce::from_slice(iv);
+    let plain_text = cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: encrypted_data,
+                aad,
+            },
+        )
+        .map_err(|e| anyhow!("aes-256-gcm-siv decrypt failed: {:?}", e))?;
+
+    Ok(plain_text)
+}
+
+/// Encrypt the `data` and return the ciphertext followed by the tag, which
+/// also authenticates the additional data `aad`.
+pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(key));
+    let nonce = Nonce::from_slice(iv);
+    let ciphertext = cipher
+        .encrypt(nonce, Payload { msg: data, aad })
+        .map_err(|e| anyhow!("aes-256-gcm-siv encrypt failed: {:?}", e))?;
+
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt};
+
+    /// Encrypted by Python `cryptography`
+    #[test]
+    fn en_decrypt() {
+        let key: Vec<u8> = (0x80..0xa0).collect();
+        let iv = [
+            0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let expected = [
+            0x96, 0xaf, 0x73, 0x97, 0x37, 0x3d, 0x49, 0x75, 0x83, 0x7a, 0x26, 0x30, 0xb2, 0x10,
+            0xd4, 0x1d, 0x8d, 0xcb, 0xb0, 0xed, 0x17, 0xc2, 0xe5, 0x47, 0x97, 0x2b, 0x26, 0xfc,
+            0xa1, 0x0a, 0x15, 0xad, 0xc5, 0xba, 0x8f, 0x0e, 0x2d, 0x74, 0xe6, 0x51, 0xe8, 0x5c,
+            0x3f, 0x46, 0xf6, 0xfb, 0x92, 0x5b, 0xe0, 0x8a, 0x1b, 0xd9, 0x9c, 0xd0, 0x04, 0x6c,
+            0x06, 0x57, 0x5a, 0xc6, 0xee, 0x78, 0x83, 0x09, 0xd9, 0x39, 0x08, 0x6c, 0xd8, 0xc2,
+            0x6c, 0x24, 0x32, 0x3e, 0xae, 0x19, 0x8d, 0xd1, 0xed, 0x9c, 0x1a, 0xae, 0x79, 0x4b,
+            0xbe, 0x15, 0x4d, 0x4d, 0xa6, 0x19, 0x87, 0x04, 0x54, 0x20, 0x59, 0x2e, 0x7a, 0xfb,
+            0xba, 0x7e, 0x85, 0x9d, 0x8a, 0x4a, 0x95, 0x9f, 0xc8, 0xc2, 0xfc, 0xce, 0x99, 0xa5,
+            0x45, 0x3e, 0xc7, 0x20, 0x3a, 0x0b, 0x08, 0x6d, 0xc8, 0x95, 0x59, 0x60, 0xc2, 0x22,
+            0x74, 0xfb, 0x04, 0xa6,
+        ];
+        let ciphertext = encrypt(plaintext, &key, &iv, &aad).expect("encryption failed");
+        assert_eq!(ciphertext, expected);
+        let plaintext_de = decrypt(&ciphertext, &key, &iv, &aad).expect("decryption failed");
+        assert_eq!(plaintext_de, plaintext);
+        decrypt(&ciphertext, &key, &iv, b"other").expect_err("decryption should fail");
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Implementations of the EC TeeKey
+
+use anyhow::*;
+use elliptic_curve::{
+    ecdh::diffie_hellman,
+    sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint},
+    AffinePoint, CurveArithmetic, FieldBytesSize, PublicKey, SecretKey,
+};
+use p256::NistP256;
+use p384::NistP384;
+use zeroize::Zeroizing;
+
+use crate::Curve;
+
+#[derive(Clone)]
+pub enum ECKeyPair {
+    P256(SecretKey<NistP256>),
+    P384(SecretKey<NistP384>),
+}
+
+impl ECKeyPair {
+    pub fn new(curve: Curve) -> Result<ECKeyPair> {
+        let mut rng = rand::rngs::OsRng;
+        Ok(match curve {
+            Curve::P256 => ECKeyPair::P256(SecretKey::random(&mut rng)),
+            Curve::P384 => ECKeyPair::P384(SecretKey::random(&mut rng)),
+        })
+    }
+
+    /// The x and y coordinates of the public key in big-endian bytes
+    pub fn coordinates(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        match self {
+            ECKeyPair::P256(key) => coordinates(key),
+            ECKeyPair::P384(key) => coordinates(key),
+        }
+    }
+
+    /// Agree the shared secret with the public key of coordinates `x` and
+    /// `y` on the same curve by ECDH.
+    pub fn derive(&self, x: &[u8], y: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        match self {
+            ECKeyPair::P256(key) => derive(key, x, y),
+            ECKeyPair::P384(key) => derive(key, x, y),
+        }
+    }
+}
+
+fn coordinates<C>(key: &SecretKey<C>) -> Result<(Vec<u8>, Vec<u8>)>
+where
+    C: CurveArithmetic,
+    FieldBytesSize<C>: ModulusSize,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+{
+    let point = key.public_key().to_encoded_point(false);
+    match (point.x(), point.y()) {
+        (Some(x), Some(y)) => Ok((x.to_vec(), y.to_vec())),
+        _ => bail!("EC public key is the identity point"),
+    }
+}
+
+fn derive<C>(key: &SecretKey<C>, x: &[u8], y: &[u8]) -> Result<Zeroizing<Vec<u8>>>
+where
+    C: CurveArithmetic,
+    FieldBytesSize<C>: ModulusSize,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+{
+    let sec1 = [&[0x04], x, y].concat();
+    let public_key =
+        PublicKey::<C>::from_sec1_bytes(&sec1).map_err(|_| anyhow!("illegal EC public key"))?;
+    let shared_secret = diffie_hellman(key.to_nonzero_scalar(), public_key.as_affine());
+    Ok(Zeroizing::new(shared_secret.raw_secret_bytes().to_vec()))
+}
@@ -5,11 +5,15 @@
 
 //! Crypto suites implemented by purely rust
 
+pub mod aes256cbchs512;
 pub mod aes256ctr;
 pub mod aes256gcm;
+pub mod aes256gcmsiv;
+pub mod aes256kw;
+pub mod chacha20poly1305;
+pub mod xchacha20poly1305;
 
-pub use aes256ctr::*;
-pub use aes256gcm::*;
+pub mod ec;
 
 pub mod rsa;
 pub use ::rsa::*;
@@ -6,7 +6,9 @@
 //! Implementations of the TeeKey
 
 use anyhow::*;
-use rsa::{Oaep, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use rsa::{traits::PublicKeyParts, BigUint, Oaep, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+
+use crate::{Error, PaddingMode};
 
 const RSA_PUBKEY_LENGTH: usize = 2048;
 
@@ -16,18 +18,6 @@ pub struct RSAKeyPair {
     pub public_key: RsaPublicKey,
 }
 
-/// Definations of different Padding mode for encryption. Refer to
-/// <https://datatracker.ietf.org/doc/html/rfc7518#section-4.1> for
-/// more information.
-#[derive(EnumString, AsRefStr)]
-pub enum PaddingMode {
-    #[strum(serialize = "RSA-OAEP")]
-    OAEP,
-
-    #[strum(serialize = "RSA1_5")]
-    PKCS1v15,
-}
-
 impl RSAKeyPair {
     pub fn new() -> Result<RSAKeyPair> {
         let mut rng = rand::thread_rng();
@@ -41,16 +31,52 @@ impl RSAKeyPair {
         })
     }
 
+    /// The modulus of the public key in big-endian bytes
+    pub fn n(&self) -> Vec<u8> {
+        self.public_key.n().to_bytes_be()
+    }
+
+    /// The public exponent in big-endian bytes
+    pub fn e(&self) -> Vec<u8> {
+        self.public_key.e().to_bytes_be()
+    }
+
     pub fn decrypt(&self, mode: PaddingMode, cipher_text: Vec<u8>) -> Result<Vec<u8>> {
         match mode {
             PaddingMode::OAEP => self
+                .private_key
+                .decrypt(Oaep::new::<sha1::Sha1>(), &cipher_text)
+                .map_err(|e| {
+                    Error::DecryptionFailed(format!("RSA key decrypt OAEP failed: {e}")).into()
+                }),
+            PaddingMode::OAEP256 => self
                 .private_key
                 .decrypt(Oaep::new::<sha2::Sha256>(), &cipher_text)
-                .map_err(|e| anyhow!("RSA key decrypt OAEP failed: {:?}", e)),
+                .map_err(|e| {
+                    Error::DecryptionFailed(format!("RSA key decrypt OAEP-256 failed: {e}")).into()
+                }),
             PaddingMode::PKCS1v15 => self
                 .private_key
                 .decrypt(Pkcs1v15Encrypt, &cipher_text)
-                .map_err(|e| anyhow!("RSA key pkcs1v15 decrypt failed: {:?}", e)),
+                .map_err(|e| {
+                    Error::DecryptionFailed(format!("RSA key pkcs1v15 decrypt failed: {e}")).into()
+                }),
         }
     }
 }
+
+/// Encrypt `plaintext` with the public key of modulus `n` and public
+/// exponent `e`, both in big-endian bytes.
+pub fn encrypt(n: &[u8], e: &[u8], mode: PaddingMode, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let public_key = RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+        .context("illegal RSA public key")?;
+    let mut rng = rand::thread_rng();
+    let ciphertext = match mode {
+        PaddingMode::OAEP => public_key.encrypt(&mut rng, Oaep::new::<sha1::Sha1>(), plaintext),
+        PaddingMode::OAEP256 => {
+            public_key.encrypt(&mut rng, Oaep::new::<sha2::Sha256>(), plaintext)
+        }
+        PaddingMode::PKCS1v15 => public_key.encrypt(&mut rng, Pkcs1v15Encrypt, plaintext),
+    }?;
+    Ok(ciphertext)
+}
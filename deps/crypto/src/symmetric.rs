@@ -12,13 +12,20 @@ use zeroize::Zeroizing;
 #[cfg(feature = "openssl")]
 use crate::native::*;
 
+#[cfg(all(feature = "rust-crypto", feature = "openssl"))]
+use crate::rust::{aes256gcmsiv, xchacha20poly1305};
+
 #[cfg(all(feature = "rust-crypto", not(feature = "openssl")))]
 use crate::rust::*;
 
+use crate::Error;
+
+/// Length of the keys of all the [`WrapType`]s
+const KEY_LENGTH: usize = 32;
+
 /// Supported WrapType, s.t. encryption algorithm using to encrypt the
 /// [PLBCO](https://github.com/confidential-containers/attestation-agent/blob/main/docs/IMPLEMENTATION.md#encryption-and-decryption-of-container-image).
-/// TODO: Support more kinds of en/decryption schemes.
-#[derive(EnumString, AsRefStr, Deserialize, Serialize, Copy, Clone)]
+#[derive(EnumString, AsRefStr, Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum WrapType {
     /// The serialized name follows 5.2.6 section
     /// <https://www.rfc-editor.org/rfc/inline-errata/rfc7518.html>
@@ -30,36 +37,336 @@ pub enum WrapType {
     #[strum(serialize = "A256CTR")]
     #[serde(alias = "A256CTR")]
     Aes256Ctr,
+
+    /// ChaCha20-Poly1305 of RFC 8439, faster than A256GCM on the platforms
+    /// without AES instructions. The serialized name follows
+    /// draft-amringer-jose-chacha.
+    #[strum(serialize = "C20P")]
+    #[serde(alias = "C20P")]
+    ChaCha20Poly1305,
+
+    /// ChaCha20-Poly1305 with 24-byte nonces, which are long enough to be
+    /// generated randomly for any number of messages under a key. Only the
+    /// `rust-crypto` backend implements it, so it fails with
+    /// [`Error::Unsupported`] if built with the `openssl` backend alone.
+    #[strum(serialize = "XC20P")]
+    #[serde(alias = "XC20P")]
+    XChaCha20Poly1305,
+
+    /// AES-GCM-SIV of RFC 8452, which only leaks whether the messages are
+    /// identical if a nonce is reused. Like XC20P, it fails with
+    /// [`Error::Unsupported`] if built with the `openssl` backend alone.
+    #[strum(serialize = "A256GCM-SIV")]
+    #[serde(alias = "A256GCM-SIV")]
+    Aes256GcmSiv,
 }
 
-/// Decrypt the given `ciphertext`.
-/// Note:
-/// - IV length for A256GCM: 12 bytes
-/// - IV length for A256CTR: 16 bytes
+impl WrapType {
+    /// Length in bytes of the keys
+    pub fn key_length(&self) -> usize {
+        KEY_LENGTH
+    }
+
+    /// Length in bytes of the IVs, i.e. the nonces of the AEADs
+    pub fn iv_length(&self) -> usize {
+        match self {
+            WrapType::Aes256Gcm => 12,
+            WrapType::Aes256Ctr => 16,
+            WrapType::ChaCha20Poly1305 => 12,
+            WrapType::XChaCha20Poly1305 => 24,
+            WrapType::Aes256GcmSiv => 12,
+        }
+    }
+
+    /// Whether the enabled backends implement the wrap type. OpenSSL lacks
+    /// XC20P and A256GCM-SIV, so they need feature `rust-crypto`.
+    pub fn is_supported(&self) -> bool {
+        cfg!(feature = "rust-crypto")
+            || !matches!(self, WrapType::XChaCha20Poly1305 | WrapType::Aes256GcmSiv)
+    }
+
+    /// Check the lengths of the `key` and `iv` before passing them to the
+    /// implementations, some of which panic on wrong lengths, that the
+    /// `aad` can be authenticated, and that the wrap type is supported.
+    fn check(&self, key: &[u8], iv: &[u8], aad: &[u8]) -> Result<()> {
+        if key.len() != KEY_LENGTH {
+            return Err(Error::InvalidKeyLength {
+                expected: KEY_LENGTH,
+                actual: key.len(),
+            }
+            .into());
+        }
+
+        if iv.len() != self.iv_length() {
+            return Err(Error::InvalidIvLength {
+                expected: self.iv_length(),
+                actual: iv.len(),
+            }
+            .into());
+        }
+
+        if *self == WrapType::Aes256Ctr && !aad.is_empty() {
+            return Err(Error::AadUnsupported(self.as_ref().to_string()).into());
+        }
+
+        if !self.is_supported() {
+            return Err(Error::Unsupported(self.as_ref().to_string()).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Decrypt the given `ciphertext`, authenticating the associated data
+/// `aad` along with it. The IV length is given by [`WrapType::iv_length`],
+/// e.g. 12 bytes for A256GCM. A256CTR only accepts an empty `aad`.
 pub fn decrypt(
     key: Zeroizing<Vec<u8>>,
     ciphertext: Vec<u8>,
     iv: Vec<u8>,
+    aad: &[u8],
     wrap_type: WrapType,
 ) -> Result<Vec<u8>> {
+    wrap_type.check(&key, &iv, aad)?;
     match wrap_type {
-        WrapType::Aes256Gcm => aes256gcm::decrypt(&ciphertext, &key, &iv),
+        WrapType::Aes256Gcm => aes256gcm::decrypt(&ciphertext, &key, &iv, aad),
         WrapType::Aes256Ctr => aes256ctr::decrypt(&ciphertext, &key, &iv),
+        WrapType::ChaCha20Poly1305 => chacha20poly1305::decrypt(&ciphertext, &key, &iv, aad),
+        #[cfg(feature = "rust-crypto")]
+        WrapType::XChaCha20Poly1305 => xchacha20poly1305::decrypt(&ciphertext, &key, &iv, aad),
+        #[cfg(feature = "rust-crypto")]
+        WrapType::Aes256GcmSiv => aes256gcmsiv::decrypt(&ciphertext, &key, &iv, aad),
+        #[cfg(not(feature = "rust-crypto"))]
+        WrapType::XChaCha20Poly1305 | WrapType::Aes256GcmSiv => {
+            unreachable!("rejected by WrapType::check")
+        }
     }
+    .map_err(|e| Error::DecryptionFailed(e.to_string()).into())
 }
 
-/// Encrypt the given `plaintext`.
-/// Note:
-/// - IV length for A256GCM: 12 bytes
-/// - IV length for A256CTR: 16 bytes
+/// Encrypt the given `plaintext`, whose ciphertext also authenticates the
+/// associated data `aad`, which is not encrypted. The IV length is given by
+/// [`WrapType::iv_length`], e.g. 12 bytes for A256GCM. A256CTR only accepts
+/// an empty `aad`.
 pub fn encrypt(
     key: Zeroizing<Vec<u8>>,
     plaintext: Vec<u8>,
     iv: Vec<u8>,
+    aad: &[u8],
     wrap_type: WrapType,
 ) -> Result<Vec<u8>> {
+    wrap_type.check(&key, &iv, aad)?;
     match wrap_type {
-        WrapType::Aes256Gcm => aes256gcm::encrypt(&plaintext, &key, &iv),
+        WrapType::Aes256Gcm => aes256gcm::encrypt(&plaintext, &key, &iv, aad),
         WrapType::Aes256Ctr => aes256ctr::encrypt(&plaintext, &key, &iv),
+        WrapType::ChaCha20Poly1305 => chacha20poly1305::encrypt(&plaintext, &key, &iv, aad),
+        #[cfg(feature = "rust-crypto")]
+        WrapType::XChaCha20Poly1305 => xchacha20poly1305::encrypt(&plaintext, &key, &iv, aad),
+        #[cfg(feature = "rust-crypto")]
+        WrapType::Aes256GcmSiv => aes256gcmsiv::encrypt(&plaintext, &key, &iv, aad),
+        #[cfg(not(feature = "rust-crypto"))]
+        WrapType::XChaCha20Poly1305 | WrapType::Aes256GcmSiv => {
+            unreachable!("rejected by WrapType::check")
+        }
+    }
+}
+
+/// Wrap the `key` with the 32-byte key wrapping key `kek` by AES Key Wrap,
+/// i.e. `A256KW` of <https://datatracker.ietf.org/doc/html/rfc7518#section-4.4>.
+pub fn wrap_key(kek: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+    check_kek(kek)?;
+    aes256kw::wrap(key, kek)
+}
+
+/// Unwrap the `wrapped_key` with the 32-byte key wrapping key `kek`, which
+/// fails with [`Error::DecryptionFailed`] if `wrapped_key` is corrupted.
+pub fn unwrap_key(kek: &[u8], wrapped_key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    check_kek(kek)?;
+    aes256kw::unwrap(wrapped_key, kek)
+        .map(Zeroizing::new)
+        .map_err(|e| Error::DecryptionFailed(e.to_string()).into())
+}
+
+fn check_kek(kek: &[u8]) -> Result<()> {
+    if kek.len() != KEY_LENGTH {
+        return Err(Error::InvalidKeyLength {
+            expected: KEY_LENGTH,
+            actual: kek.len(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use zeroize::Zeroizing;
+
+    use crate::Error;
+
+    use super::{decrypt, encrypt, unwrap_key, wrap_key, WrapType};
+
+    #[rstest]
+    #[case(WrapType::Aes256Gcm, 16, 12)]
+    #[case(WrapType::Aes256Gcm, 32, 16)]
+    #[case(WrapType::Aes256Ctr, 32, 12)]
+    #[case(WrapType::XChaCha20Poly1305, 32, 12)]
+    #[case(WrapType::Aes256GcmSiv, 64, 12)]
+    fn invalid_lengths(#[case] wrap_type: WrapType, #[case] key_len: usize, #[case] iv_len: usize) {
+        let key = Zeroizing::new(vec![0u8; key_len]);
+        let err = encrypt(key, b"data".to_vec(), vec![0u8; iv_len], &[], wrap_type)
+            .expect_err("encrypt should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::InvalidKeyLength { .. } | Error::InvalidIvLength { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case(WrapType::Aes256Gcm)]
+    #[case(WrapType::ChaCha20Poly1305)]
+    #[case(WrapType::XChaCha20Poly1305)]
+    #[case(WrapType::Aes256GcmSiv)]
+    fn tampered_ciphertext(#[case] wrap_type: WrapType) {
+        let key = Zeroizing::new(vec![7u8; 32]);
+        let iv = vec![0u8; wrap_type.iv_length()];
+        if !wrap_type.is_supported() {
+            let err = encrypt(key, b"data".to_vec(), iv, b"aad", wrap_type)
+                .expect_err("encrypt should fail");
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Unsupported(_))
+            ));
+            return;
+        }
+
+        let mut ciphertext = encrypt(key.clone(), b"data".to_vec(), iv.clone(), b"aad", wrap_type)
+            .expect("encrypt failed");
+        assert_eq!(
+            decrypt(
+                key.clone(),
+                ciphertext.clone(),
+                iv.clone(),
+                b"aad",
+                wrap_type
+            )
+            .expect("decrypt failed"),
+            b"data"
+        );
+
+        let err = decrypt(
+            key.clone(),
+            ciphertext.clone(),
+            iv.clone(),
+            b"abc",
+            wrap_type,
+        )
+        .expect_err("decrypt should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DecryptionFailed(_))
+        ));
+
+        ciphertext[0] ^= 1;
+        let err = decrypt(key, ciphertext, iv, b"aad", wrap_type).expect_err("decrypt should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DecryptionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn aad_unsupported() {
+        let key = Zeroizing::new(vec![7u8; 32]);
+        let err = encrypt(
+            key,
+            b"data".to_vec(),
+            vec![0u8; 16],
+            b"aad",
+            WrapType::Aes256Ctr,
+        )
+        .expect_err("encrypt should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::AadUnsupported(_))
+        ));
+    }
+
+    #[test]
+    fn tampered_wrapped_key() {
+        let kek = [7u8; 32];
+        let mut wrapped_key = wrap_key(&kek, &[1u8; 32]).expect("wrap failed");
+        assert_eq!(
+            *unwrap_key(&kek, &wrapped_key).expect("unwrap failed"),
+            [1u8; 32]
+        );
+
+        wrapped_key[0] ^= 1;
+        let err = unwrap_key(&kek, &wrapped_key).expect_err("unwrap should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DecryptionFailed(_))
+        ));
+    }
+
+    /// The ciphertexts of either implementation are decrypted by the other.
+    #[cfg(all(feature = "rust-crypto", feature = "openssl"))]
+    #[test]
+    fn cross_implementations() {
+        use crate::{native, rust};
+
+        let key = [7u8; 32];
+        let iv = [1u8; 16];
+        let ciphertext =
+            rust::aes256gcm::encrypt(b"data", &key, &iv[..12], b"aad").expect("encrypt failed");
+        let plaintext = native::aes256gcm::decrypt(&ciphertext, &key, &iv[..12], b"aad")
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let ciphertext =
+            native::aes256gcm::encrypt(b"data", &key, &iv[..12], b"aad").expect("encrypt failed");
+        let plaintext =
+            rust::aes256gcm::decrypt(&ciphertext, &key, &iv[..12], b"aad").expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let ciphertext = rust::aes256ctr::encrypt(b"data", &key, &iv).expect("encrypt failed");
+        let plaintext = native::aes256ctr::decrypt(&ciphertext, &key, &iv).expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let ciphertext = native::aes256ctr::encrypt(b"data", &key, &iv).expect("encrypt failed");
+        let plaintext = rust::aes256ctr::decrypt(&ciphertext, &key, &iv).expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let ciphertext = rust::chacha20poly1305::encrypt(b"data", &key, &iv[..12], b"aad")
+            .expect("encrypt failed");
+        let plaintext = native::chacha20poly1305::decrypt(&ciphertext, &key, &iv[..12], b"aad")
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let ciphertext = native::chacha20poly1305::encrypt(b"data", &key, &iv[..12], b"aad")
+            .expect("encrypt failed");
+        let plaintext = rust::chacha20poly1305::decrypt(&ciphertext, &key, &iv[..12], b"aad")
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        // A256CBC-HS512 takes a 64-byte key, half for HMAC and half for AES.
+        let cbc_key: Vec<u8> = (0..64).collect();
+        let ciphertext =
+            rust::aes256cbchs512::encrypt(b"data", &cbc_key, &iv, b"aad").expect("encrypt failed");
+        let plaintext = native::aes256cbchs512::decrypt(&ciphertext, &cbc_key, &iv, b"aad")
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let ciphertext = native::aes256cbchs512::encrypt(b"data", &cbc_key, &iv, b"aad")
+            .expect("encrypt failed");
+        let plaintext = rust::aes256cbchs512::decrypt(&ciphertext, &cbc_key, &iv, b"aad")
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let wrapped_key = rust::aes256kw::wrap(&iv, &key).expect("wrap failed");
+        let unwrapped_key = native::aes256kw::unwrap(&wrapped_key, &key).expect("unwrap failed");
+        assert_eq!(unwrapped_key, iv);
     }
 }
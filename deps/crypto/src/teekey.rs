@@ -0,0 +1,335 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! APIs for the asymmetric keys, i.e. the TEE keys used in KBS Attestation
+//! Protocol
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+#[cfg(feature = "openssl")]
+use crate::native::{ec, rsa};
+
+#[cfg(all(feature = "rust-crypto", not(feature = "openssl")))]
+use crate::rust::{ec, rsa};
+
+/// Definations of different Padding mode for encryption. Refer to
+/// <https://datatracker.ietf.org/doc/html/rfc7518#section-4.1> for
+/// more information.
+#[derive(EnumString, AsRefStr, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingMode {
+    /// OAEP using SHA-1
+    #[strum(serialize = "RSA-OAEP")]
+    OAEP,
+
+    /// OAEP using SHA-256
+    #[strum(serialize = "RSA-OAEP-256")]
+    OAEP256,
+
+    #[strum(serialize = "RSA1_5")]
+    PKCS1v15,
+}
+
+/// A 2048-bit RSA key pair generated by the enabled implementation.
+#[derive(Debug, Clone)]
+pub struct RSAKeyPair {
+    inner: rsa::RSAKeyPair,
+}
+
+impl RSAKeyPair {
+    pub fn new() -> Result<RSAKeyPair> {
+        Ok(RSAKeyPair {
+            inner: rsa::RSAKeyPair::new()?,
+        })
+    }
+
+    pub fn public_key(&self) -> RSAPublicKey {
+        RSAPublicKey {
+            n: self.inner.n(),
+            e: self.inner.e(),
+        }
+    }
+
+    /// Decrypt the given `cipher_text`. A corrupted ciphertext fails with
+    /// [`crate::Error::DecryptionFailed`].
+    pub fn decrypt(&self, mode: PaddingMode, cipher_text: Vec<u8>) -> Result<Vec<u8>> {
+        self.inner.decrypt(mode, cipher_text)
+    }
+}
+
+/// An RSA public key given by its components in big-endian bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RSAPublicKey {
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
+
+impl RSAPublicKey {
+    /// Create the public key of modulus `n` and public exponent `e`. They
+    /// are checked when encrypting.
+    pub fn new(n: Vec<u8>, e: Vec<u8>) -> Self {
+        Self { n, e }
+    }
+
+    /// The modulus in big-endian bytes
+    pub fn n(&self) -> &[u8] {
+        &self.n
+    }
+
+    /// The public exponent in big-endian bytes
+    pub fn e(&self) -> &[u8] {
+        &self.e
+    }
+
+    pub fn encrypt(&self, mode: PaddingMode, plaintext: &[u8]) -> Result<Vec<u8>> {
+        rsa::encrypt(&self.n, &self.e, mode, plaintext)
+    }
+}
+
+/// Curves of the EC keys, named as in
+/// <https://datatracker.ietf.org/doc/html/rfc7518#section-6.2.1.1>.
+#[derive(EnumString, AsRefStr, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    #[strum(serialize = "P-256")]
+    P256,
+
+    #[strum(serialize = "P-384")]
+    P384,
+}
+
+impl Curve {
+    /// Length in bytes of each coordinate of the points
+    pub fn coordinate_length(&self) -> usize {
+        match self {
+            Curve::P256 => 32,
+            Curve::P384 => 48,
+        }
+    }
+}
+
+/// An EC key pair generated by the enabled implementation.
+#[derive(Clone)]
+pub struct ECKeyPair {
+    inner: ec::ECKeyPair,
+    public_key: ECPublicKey,
+}
+
+impl ECKeyPair {
+    pub fn new(curve: Curve) -> Result<ECKeyPair> {
+        let inner = ec::ECKeyPair::new(curve)?;
+        let (x, y) = inner.coordinates()?;
+        Ok(ECKeyPair {
+            inner,
+            public_key: ECPublicKey { curve, x, y },
+        })
+    }
+
+    pub fn public_key(&self) -> &ECPublicKey {
+        &self.public_key
+    }
+
+    /// Agree the shared secret `Z` with the `peer` public key by ECDH. The
+    /// peer must be on the same curve.
+    pub fn derive(&self, peer: &ECPublicKey) -> Result<Zeroizing<Vec<u8>>> {
+        if peer.curve != self.public_key.curve {
+            bail!(
+                "EC public key on {} given, but {} expected",
+                peer.curve.as_ref(),
+                self.public_key.curve.as_ref()
+            );
+        }
+
+        self.inner.derive(&peer.x, &peer.y)
+    }
+}
+
+/// An EC public key given by the coordinates in big-endian bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ECPublicKey {
+    curve: Curve,
+    x: Vec<u8>,
+    y: Vec<u8>,
+}
+
+impl ECPublicKey {
+    /// Create the public key of coordinates `x` and `y` on the `curve`. The
+    /// point is checked when deriving the shared secret.
+    pub fn new(curve: Curve, x: Vec<u8>, y: Vec<u8>) -> Self {
+        Self { curve, x, y }
+    }
+
+    pub fn curve(&self) -> Curve {
+        self.curve
+    }
+
+    pub fn x(&self) -> &[u8] {
+        &self.x
+    }
+
+    pub fn y(&self) -> &[u8] {
+        &self.y
+    }
+}
+
+/// Derive a key of `key_length` bytes from the shared secret `z` agreed by
+/// ECDH-ES, with the Concat KDF of
+/// <https://datatracker.ietf.org/doc/html/rfc7518#section-4.6.2>.
+/// `algorithm_id` is the `enc` of the JWE for the direct key agreement, or
+/// the `alg` when the derived key wraps the content key.
+pub fn concat_kdf(
+    z: &[u8],
+    algorithm_id: &str,
+    apu: &[u8],
+    apv: &[u8],
+    key_length: usize,
+) -> Zeroizing<Vec<u8>> {
+    let mut other_info = Vec::new();
+    for field in [algorithm_id.as_bytes(), apu, apv] {
+        other_info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        other_info.extend_from_slice(field);
+    }
+    other_info.extend_from_slice(&((key_length * 8) as u32).to_be_bytes());
+
+    let mut key = Zeroizing::new(Vec::with_capacity(key_length));
+    let mut counter = 1u32;
+    while key.len() < key_length {
+        let digest = Sha256::new()
+            .chain_update(counter.to_be_bytes())
+            .chain_update(z)
+            .chain_update(&other_info)
+            .finalize();
+        let length = (key_length - key.len()).min(digest.len());
+        key.extend_from_slice(&digest[..length]);
+        counter += 1;
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::Error;
+
+    use super::{concat_kdf, Curve, ECKeyPair, PaddingMode, RSAKeyPair};
+
+    #[rstest]
+    #[case(PaddingMode::OAEP)]
+    #[case(PaddingMode::OAEP256)]
+    #[case(PaddingMode::PKCS1v15)]
+    fn en_decrypt(#[case] mode: PaddingMode) {
+        let keypair = RSAKeyPair::new().expect("generate key pair failed");
+        let ciphertext = keypair
+            .public_key()
+            .encrypt(mode, b"plaintext")
+            .expect("encryption failed");
+        let plaintext = keypair
+            .decrypt(mode, ciphertext)
+            .expect("decryption failed");
+        assert_eq!(plaintext, b"plaintext");
+    }
+
+    #[test]
+    fn wrong_padding() {
+        let keypair = RSAKeyPair::new().expect("generate key pair failed");
+        let ciphertext = keypair
+            .public_key()
+            .encrypt(PaddingMode::OAEP, b"plaintext")
+            .expect("encryption failed");
+        let err = keypair
+            .decrypt(PaddingMode::PKCS1v15, ciphertext)
+            .expect_err("decryption should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DecryptionFailed(_))
+        ));
+    }
+
+    #[rstest]
+    #[case(Curve::P256)]
+    #[case(Curve::P384)]
+    fn ecdh(#[case] curve: Curve) {
+        let alice = ECKeyPair::new(curve).expect("generate key pair failed");
+        let bob = ECKeyPair::new(curve).expect("generate key pair failed");
+        assert_eq!(alice.public_key().x().len(), curve.coordinate_length());
+        let z = alice.derive(bob.public_key()).expect("derive failed");
+        assert_eq!(z.len(), curve.coordinate_length());
+        assert_eq!(z, bob.derive(alice.public_key()).expect("derive failed"));
+
+        let other_curve = match curve {
+            Curve::P256 => Curve::P384,
+            Curve::P384 => Curve::P256,
+        };
+        let other = ECKeyPair::new(other_curve).expect("generate key pair failed");
+        alice
+            .derive(other.public_key())
+            .expect_err("derive should fail");
+    }
+
+    /// The example of Appendix C of RFC 7518
+    #[test]
+    fn concat_kdf_example() {
+        let z = [
+            158, 86, 217, 29, 129, 113, 53, 211, 114, 131, 66, 131, 191, 132, 38, 156, 251, 49,
+            110, 163, 218, 128, 106, 72, 246, 218, 167, 121, 140, 254, 144, 196,
+        ];
+        let key = concat_kdf(&z, "A128GCM", b"Alice", b"Bob", 16);
+        assert_eq!(
+            *key,
+            [86, 170, 141, 234, 248, 35, 109, 32, 92, 34, 40, 205, 113, 167, 16, 26]
+        );
+    }
+
+    /// The ciphertexts of either implementation are decrypted by the other.
+    #[cfg(all(feature = "rust-crypto", feature = "openssl"))]
+    #[rstest]
+    #[case(PaddingMode::OAEP)]
+    #[case(PaddingMode::OAEP256)]
+    #[case(PaddingMode::PKCS1v15)]
+    fn cross_implementations(#[case] mode: PaddingMode) {
+        use crate::{native, rust};
+
+        let rust_key = rust::rsa::RSAKeyPair::new().expect("generate key pair failed");
+        let ciphertext = native::rsa::encrypt(&rust_key.n(), &rust_key.e(), mode, b"to rust")
+            .expect("encryption failed");
+        let plaintext = rust_key
+            .decrypt(mode, ciphertext)
+            .expect("decryption failed");
+        assert_eq!(plaintext, b"to rust");
+
+        let native_key = native::rsa::RSAKeyPair::new().expect("generate key pair failed");
+        let ciphertext = rust::rsa::encrypt(&native_key.n(), &native_key.e(), mode, b"to openssl")
+            .expect("encryption failed");
+        let plaintext = native_key
+            .decrypt(mode, ciphertext)
+            .expect("decryption failed");
+        assert_eq!(plaintext, b"to openssl");
+    }
+
+    /// Both implementations agree the same shared secret.
+    #[cfg(all(feature = "rust-crypto", feature = "openssl"))]
+    #[rstest]
+    #[case(Curve::P256)]
+    #[case(Curve::P384)]
+    fn cross_implementations_ecdh(#[case] curve: Curve) {
+        use crate::{native, rust};
+
+        let rust_key = rust::ec::ECKeyPair::new(curve).expect("generate key pair failed");
+        let native_key = native::ec::ECKeyPair::new(curve).expect("generate key pair failed");
+        let (rust_x, rust_y) = rust_key.coordinates().expect("get coordinates failed");
+        let (native_x, native_y) = native_key.coordinates().expect("get coordinates failed");
+        assert_eq!(native_x.len(), curve.coordinate_length());
+
+        let z = rust_key
+            .derive(&native_x, &native_y)
+            .expect("derive failed");
+        assert_eq!(
+            z,
+            native_key.derive(&rust_x, &rust_y).expect("derive failed")
+        );
+    }
+}
@@ -0,0 +1,615 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # Mock KBS
+//!
+//! An in-process KBS for tests. It serves the `/kbs/v0/auth`,
+//! `/kbs/v0/attest` and `/kbs/v0/resource` paths of the KBS protocol on a
+//! random local port, like a real KBS:
+//! - The TEE key algorithm is selected from those offered in the
+//!   `extra-params` of the auth request, see
+//!   [`MockKbs::set_tee_key_algorithms`].
+//! - The evidence of [`Tee::Sample`] is verified against the nonce and the
+//!   submitted TEE public key. Other TEEs are rejected.
+//! - The attestation token is a JWT, which must be given as bearer token
+//!   when getting resources. Opaque tokens can be issued instead, see
+//!   [`MockKbs::set_opaque_tokens`].
+//! - Resources are encrypted to the TEE public key as JWEs of `A256GCM` or
+//!   `A256CBC-HS512`, whose content key is encrypted with `RSA1_5` or
+//!   `RSA-OAEP-256`, or agreed by `ECDH-ES+A256KW` or `ECDH-ES`. Without
+//!   the negotiation, the responses are in the legacy format instead, see
+//!   [`MockKbs::set_tee_key_algorithms`].
+//!
+//! Besides, tests can script the status code of the following responses of
+//! each path by [`MockKbs::fail_next`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use crypto::{
+    jwe::{ContentEncryption, EncryptionKey, Jwe, KeyAlgorithm},
+    Curve, ECPublicKey, PaddingMode, RSAPublicKey, WrapType,
+};
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use jwt_simple::prelude::*;
+use kbs_types::{Challenge, ErrorInformation, Tee};
+use log::debug;
+use rand::Rng;
+use sha2::{Digest, Sha384};
+use tokio::sync::oneshot;
+use zeroize::Zeroizing;
+
+/// Prefix of all the paths of the KBS protocol
+const KBS_URL_PREFIX: &str = "/kbs/v0";
+
+/// Name of the cookie to track the session between `auth` and `attest`
+const SESSION_COOKIE: &str = "kbs-session-id";
+
+/// Default lifetime of the attestation tokens in seconds
+const DEFAULT_TOKEN_LIFETIME_SEC: u64 = 300;
+
+/// Prefix of the opaque attestation tokens, followed by the session id
+const OPAQUE_TOKEN_PREFIX: &str = "opaque-";
+
+/// The TEE key algorithms supported by default
+const TEE_KEY_ALGORITHMS: [&str; 4] = ["EC-P256", "EC-P384", "RSA-OAEP-256", "RSA1_5"];
+
+/// Paths served by the mock KBS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Auth,
+    Attest,
+    Resource,
+}
+
+/// The sample evidence, which is the same format as the one generated by
+/// the sample evidence provider.
+#[derive(Deserialize)]
+struct SampleQuote {
+    report_data: String,
+}
+
+/// The TEE public key in JWK format
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kty")]
+enum TeePubKey {
+    #[serde(rename = "RSA")]
+    Rsa { alg: String, n: String, e: String },
+    #[serde(rename = "EC")]
+    Ec { crv: String, x: String, y: String },
+}
+
+#[derive(Deserialize)]
+struct Attestation {
+    #[serde(rename = "tee-pubkey")]
+    tee_pubkey: TeePubKey,
+    #[serde(rename = "tee-evidence")]
+    tee_evidence: String,
+}
+
+#[derive(Default)]
+struct Session {
+    nonce: String,
+    tee_key_algorithm: String,
+    tee_pubkey: Option<TeePubKey>,
+}
+
+#[derive(Default)]
+struct State {
+    resources: HashMap<String, Vec<u8>>,
+    sessions: HashMap<String, Session>,
+    scripted: HashMap<Endpoint, VecDeque<StatusCode>>,
+    requests: HashMap<Endpoint, usize>,
+    token_lifetime: u64,
+    opaque_tokens: bool,
+    tee_key_algorithms: Option<Vec<String>>,
+    direct_key_agreement: bool,
+    content_encryption: Option<ContentEncryption>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    token_key: HS256Key,
+}
+
+/// A KBS running in background until dropped.
+pub struct MockKbs {
+    addr: SocketAddr,
+    inner: Arc<Inner>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockKbs {
+    /// Start a mock KBS listening on a random local port. Must be called
+    /// inside a tokio runtime.
+    pub fn start() -> Result<Self> {
+        let inner = Arc::new(Inner {
+            state: Mutex::new(State {
+                token_lifetime: DEFAULT_TOKEN_LIFETIME_SEC,
+                tee_key_algorithms: Some(TEE_KEY_ALGORITHMS.map(String::from).to_vec()),
+                ..Default::default()
+            }),
+            token_key: HS256Key::generate(),
+        });
+
+        let service_inner = inner.clone();
+        let make_service = make_service_fn(move |_| {
+            let inner = service_inner.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let inner = inner.clone();
+                    async move { Ok::<_, Infallible>(inner.handle(req).await) }
+                }))
+            }
+        });
+
+        let (shutdown, rx) = oneshot::channel();
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .context("bind mock KBS")?
+            .serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = rx.await;
+        }));
+
+        Ok(Self {
+            addr,
+            inner,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /// URL of the mock KBS, e.g. `http://127.0.0.1:12345`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Set the resource of `path`, which is `<repository>/<type>/<tag>`.
+    pub fn set_resource(&self, path: &str, content: Vec<u8>) {
+        self.inner
+            .state()
+            .resources
+            .insert(path.trim_start_matches('/').to_string(), content);
+    }
+
+    /// Let the next request to `endpoint` fail with `status`. Multiple calls
+    /// are queued and consumed by the following requests in order.
+    pub fn fail_next(&self, endpoint: Endpoint, status: StatusCode) {
+        self.inner
+            .state()
+            .scripted
+            .entry(endpoint)
+            .or_default()
+            .push_back(status);
+    }
+
+    /// Set the lifetime in seconds of the attestation tokens issued later.
+    pub fn set_token_lifetime(&self, seconds: u64) {
+        self.inner.state().token_lifetime = seconds;
+    }
+
+    /// Issue attestation tokens which are not JWTs, like some KBSes do.
+    /// They never expire.
+    pub fn set_opaque_tokens(&self, opaque: bool) {
+        self.inner.state().opaque_tokens = opaque;
+    }
+
+    /// Set the TEE key algorithms supported by the KBS, which selects the
+    /// first one offered by the client. With `None`, the KBS does not
+    /// negotiate the algorithm, like the legacy KBSes, and only supports
+    /// `RSA1_5` with the legacy response format. All the algorithms are
+    /// supported by default.
+    pub fn set_tee_key_algorithms(&self, algorithms: Option<&[&str]>) {
+        self.inner.state().tee_key_algorithms =
+            algorithms.map(|algorithms| algorithms.iter().map(|a| a.to_string()).collect());
+    }
+
+    /// Let the responses to the EC TEE keys use the key agreed by `ECDH-ES`
+    /// as the content key, instead of wrapping the content key with it by
+    /// `ECDH-ES+A256KW`.
+    pub fn set_direct_key_agreement(&self, direct: bool) {
+        self.inner.state().direct_key_agreement = direct;
+    }
+
+    /// Set the `enc` of the responses, which is `A256GCM` by default.
+    pub fn set_content_encryption(&self, enc: ContentEncryption) {
+        self.inner.state().content_encryption = Some(enc);
+    }
+
+    /// Number of the requests to `endpoint` received so far, including the
+    /// failed ones.
+    pub fn requests(&self, endpoint: Endpoint) -> usize {
+        self.inner
+            .state()
+            .requests
+            .get(&endpoint)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for MockKbs {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+impl Inner {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("mock KBS state poisoned")
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let path = req.uri().path().to_string();
+        let Some(path) = path.strip_prefix(KBS_URL_PREFIX) else {
+            return error_response(StatusCode::NOT_FOUND, "UnknownPath", &path);
+        };
+
+        let endpoint = match (req.method(), path) {
+            (&Method::POST, "/auth") => Endpoint::Auth,
+            (&Method::POST, "/attest") => Endpoint::Attest,
+            (&Method::GET, p) if p.starts_with("/resource/") => Endpoint::Resource,
+            _ => return error_response(StatusCode::NOT_FOUND, "UnknownPath", path),
+        };
+        debug!("mock KBS got request {} {path}", req.method());
+
+        let scripted = {
+            let mut state = self.state();
+            *state.requests.entry(endpoint).or_default() += 1;
+            state
+                .scripted
+                .get_mut(&endpoint)
+                .and_then(|queue| queue.pop_front())
+        };
+        if let Some(status) = scripted {
+            return error_response(status, "Scripted", "scripted failure");
+        }
+
+        let res = match endpoint {
+            Endpoint::Auth => self.auth(req).await,
+            Endpoint::Attest => self.attest(req).await,
+            Endpoint::Resource => {
+                let resource_path = path["/resource/".len()..].to_string();
+                self.resource(req, &resource_path)
+            }
+        };
+
+        res.unwrap_or_else(|(status, detail)| error_response(status, "MockKbsError", &detail))
+    }
+
+    async fn auth(&self, req: Request<Body>) -> HandlerResult {
+        let request: kbs_types::Request = parse_body(req).await?;
+        if request.tee != Tee::Sample {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                format!("unsupported TEE {:?}", request.tee),
+            ));
+        }
+
+        let offered: Vec<String> = serde_json::from_str::<serde_json::Value>(&request.extra_params)
+            .ok()
+            .and_then(|params| serde_json::from_value(params["tee-key-algorithms"].clone()).ok())
+            .unwrap_or_default();
+        let (tee_key_algorithm, extra_params) = match &self.state().tee_key_algorithms {
+            Some(supported) => {
+                let algorithm = offered
+                    .into_iter()
+                    .find(|a| supported.contains(a))
+                    .ok_or_else(|| {
+                        (
+                            StatusCode::BAD_REQUEST,
+                            "no supported TEE key algorithm offered".to_string(),
+                        )
+                    })?;
+                let extra_params = serde_json::json!({ "tee-key-algorithm": algorithm });
+                (algorithm, extra_params.to_string())
+            }
+            None => ("RSA1_5".to_string(), String::new()),
+        };
+
+        let nonce = base64::engine::general_purpose::STANDARD.encode(random_bytes());
+        let session_id = hex(&random_bytes());
+        self.state().sessions.insert(
+            session_id.clone(),
+            Session {
+                nonce: nonce.clone(),
+                tee_key_algorithm,
+                ..Default::default()
+            },
+        );
+
+        let challenge = Challenge {
+            nonce,
+            extra_params,
+        };
+        let mut res = json_response(&challenge)?;
+        let cookie = format!("{SESSION_COOKIE}={session_id}; Path={KBS_URL_PREFIX}")
+            .parse()
+            .map_err(internal_error)?;
+        res.headers_mut().insert(header::SET_COOKIE, cookie);
+        Ok(res)
+    }
+
+    async fn attest(&self, req: Request<Body>) -> HandlerResult {
+        let session_id = session_id(&req)
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "no KBS session".to_string()))?;
+        let attestation: Attestation = parse_body(req).await?;
+
+        let (nonce, tee_key_algorithm) = self
+            .state()
+            .sessions
+            .get(&session_id)
+            .map(|session| (session.nonce.clone(), session.tee_key_algorithm.clone()))
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "unknown KBS session".to_string()))?;
+        check_tee_pubkey(&tee_key_algorithm, &attestation.tee_pubkey)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+        verify_sample_evidence(&nonce, &attestation)
+            .map_err(|e| (StatusCode::UNAUTHORIZED, format!("{e:#}")))?;
+
+        let (token_lifetime, opaque_tokens) = {
+            let mut state = self.state();
+            if let Some(session) = state.sessions.get_mut(&session_id) {
+                session.tee_pubkey = Some(attestation.tee_pubkey);
+            }
+            (state.token_lifetime, state.opaque_tokens)
+        };
+
+        if opaque_tokens {
+            let token = format!("{OPAQUE_TOKEN_PREFIX}{session_id}");
+            return json_response(&serde_json::json!({ "token": token }));
+        }
+        let claims = Claims::create(Duration::from_secs(token_lifetime)).with_subject(session_id);
+        let token = self
+            .token_key
+            .authenticate(claims)
+            .map_err(internal_error)?;
+        json_response(&serde_json::json!({ "token": token }))
+    }
+
+    fn resource(&self, req: Request<Body>, path: &str) -> HandlerResult {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "no attestation token".to_string()))?;
+
+        let session_id = match token.strip_prefix(OPAQUE_TOKEN_PREFIX) {
+            Some(session_id) => Some(session_id.to_string()),
+            None => {
+                let options = VerificationOptions {
+                    time_tolerance: Some(Duration::from_secs(0)),
+                    ..Default::default()
+                };
+                self.token_key
+                    .verify_token::<NoCustomClaims>(token, Some(options))
+                    .map_err(|e| (StatusCode::UNAUTHORIZED, format!("invalid token: {e}")))?
+                    .subject
+            }
+        };
+
+        let (tee_pubkey, resource, legacy, direct_key_agreement, enc) = {
+            let state = self.state();
+            let tee_pubkey = session_id
+                .and_then(|id| state.sessions.get(&id))
+                .and_then(|session| session.tee_pubkey.clone())
+                .ok_or_else(|| (StatusCode::UNAUTHORIZED, "session not attested".to_string()))?;
+            let resource = state
+                .resources
+                .get(path)
+                .cloned()
+                .ok_or_else(|| (StatusCode::NOT_FOUND, format!("resource {path} not found")))?;
+            (
+                tee_pubkey,
+                resource,
+                state.tee_key_algorithms.is_none(),
+                state.direct_key_agreement,
+                state
+                    .content_encryption
+                    .unwrap_or(ContentEncryption::A256Gcm),
+            )
+        };
+
+        let response = match legacy {
+            true => encrypt_legacy_response(&tee_pubkey, resource),
+            false => encrypt_response(&tee_pubkey, resource, direct_key_agreement, enc),
+        }
+        .map_err(internal_error)?;
+        json_response(&response)
+    }
+}
+
+type HandlerResult = std::result::Result<Response<Body>, (StatusCode, String)>;
+
+fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+async fn parse_body<T: serde::de::DeserializeOwned>(
+    req: Request<Body>,
+) -> std::result::Result<T, (StatusCode, String)> {
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(internal_error)?;
+    serde_json::from_slice(&body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+fn json_response<T: Serialize>(body: &T) -> HandlerResult {
+    let body = serde_json::to_vec(body).map_err(internal_error)?;
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .map_err(internal_error)
+}
+
+fn error_response(status: StatusCode, error_type: &str, detail: &str) -> Response<Body> {
+    let info = ErrorInformation {
+        error_type: error_type.into(),
+        detail: detail.into(),
+    };
+    let mut res = Response::new(Body::from(
+        serde_json::to_vec(&info).expect("serialize error information"),
+    ));
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    res
+}
+
+fn session_id(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(SESSION_COOKIE)
+                .and_then(|c| c.strip_prefix('='))
+                .map(str::to_string)
+        })
+}
+
+/// Check that the report data of the sample evidence binds the nonce and the
+/// TEE public key, in the same way as the KBS protocol client computes it.
+fn verify_sample_evidence(nonce: &str, attestation: &Attestation) -> Result<()> {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let evidence = engine
+        .decode(&attestation.tee_evidence)
+        .context("decode evidence")?;
+    let quote: SampleQuote = serde_json::from_slice(&evidence).context("parse sample evidence")?;
+
+    let materials = match &attestation.tee_pubkey {
+        TeePubKey::Rsa { n, e, .. } => [n, e],
+        TeePubKey::Ec { x, y, .. } => [x, y],
+    };
+    let mut hasher = Sha384::new();
+    hasher.update(nonce.as_bytes());
+    for material in materials {
+        hasher.update(material.as_bytes());
+    }
+    let expected = engine.encode(hasher.finalize());
+    if quote.report_data != expected {
+        bail!("report data of the evidence mismatches");
+    }
+
+    Ok(())
+}
+
+/// Check that the TEE public key is of the negotiated algorithm.
+fn check_tee_pubkey(algorithm: &str, tee_pubkey: &TeePubKey) -> Result<()> {
+    let matched = match tee_pubkey {
+        TeePubKey::Rsa { alg, .. } => alg == algorithm,
+        TeePubKey::Ec { crv, .. } => algorithm.strip_prefix("EC-") == Some(&crv.replace('-', "")),
+    };
+    if !matched {
+        bail!("TEE public key mismatches the algorithm {algorithm}");
+    }
+
+    Ok(())
+}
+
+/// Encrypt `resource` to the TEE public key as a JWE of `enc`. The content
+/// key is encrypted to the RSA keys, and is wrapped with or is the key
+/// agreed by ECDH-ES with the EC keys.
+fn encrypt_response(
+    tee_pubkey: &TeePubKey,
+    resource: Vec<u8>,
+    direct_key_agreement: bool,
+    enc: ContentEncryption,
+) -> Result<kbs_types::Response> {
+    let jwe = match tee_pubkey {
+        TeePubKey::Rsa { alg, n, e } => {
+            let alg: KeyAlgorithm = alg
+                .parse()
+                .map_err(|_| anyhow!("unsupported TEE public key algorithm {alg}"))?;
+            let engine = base64::engine::general_purpose::STANDARD;
+            let n = engine.decode(n).context("decode modulus")?;
+            let e = engine.decode(e).context("decode exponent")?;
+            let public_key = RSAPublicKey::new(n, e);
+            Jwe::encrypt(EncryptionKey::Rsa(&public_key), alg, enc, &resource)?
+        }
+        TeePubKey::Ec { crv, x, y } => {
+            let curve: Curve = crv
+                .parse()
+                .map_err(|_| anyhow!("unsupported curve {crv}"))?;
+            let decoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+            let public_key = ECPublicKey::new(
+                curve,
+                decoder.decode(x).context("decode x")?,
+                decoder.decode(y).context("decode y")?,
+            );
+            let alg = match direct_key_agreement {
+                true => KeyAlgorithm::EcdhEs,
+                false => KeyAlgorithm::EcdhEsA256Kw,
+            };
+            Jwe::encrypt(EncryptionKey::Ec(&public_key), alg, enc, &resource)?
+        }
+    };
+
+    Ok(kbs_types::Response {
+        protected: jwe.protected,
+        encrypted_key: jwe.encrypted_key,
+        iv: jwe.iv,
+        ciphertext: jwe.ciphertext,
+        tag: jwe.tag,
+    })
+}
+
+/// Encrypt `resource` like the KBSes not negotiating the TEE key algorithm,
+/// i.e. with an A256GCM key encrypted to the RSA key by `RSA1_5`. The
+/// protected header is plain JSON, and the tag is appended to the
+/// ciphertext.
+fn encrypt_legacy_response(
+    tee_pubkey: &TeePubKey,
+    resource: Vec<u8>,
+) -> Result<kbs_types::Response> {
+    let TeePubKey::Rsa { n, e, .. } = tee_pubkey else {
+        bail!("legacy KBS only supports RSA TEE public keys");
+    };
+    let engine = base64::engine::general_purpose::STANDARD;
+    let n = engine.decode(n).context("decode modulus")?;
+    let e = engine.decode(e).context("decode exponent")?;
+    let symkey = Zeroizing::new(random_bytes().to_vec());
+    let encrypted_key = RSAPublicKey::new(n, e).encrypt(PaddingMode::PKCS1v15, &symkey)?;
+
+    let mut iv = [0u8; 12];
+    rand::thread_rng().fill(&mut iv);
+    let ciphertext = crypto::encrypt(symkey, resource, iv.to_vec(), &[], WrapType::Aes256Gcm)?;
+
+    let encoder = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    Ok(kbs_types::Response {
+        protected: serde_json::json!({"alg": "RSA1_5", "enc": "A256GCM"}).to_string(),
+        encrypted_key: encoder.encode(encrypted_key),
+        iv: encoder.encode(iv),
+        ciphertext: encoder.encode(ciphertext),
+        tag: String::new(),
+    })
+}
+
+fn random_bytes() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes);
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -16,7 +16,7 @@ const RESOURCE_ID_ERROR_INFO: &str =
 const SCHEME: &str = "kbs";
 
 /// Resource Id document <https://github.com/confidential-containers/attestation-agent/blob/main/docs/KBS_URI.md>
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ResourceUri {
     pub kbs_addr: String,
     pub repository: String,
@@ -187,7 +187,7 @@ mod tests {
         };
 
         let url = url::Url::try_from(TEST_URL).expect("failed to parse url");
-        let try_into: url::Url = rid.clone().try_into().expect("failed to try into url");
+        let try_into: url::Url = rid.clone().into();
         assert_eq!(url, try_into);
 
         let rid_try_from = ResourceUri::try_from(url).expect("failed to try from url");
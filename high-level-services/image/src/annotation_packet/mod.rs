@@ -3,14 +3,23 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use anyhow::Result;
+use base64::Engine;
+use crypto::WrapType;
+use kbs_client::Client as KbsClient;
+use rand::Rng;
+use resource_uri::ResourceUri;
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::Error;
 
 use self::{v1::AnnotationPacketV1, v2::AnnotationPacketV2};
 
 pub mod v1;
 pub mod v2;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum AnnotationPacket {
     /// Legacy format of AnnotationPacket, aiming to be decrypted by KBS
@@ -20,3 +29,117 @@ pub enum AnnotationPacket {
     /// but also different kinds of KMS.
     V2(AnnotationPacketV2),
 }
+
+/// Format of the AnnotationPacket to generate when wrapping a LEK.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketVersion {
+    /// [`AnnotationPacketV1`], for legacy consumers. Only KBS is supported.
+    V1,
+
+    /// [`AnnotationPacketV2`]
+    #[default]
+    V2,
+}
+
+/// Parameters to wrap a LEK into an [`AnnotationPacket`], e.g.
+///
+/// ```json
+/// {
+///     "provider": "kbs",
+///     "kid": "kbs:///default/key/1",
+///     "packet": "v2",
+///     "wrap_type": "XC20P"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WrapParameters {
+    /// Provider of the KEK, s.t. `kbs` or the name of a KMS driver
+    pub provider: String,
+
+    /// Key ID of the KEK. If provider is `kbs`, this field should be a
+    /// KBS Resource URI.
+    pub kid: String,
+
+    /// Format of the AnnotationPacket to generate
+    #[serde(default)]
+    pub packet: PacketVersion,
+
+    /// Wrap type to encrypt the LEK with the KEK from `kbs`, e.g. `C20P`.
+    /// `A256GCM` by default. The KMS providers encrypt the LEK in their own
+    /// way, so it must not be given with them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrap_type: Option<String>,
+}
+
+impl WrapParameters {
+    /// The wrap type to encrypt the LEK with the KEK from `kbs`.
+    pub fn wrap_type(&self) -> Result<WrapType> {
+        match &self.wrap_type {
+            Some(wrap_type) => WrapType::try_from(&wrap_type[..]).map_err(|_| {
+                Error::InvalidWrapParameters(format!("unsupported wrap type {wrap_type}")).into()
+            }),
+            None => Ok(WrapType::Aes256Gcm),
+        }
+    }
+}
+
+/// Decode the base64 encoded `field` of an AnnotationPacket.
+fn decode(field: &str, value: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| Error::InvalidAnnotationPacket(format!("decode `{field}`: {e}")).into())
+}
+
+/// Parse the `wrap_type` field of an AnnotationPacket.
+fn parse_wrap_type(wrap_type: &str) -> Result<WrapType> {
+    WrapType::try_from(wrap_type).map_err(|_| {
+        Error::InvalidAnnotationPacket(format!("unsupported wrap type {wrap_type}")).into()
+    })
+}
+
+/// The fields of an AnnotationPacket of a LEK wrapped with the KEK from the
+/// KBS, base64 encoded.
+struct KbsWrappedKey {
+    wrapped_data: String,
+    iv: String,
+    wrap_type: String,
+}
+
+/// Wrap the `lek` with the KEK of `kid` from the KBS, encrypting it with
+/// `wrap_type` and a random IV.
+async fn wrap_key_with_kbs(
+    lek: &[u8],
+    kid: ResourceUri,
+    wrap_type: WrapType,
+    kbs_client: &KbsClient,
+) -> Result<KbsWrappedKey> {
+    let key = Zeroizing::new(kbs_client.get_resource(kid).await?);
+
+    let mut iv = vec![0u8; wrap_type.iv_length()];
+    rand::thread_rng().fill(&mut iv[..]);
+    let wrapped_data = crypto::encrypt(key, lek.to_vec(), iv.clone(), &[], wrap_type)?;
+
+    let encoder = base64::engine::general_purpose::STANDARD;
+    Ok(KbsWrappedKey {
+        wrapped_data: encoder.encode(wrapped_data),
+        iv: encoder.encode(iv),
+        wrap_type: wrap_type.as_ref().to_string(),
+    })
+}
+
+/// Unwrap the LEK of the `wrapped` fields of an AnnotationPacket with the
+/// KEK of `kid` from the KBS.
+async fn unwrap_key_with_kbs(
+    wrapped: &KbsWrappedKey,
+    kid: ResourceUri,
+    kbs_client: &KbsClient,
+) -> Result<Vec<u8>> {
+    let key = Zeroizing::new(kbs_client.get_resource(kid).await?);
+
+    let iv = decode("iv", &wrapped.iv)?;
+    let wrap_type = parse_wrap_type(&wrapped.wrap_type)?;
+    let wrapped_data = decode("wrapped_data", &wrapped.wrapped_data)?;
+
+    crypto::decrypt(key, wrapped_data, iv, &[], wrap_type)
+}
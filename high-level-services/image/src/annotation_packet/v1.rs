@@ -6,14 +6,13 @@
 use std::sync::Arc;
 
 use anyhow::*;
-use base64::Engine;
 use crypto::WrapType;
 use kbs_client::Client as KbsClient;
 use serde::{Deserialize, Serialize};
 
 use resource_uri::ResourceUri;
-use tokio::sync::Mutex;
-use zeroize::Zeroizing;
+
+use super::{unwrap_key_with_kbs, wrap_key_with_kbs, KbsWrappedKey};
 
 /// `AnnotationPacketV1` is what a encrypted image layer's
 /// `org.opencontainers.image.enc.keys.provider.attestation-agent`
@@ -38,19 +37,66 @@ pub struct AnnotationPacketV1 {
 }
 
 impl AnnotationPacketV1 {
-    pub async fn unwrap_key_with(self, kbs_client: Arc<Mutex<KbsClient>>) -> Result<Vec<u8>> {
-        let key = {
-            let mut client = kbs_client.lock().await;
-            Zeroizing::new(client.get_resource(self.kid).await?)
+    pub async fn unwrap_key_with(self, kbs_client: Arc<KbsClient>) -> Result<Vec<u8>> {
+        let wrapped = KbsWrappedKey {
+            wrapped_data: self.wrapped_data,
+            iv: self.iv,
+            wrap_type: self.wrap_type,
         };
+        unwrap_key_with_kbs(&wrapped, self.kid, &kbs_client).await
+    }
+
+    /// Wrap the `lek` with the KEK of `kid` from the KBS. The LEK is
+    /// encrypted with `wrap_type`.
+    pub async fn wrap_key_with(
+        lek: &[u8],
+        kid: ResourceUri,
+        wrap_type: WrapType,
+        kbs_client: Arc<KbsClient>,
+    ) -> Result<Self> {
+        let wrapped = wrap_key_with_kbs(lek, kid.clone(), wrap_type, &kbs_client).await?;
+        Ok(Self {
+            kid,
+            wrapped_data: wrapped.wrapped_data,
+            iv: wrapped.iv,
+            wrap_type: wrapped.wrap_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crypto::WrapType;
+    use kbs_client::{Client as KbsClient, SampleProvider};
+    use mock_kbs::MockKbs;
+    use resource_uri::ResourceUri;
+    use rstest::rstest;
+
+    use super::AnnotationPacketV1;
+
+    #[rstest]
+    #[case(WrapType::Aes256Gcm, "A256GCM")]
+    #[case(WrapType::ChaCha20Poly1305, "C20P")]
+    #[case(WrapType::XChaCha20Poly1305, "XC20P")]
+    #[case(WrapType::Aes256GcmSiv, "A256GCM-SIV")]
+    #[tokio::test]
+    async fn wrap_unwrap_with_kbs(#[case] wrap_type: WrapType, #[case] name: &str) {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_resource("default/key/1", vec![3u8; 32]);
+        let client = KbsClient::new(kbs.url(), Arc::new(SampleProvider), Duration::from_secs(5))
+            .expect("create kbs client failed");
+        let client = Arc::new(client);
 
-        let decoder = base64::engine::general_purpose::STANDARD;
-        let iv = decoder.decode(&self.iv).context("decode iv")?;
-        let wrap_type = WrapType::try_from(&self.wrap_type[..]).context("parse wrap type")?;
-        let wrapped_data = decoder
-            .decode(&self.wrapped_data)
-            .context("decode wrapped data")?;
+        let kid = ResourceUri::try_from("kbs:///default/key/1").expect("parse kid failed");
+        let lek = b"layer encryption key".to_vec();
+        let packet = AnnotationPacketV1::wrap_key_with(&lek, kid, wrap_type, client.clone())
+            .await
+            .expect("wrap failed");
+        assert_eq!(packet.wrap_type, name);
 
-        crypto::decrypt(key, wrapped_data, iv, wrap_type)
+        let unwrapped = packet.unwrap_key_with(client).await.expect("unwrap failed");
+        assert_eq!(unwrapped, lek);
     }
 }
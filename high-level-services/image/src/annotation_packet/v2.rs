@@ -12,8 +12,13 @@ use kbs_client::Client as KbsClient;
 use kms::KMS;
 use resource_uri::ResourceUri;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
-use zeroize::Zeroizing;
+
+use crate::Error;
+
+use super::{decode, unwrap_key_with_kbs, wrap_key_with_kbs, KbsWrappedKey};
+
+/// Version of the AnnotationPacketV2 generated by this crate
+pub const VERSION: &str = "0.1.0";
 
 /// New version format of AnnotationPacket
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -47,68 +52,185 @@ pub struct AnnotationPacketV2 {
 }
 
 pub enum Unwrapper {
-    Kms(Arc<Mutex<dyn KMS>>),
-    Kbs(Arc<Mutex<KbsClient>>),
+    Kms(Arc<dyn KMS>),
+    Kbs(Arc<KbsClient>),
 }
 
+/// The providers of the KEK used to wrap a LEK are the same as the ones
+/// used to unwrap it.
+pub type Wrapper = Unwrapper;
+
 impl AnnotationPacketV2 {
     pub async fn unwrap_key_with(self, unwrapper: Unwrapper) -> Result<Vec<u8>> {
         match unwrapper {
             Unwrapper::Kbs(kbs_client) => {
                 if self.provider != "kbs" {
-                    bail!(
+                    bail!(Error::InvalidAnnotationPacket(format!(
                         "The given provider is `kbs`, but the one of the KEK is {}",
                         self.provider
-                    );
+                    )));
                 }
 
-                let wrap_type = self.wrap_type.clone().ok_or_else(|| anyhow!("The KEK is provided by `kbs` but no `WrapType` is defined inside the AnnotationPacket"))?;
-                let iv = self.iv.clone().ok_or_else(|| anyhow!("The KEK is provided by `kbs` but no `iv` is defined inside the AnnotationPacket"))?;
+                let wrap_type = self.wrap_type.clone().ok_or_else(|| Error::InvalidAnnotationPacket("The KEK is provided by `kbs` but no `WrapType` is defined inside the AnnotationPacket".into()))?;
+                let iv = self.iv.clone().ok_or_else(|| Error::InvalidAnnotationPacket("The KEK is provided by `kbs` but no `iv` is defined inside the AnnotationPacket".into()))?;
 
-                let resource_uri = ResourceUri::try_from(&self.kid[..])
-                    .map_err(|e| anyhow!("cannot parse the kid into a KBS Resource URI: {e}"))?;
-                let key = {
-                    let mut client = kbs_client.lock().await;
-                    Zeroizing::new(client.get_resource(resource_uri).await?)
+                let resource_uri = ResourceUri::try_from(&self.kid[..]).map_err(|e| {
+                    Error::InvalidAnnotationPacket(format!(
+                        "cannot parse the kid into a KBS Resource URI: {e}"
+                    ))
+                })?;
+                let wrapped = KbsWrappedKey {
+                    wrapped_data: self.wrapped_data,
+                    iv,
+                    wrap_type,
                 };
-                let decoder = base64::engine::general_purpose::STANDARD;
-                let iv = decoder.decode(&iv).context("decode iv")?;
-                let wrap_type = WrapType::try_from(&wrap_type[..]).context("parse wrap type")?;
-                let wrapped_data = decoder
-                    .decode(&self.wrapped_data)
-                    .context("decode wrapped data")?;
-
-                crypto::decrypt(key, wrapped_data, iv, wrap_type)
+                unwrap_key_with_kbs(&wrapped, resource_uri, &kbs_client).await
             }
             Unwrapper::Kms(kms_client) => {
-                let decoder = base64::engine::general_purpose::STANDARD;
-                let wrapped_data = decoder
-                    .decode(&self.wrapped_data)
-                    .context("decode wrapped data")?;
+                let wrapped_data = decode("wrapped_data", &self.wrapped_data)?;
 
-                let mut client = kms_client.lock().await;
-                let driver_name = client.name();
+                let driver_name = kms_client.name();
                 if driver_name != self.provider {
-                    bail!("cannot decrypt the LEK, because given KEK provider is {driver_name}, but {} expected", self.provider);
+                    bail!(Error::InvalidAnnotationPacket(format!("cannot decrypt the LEK, because given KEK provider is {driver_name}, but {} expected", self.provider)));
                 }
 
-                client
+                kms_client
                     .decrypt(&wrapped_data, &self.kid, &self.annotations)
                     .await
             }
         }
     }
+
+    /// Wrap the `lek` with the KEK of `kid` provided by the `wrapper`. If
+    /// the KEK is provided by `kbs`, `kid` must be a [`ResourceUri`] and the
+    /// LEK is encrypted with `wrap_type`, which is not used by the KMSes.
+    pub async fn wrap_key_with(
+        lek: &[u8],
+        kid: String,
+        wrap_type: WrapType,
+        wrapper: Wrapper,
+    ) -> Result<Self> {
+        let encoder = base64::engine::general_purpose::STANDARD;
+        match wrapper {
+            Wrapper::Kbs(kbs_client) => {
+                let resource_uri = ResourceUri::try_from(&kid[..]).map_err(|e| {
+                    Error::InvalidWrapParameters(format!(
+                        "cannot parse the kid into a KBS Resource URI: {e}"
+                    ))
+                })?;
+                let wrapped = wrap_key_with_kbs(lek, resource_uri, wrap_type, &kbs_client).await?;
+
+                Ok(Self {
+                    version: VERSION.into(),
+                    kid,
+                    wrapped_data: wrapped.wrapped_data,
+                    provider: "kbs".into(),
+                    iv: Some(wrapped.iv),
+                    wrap_type: Some(wrapped.wrap_type),
+                    annotations: HashMap::new(),
+                })
+            }
+            Wrapper::Kms(kms_client) => {
+                let (wrapped_data, annotations) = kms_client.encrypt(lek, &kid).await?;
+
+                Ok(Self {
+                    version: VERSION.into(),
+                    kid,
+                    wrapped_data: encoder.encode(wrapped_data),
+                    provider: kms_client.name().to_string(),
+                    iv: None,
+                    wrap_type: None,
+                    annotations,
+                })
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, sync::Arc};
 
+    use anyhow::Result;
     use assert_json_diff::assert_json_eq;
+    use async_trait::async_trait;
+    use crypto::WrapType;
+    use kms::{Registry, KMS};
     use rstest::rstest;
     use serde_json::{json, Value};
 
-    use super::AnnotationPacketV2;
+    use super::{AnnotationPacketV2, Wrapper};
+
+    /// A KMS "encrypting" data by xor-ing it with the last byte of the key id
+    struct XorKms;
+
+    #[async_trait]
+    impl KMS for XorKms {
+        fn name(&self) -> &str {
+            "xor"
+        }
+
+        async fn encrypt(
+            &self,
+            data: &[u8],
+            keyid: &str,
+        ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+            let key = keyid.as_bytes()[keyid.len() - 1];
+            let annotations = [("keyid".into(), keyid.into())].into_iter().collect();
+            Ok((data.iter().map(|b| b ^ key).collect(), annotations))
+        }
+
+        async fn decrypt(
+            &self,
+            ciphertext: &[u8],
+            keyid: &str,
+            annotations: &HashMap<String, String>,
+        ) -> Result<Vec<u8>> {
+            assert_eq!(annotations["keyid"], keyid);
+            let key = keyid.as_bytes()[keyid.len() - 1];
+            Ok(ciphertext.iter().map(|b| b ^ key).collect())
+        }
+
+        async fn get_secret(
+            &self,
+            _name: &str,
+            _annotations: &HashMap<String, String>,
+        ) -> Result<Vec<u8>> {
+            Err(anyhow::anyhow!("xor kms has no secrets"))
+        }
+    }
+
+    #[rstest]
+    #[case("xor")]
+    #[case("local")]
+    #[tokio::test]
+    async fn wrap_unwrap_with_kms(#[case] provider: &str) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms: Arc<dyn KMS> = match provider {
+            "xor" => Arc::new(XorKms),
+            _ => Registry::builtin()
+                .new_client(provider, json!({ "dir": dir.path() }).as_object().unwrap())
+                .await
+                .expect("create kms failed"),
+        };
+        let lek = b"layer encryption key".to_vec();
+        let packet = AnnotationPacketV2::wrap_key_with(
+            &lek,
+            "key-1".into(),
+            WrapType::Aes256Gcm,
+            Wrapper::Kms(kms.clone()),
+        )
+        .await
+        .expect("wrap failed");
+        assert_eq!(packet.provider, provider);
+        assert_eq!(packet.kid, "key-1");
+
+        let unwrapped = packet
+            .unwrap_key_with(Wrapper::Kms(kms))
+            .await
+            .expect("unwrap failed");
+        assert_eq!(unwrapped, lek);
+    }
 
     #[rstest]
     #[case(json!({
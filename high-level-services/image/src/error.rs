@@ -0,0 +1,25 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Errors of wrapping and unwrapping the image layer keys
+
+use thiserror::Error;
+
+/// Failures caused by the requests to wrap or unwrap a LEK, rather than the
+/// KBS or KMS providing the KEK. They are returned inside [`anyhow::Error`],
+/// and can be found by downcasting it.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A field of the AnnotationPacket is malformed or missing.
+    #[error("invalid AnnotationPacket: {0}")]
+    InvalidAnnotationPacket(String),
+
+    #[error("invalid wrap parameters: {0}")]
+    InvalidWrapParameters(String),
+
+    /// No KBS or KMS of the provider of the KEK is available.
+    #[error("unknown KEK provider {0}")]
+    UnknownProvider(String),
+}
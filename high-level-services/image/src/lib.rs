@@ -6,3 +6,6 @@
 //! Implements ocicrypt's keyprovider gRPC api for Confidential Containers
 
 pub mod annotation_packet;
+
+pub mod error;
+pub use error::Error;
@@ -0,0 +1,22 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Errors of the sealed secrets
+
+use thiserror::Error;
+
+/// Failures caused by the sealed secrets themselves, rather than the KBS or
+/// KMS unsealing them. They are returned inside [`anyhow::Error`], and can be
+/// found by downcasting it.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A field of the secret is malformed or missing.
+    #[error("invalid secret: {0}")]
+    InvalidSecret(String),
+
+    /// No KBS or KMS of the provider of the secret is available.
+    #[error("unknown provider {0} of the secret")]
+    UnknownProvider(String),
+}
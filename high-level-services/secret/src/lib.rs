@@ -3,5 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+pub mod error;
+pub use error::Error;
+
 pub mod secret;
 pub mod unsealer;
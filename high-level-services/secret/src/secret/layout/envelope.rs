@@ -3,7 +3,10 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use anyhow::*;
 use base64::Engine;
@@ -13,9 +16,23 @@ use kms::KMS;
 use rand::Rng;
 use resource_uri::ResourceUri;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
 use zeroize::{Zeroize, Zeroizing};
 
+use crate::{
+    secret::{LEGACY_VERSION, VERSION},
+    Error,
+};
+
+/// Provider of the secrets sealed by a KBS
+const KBS_PROVIDER: &str = "kbs";
+
+/// Decode the base64 encoded `field` of a secret.
+fn decode(field: &str, value: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| Error::InvalidSecret(format!("decode `{field}`: {e}")).into())
+}
+
 /// An Envelope is a secret encrypted by digital envelope mechanism.
 /// It can be described as
 ///
@@ -45,18 +62,59 @@ pub struct Envelope {
     pub annotations: HashMap<String, String>,
 }
 
+/// The fields of an [`Envelope`] authenticated as the associated data of
+/// its ciphertexts, serialized into JSON. The annotations are sorted to be
+/// serialized deterministically.
+#[derive(Serialize)]
+struct AuthenticatedFields<'a> {
+    provider: &'a str,
+    key_id: &'a str,
+    wrap_type: WrapType,
+    annotations: BTreeMap<&'a str, &'a str>,
+}
+
 impl Envelope {
+    /// The associated data of the ciphertexts of this envelope inside a
+    /// secret of `version` and `provider`. It is empty for
+    /// [`LEGACY_VERSION`].
+    fn aad(&self, version: &str, provider: &str) -> Result<Vec<u8>> {
+        match version {
+            LEGACY_VERSION => Ok(Vec::new()),
+            VERSION => {
+                let fields = AuthenticatedFields {
+                    provider,
+                    key_id: &self.key_id,
+                    wrap_type: self.wrap_type,
+                    annotations: self
+                        .annotations
+                        .iter()
+                        .map(|(k, v)| (&k[..], &v[..]))
+                        .collect(),
+                };
+                serde_json::to_vec(&fields).context("serialize envelope fields failed")
+            }
+            _ => bail!(Error::InvalidSecret(format!(
+                "unsupported version {version} of an envelope secret"
+            ))),
+        }
+    }
+
     /// Unseal this envelope with the given kbs client, which means this envelope
-    /// must be sealed by kbs.
-    pub(crate) async fn unseal_with_kbs(&self, unsealer: Arc<Mutex<KbsClient>>) -> Result<Vec<u8>> {
-        let base64_decoder = base64::engine::general_purpose::STANDARD;
-        let enc_dek = base64_decoder.decode(&self.encrypted_key)?;
+    /// must be sealed by kbs. `version` and `provider` are of the secret.
+    pub(crate) async fn unseal_with_kbs(
+        &self,
+        version: &str,
+        provider: &str,
+        unsealer: Arc<KbsClient>,
+    ) -> Result<Vec<u8>> {
+        let aad = self.aad(version, provider)?;
+        let enc_dek = decode("encrypted_key", &self.encrypted_key)?;
         let datakey = {
             let key = {
-                let mut client = unsealer.lock().await;
-                let key_url = ResourceUri::try_from(&self.key_id[..])
-                    .map_err(|e| anyhow!("parse key id as resource uri failed: {e}"))?;
-                Zeroizing::new(client.get_resource(key_url).await?)
+                let key_url = ResourceUri::try_from(&self.key_id[..]).map_err(|e| {
+                    Error::InvalidSecret(format!("parse key id as resource uri failed: {e}"))
+                })?;
+                Zeroizing::new(unsealer.get_resource(key_url).await?)
             };
 
             // If KBS is used as envelope secret, a IV field must be inside the annotations.
@@ -64,127 +122,141 @@ impl Envelope {
             //
             // Dek_enc = Enc(Key_{kbs}, Dek, A256GCM)
             // Data_enc = Enc(Dek, Data, WrapType)
-            let iv = self
-                .annotations
-                .get("iv")
-                .ok_or_else(|| anyhow!("No `iv` field given in a KBS-sealed envelope secret"))
-                .and_then(|c| base64_decoder.decode(c).map_err(anyhow::Error::from))?;
-            Zeroizing::new(crypto::decrypt(key, enc_dek, iv, WrapType::Aes256Gcm)?)
+            let iv = self.annotations.get("iv").ok_or_else(|| {
+                Error::InvalidSecret("No `iv` field given in a KBS-sealed envelope secret".into())
+            })?;
+            let iv = decode("iv", iv)?;
+            Zeroizing::new(crypto::decrypt(
+                key,
+                enc_dek,
+                iv,
+                &aad,
+                WrapType::Aes256Gcm,
+            )?)
         };
-        let iv = base64_decoder.decode(&self.iv)?;
-        let ciphertext = base64_decoder.decode(&self.encrypted_data)?;
-        crypto::decrypt(datakey, ciphertext, iv, self.wrap_type)
+        let iv = decode("iv", &self.iv)?;
+        let ciphertext = decode("encrypted_data", &self.encrypted_data)?;
+        crypto::decrypt(datakey, ciphertext, iv, &aad, self.wrap_type)
     }
 
     /// Unseal this envelope with the given kms client, which means this envelope
-    /// must be sealed by kms.
-    pub(crate) async fn unseal_with_kms(&self, unsealer: Arc<Mutex<dyn KMS>>) -> Result<Vec<u8>> {
-        let base64_decoder = base64::engine::general_purpose::STANDARD;
-        let enc_dek = base64_decoder.decode(&self.encrypted_key)?;
+    /// must be sealed by kms. `version` and `provider` are of the secret.
+    pub(crate) async fn unseal_with_kms(
+        &self,
+        version: &str,
+        provider: &str,
+        unsealer: Arc<dyn KMS>,
+    ) -> Result<Vec<u8>> {
+        let aad = self.aad(version, provider)?;
+        let enc_dek = decode("encrypted_key", &self.encrypted_key)?;
         let datakey = {
-            let mut client = unsealer.lock().await;
-
             Zeroizing::new(
-                client
+                unsealer
                     .decrypt(&enc_dek, &self.key_id, &self.annotations)
                     .await?,
             )
         };
-        let iv = base64_decoder.decode(&self.iv)?;
-        let ciphertext = base64_decoder.decode(&self.encrypted_data)?;
-        crypto::decrypt(datakey, ciphertext, iv, self.wrap_type)
+        let iv = decode("iv", &self.iv)?;
+        let ciphertext = decode("encrypted_data", &self.encrypted_data)?;
+        crypto::decrypt(datakey, ciphertext, iv, &aad, self.wrap_type)
     }
 
     /// Seal the given data with the given kbs client. The keyid is used
-    /// by the kbs client and must be a resource URI.
+    /// by the kbs client and must be a resource URI. The data is encrypted
+    /// with `wrap_type`, while the DEK is always encrypted with A256GCM.
+    ///
+    /// The envelope must be put into a secret of [`VERSION`] and provider
+    /// `kbs`, which are authenticated along with it. So `wrap_type` cannot
+    /// be A256CTR.
     pub async fn seal_with_kbs(
         keyid: String,
         data: Vec<u8>,
-        sealer: Arc<Mutex<KbsClient>>,
+        wrap_type: WrapType,
+        sealer: Arc<KbsClient>,
     ) -> Result<Self> {
         // Let's use a safer rand crate then
         let mut symmetric_key = [0u8; 32];
         rand::thread_rng().fill(&mut symmetric_key);
-        let mut symmetric_iv = [0u8; 12];
-        rand::thread_rng().fill(&mut symmetric_iv);
+        let mut symmetric_iv = vec![0u8; wrap_type.iv_length()];
+        rand::thread_rng().fill(&mut symmetric_iv[..]);
+        let mut sealed_iv = [0u8; 12];
+        rand::thread_rng().fill(&mut sealed_iv);
+
+        let base64_encoder = base64::engine::general_purpose::STANDARD;
+        let mut envelope = Envelope {
+            key_id: keyid,
+            encrypted_key: String::new(),
+            encrypted_data: String::new(),
+            wrap_type,
+            iv: base64_encoder.encode(&symmetric_iv),
+            annotations: [("iv".into(), base64_encoder.encode(sealed_iv))]
+                .into_iter()
+                .collect(),
+        };
+        let aad = envelope.aad(VERSION, KBS_PROVIDER)?;
 
         let ciphertext = crypto::encrypt(
             Zeroizing::new(symmetric_key.to_vec()),
             data,
-            symmetric_iv.to_vec(),
-            WrapType::Aes256Gcm,
+            symmetric_iv,
+            &aad,
+            wrap_type,
         )?;
 
-        let (encrypted_key, annotations) = {
+        let encrypted_key = {
             let key = {
-                let mut client = sealer.lock().await;
-                let key_url = ResourceUri::try_from(&keyid[..])
+                let key_url = ResourceUri::try_from(&envelope.key_id[..])
                     .map_err(|e| anyhow!("parse key id as resource uri failed: {e}"))?;
-                Zeroizing::new(client.get_resource(key_url).await?)
+                Zeroizing::new(sealer.get_resource(key_url).await?)
             };
-            let mut sealed_iv = [0u8; 12];
-            rand::thread_rng().fill(&mut sealed_iv);
-            let engine = base64::engine::general_purpose::STANDARD;
-            let sealed_iv_base64 = engine.encode(sealed_iv);
-
-            let encrypted_key = crypto::encrypt(
+            crypto::encrypt(
                 key,
                 symmetric_key.to_vec(),
                 sealed_iv.to_vec(),
+                &aad,
                 WrapType::Aes256Gcm,
-            )?;
-            let annotations = [("iv".into(), sealed_iv_base64)].into_iter().collect();
-            (encrypted_key, annotations)
+            )?
         };
 
         symmetric_key.zeroize();
-        let base64_encoder = base64::engine::general_purpose::STANDARD;
-        let envelope = Envelope {
-            key_id: keyid,
-            encrypted_key: base64_encoder.encode(encrypted_key),
-            encrypted_data: base64_encoder.encode(ciphertext),
-            wrap_type: WrapType::Aes256Gcm,
-            iv: base64_encoder.encode(symmetric_iv),
-            annotations,
-        };
+        envelope.encrypted_key = base64_encoder.encode(encrypted_key);
+        envelope.encrypted_data = base64_encoder.encode(ciphertext);
         Ok(envelope)
     }
 
     /// Seal the given data with the given KMS driver. The keyid is used
-    /// by the KMS driver.
+    /// by the KMS driver, which generates the DEK. The data is encrypted
+    /// with `wrap_type`.
+    ///
+    /// The envelope must be put into a secret of [`VERSION`] and the
+    /// `provider` of the KMS driver, which are authenticated along with it.
+    /// So `wrap_type` cannot be A256CTR.
     pub async fn seal_with_kms(
+        provider: &str,
         keyid: String,
         data: Vec<u8>,
-        sealer: Arc<Mutex<dyn KMS>>,
+        wrap_type: WrapType,
+        sealer: Arc<dyn KMS>,
     ) -> Result<Self> {
-        // Let's use a safer rand crate then
-        let mut symmetric_key = [0u8; 32];
-        rand::thread_rng().fill(&mut symmetric_key);
-        let mut symmetric_iv = [0u8; 12];
-        rand::thread_rng().fill(&mut symmetric_iv);
-
-        let ciphertext = crypto::encrypt(
-            Zeroizing::new(symmetric_key.to_vec()),
-            data,
-            symmetric_iv.to_vec(),
-            WrapType::Aes256Gcm,
-        )?;
+        let datakey = sealer
+            .generate_data_key(&keyid, wrap_type.key_length())
+            .await?;
+        let mut symmetric_iv = vec![0u8; wrap_type.iv_length()];
+        rand::thread_rng().fill(&mut symmetric_iv[..]);
 
-        let (encrypted_key, annotations) = {
-            let mut client = sealer.lock().await;
-            client.encrypt(&symmetric_key, &keyid).await?
-        };
-
-        symmetric_key.zeroize();
         let base64_encoder = base64::engine::general_purpose::STANDARD;
-        let envelope = Envelope {
+        let mut envelope = Envelope {
             key_id: keyid,
-            encrypted_key: base64_encoder.encode(encrypted_key),
-            encrypted_data: base64_encoder.encode(ciphertext),
-            wrap_type: WrapType::Aes256Gcm,
-            iv: base64_encoder.encode(symmetric_iv),
-            annotations,
+            encrypted_key: base64_encoder.encode(datakey.ciphertext),
+            encrypted_data: String::new(),
+            wrap_type,
+            iv: base64_encoder.encode(&symmetric_iv),
+            annotations: datakey.annotations,
         };
+        let aad = envelope.aad(VERSION, provider)?;
+
+        let ciphertext = crypto::encrypt(datakey.plaintext, data, symmetric_iv, &aad, wrap_type)?;
+        envelope.encrypted_data = base64_encoder.encode(ciphertext);
         Ok(envelope)
     }
 }
@@ -10,7 +10,8 @@ use kbs_client::Client as KbsClient;
 use kms::KMS;
 use resource_uri::ResourceUri;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+
+use crate::Error;
 
 #[derive(Serialize, Deserialize)]
 pub struct VaultSecret {
@@ -25,24 +26,18 @@ impl VaultSecret {
     /// Retrieve this secret. If this secret is a KBS-version vault
     /// secret, the field `name` is the KBS Resource ID of the secret
     /// from the kbs.
-    pub(crate) async fn unseal_with_kbs(&self, unsealer: Arc<Mutex<KbsClient>>) -> Result<Vec<u8>> {
-        let secret_url = ResourceUri::try_from(&self.name[..])
-            .map_err(|e| anyhow!("parse `name` as resource uri failed: {e}"))?;
-        let secret = {
-            let mut client = unsealer.lock().await;
-            client.get_resource(secret_url).await?
-        };
+    pub(crate) async fn unseal_with_kbs(&self, unsealer: Arc<KbsClient>) -> Result<Vec<u8>> {
+        let secret_url = ResourceUri::try_from(&self.name[..]).map_err(|e| {
+            Error::InvalidSecret(format!("parse `name` as resource uri failed: {e}"))
+        })?;
+        let secret = unsealer.get_resource(secret_url).await?;
 
         Ok(secret)
     }
 
     /// Retrieve this secret from a given KMS client driver
-    pub(crate) async fn unseal_with_kms(&self, unsealer: Arc<Mutex<dyn KMS>>) -> Result<Vec<u8>> {
-        let secret = {
-            let mut client = unsealer.lock().await;
-
-            client.get_secret(&self.name, &self.annotations).await?
-        };
+    pub(crate) async fn unseal_with_kms(&self, unsealer: Arc<dyn KMS>) -> Result<Vec<u8>> {
+        let secret = unsealer.get_secret(&self.name, &self.annotations).await?;
 
         Ok(secret)
     }
@@ -52,22 +47,15 @@ impl VaultSecret {
     pub async fn seal_with_kbs(
         _name: String,
         _data: Vec<u8>,
-        _sealer: Arc<Mutex<KbsClient>>,
+        _sealer: Arc<KbsClient>,
     ) -> Result<Self> {
         todo!()
     }
 
     /// Create a vault secret of the data with the given KbsClient. The
     /// data will be stored inside the vault with name `name`.
-    pub async fn seal_with_kms(
-        name: String,
-        data: Vec<u8>,
-        sealer: Arc<Mutex<dyn KMS>>,
-    ) -> Result<Self> {
-        let annotations = {
-            let mut client = sealer.lock().await;
-            client.set_secret(data, name.clone()).await?
-        };
+    pub async fn seal_with_kms(name: String, data: Vec<u8>, sealer: Arc<dyn KMS>) -> Result<Self> {
+        let annotations = sealer.set_secret(data, name.clone()).await?;
 
         Ok(Self { name, annotations })
     }
@@ -10,6 +10,14 @@ use strum::EnumString;
 
 use self::layout::{envelope::Envelope, vault::VaultSecret};
 
+/// Version of the secrets to seal. Their envelopes authenticate the
+/// `provider`, `key_id`, `wrap_type` and `annotations` along with the data.
+pub const VERSION: &str = "0.2.0";
+
+/// Version of the secrets whose envelopes only authenticate the data. They
+/// can still be unsealed.
+pub const LEGACY_VERSION: &str = "0.1.0";
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum SecretContent {
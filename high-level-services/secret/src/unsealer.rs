@@ -8,23 +8,22 @@ use std::sync::Arc;
 use anyhow::*;
 use kbs_client::Client as KbsClient;
 use kms::KMS;
-use tokio::sync::Mutex;
 
 use crate::secret::{Secret, SecretContent};
 
 pub enum UnSealer {
-    Kms(Arc<Mutex<dyn KMS>>),
-    Kbs(Arc<Mutex<KbsClient>>),
+    Kms(Arc<dyn KMS>),
+    Kbs(Arc<KbsClient>),
 }
 
-impl From<Arc<Mutex<dyn KMS>>> for UnSealer {
-    fn from(value: Arc<Mutex<dyn KMS>>) -> Self {
+impl From<Arc<dyn KMS>> for UnSealer {
+    fn from(value: Arc<dyn KMS>) -> Self {
         Self::Kms(value)
     }
 }
 
-impl From<Arc<Mutex<KbsClient>>> for UnSealer {
-    fn from(value: Arc<Mutex<KbsClient>>) -> Self {
+impl From<Arc<KbsClient>> for UnSealer {
+    fn from(value: Arc<KbsClient>) -> Self {
         Self::Kbs(value)
     }
 }
@@ -33,8 +32,16 @@ impl UnSealer {
     pub async fn unseal(&self, secret: Secret) -> Result<Vec<u8>> {
         match secret.r#type {
             SecretContent::Envelope(envelope) => match self {
-                UnSealer::Kms(k) => envelope.unseal_with_kms(k.clone()).await,
-                UnSealer::Kbs(k) => envelope.unseal_with_kbs(k.clone()).await,
+                UnSealer::Kms(k) => {
+                    envelope
+                        .unseal_with_kms(&secret.version, &secret.provider, k.clone())
+                        .await
+                }
+                UnSealer::Kbs(k) => {
+                    envelope
+                        .unseal_with_kbs(&secret.version, &secret.provider, k.clone())
+                        .await
+                }
             },
             SecretContent::Vault(vault) => match self {
                 UnSealer::Kms(k) => vault.unseal_with_kms(k.clone()).await,
@@ -43,3 +50,198 @@ impl UnSealer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use base64::Engine;
+    use crypto::WrapType;
+    use kbs_client::{Client as KbsClient, SampleProvider};
+    use kms::{Registry, KMS};
+    use mock_kbs::MockKbs;
+    use rstest::rstest;
+    use serde_json::{json, Value};
+    use zeroize::Zeroizing;
+
+    use crate::secret::{
+        layout::{envelope::Envelope, vault::VaultSecret},
+        Secret, SecretContent, LEGACY_VERSION, VERSION,
+    };
+
+    use super::UnSealer;
+
+    async fn kbs_client(kbs: &MockKbs) -> Arc<KbsClient> {
+        let client = KbsClient::new(kbs.url(), Arc::new(SampleProvider), Duration::from_secs(5))
+            .expect("create kbs client failed");
+        Arc::new(client)
+    }
+
+    async fn local_kms(dir: &std::path::Path) -> Arc<dyn KMS> {
+        let settings = json!({ "dir": dir });
+        Registry::builtin()
+            .new_client("local", settings.as_object().unwrap())
+            .await
+            .expect("create local kms failed")
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn unseal_with_kbs(
+        #[values(
+            WrapType::Aes256Gcm,
+            WrapType::ChaCha20Poly1305,
+            WrapType::XChaCha20Poly1305,
+            WrapType::Aes256GcmSiv
+        )]
+        wrap_type: WrapType,
+    ) {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_resource("default/key/1", vec![7u8; 32]);
+        kbs.set_resource("default/secret/1", b"vault secret".to_vec());
+        let client = kbs_client(&kbs).await;
+
+        let envelope = Envelope::seal_with_kbs(
+            "kbs:///default/key/1".into(),
+            b"envelope secret".to_vec(),
+            wrap_type,
+            client.clone(),
+        )
+        .await
+        .expect("seal failed");
+        let vault = VaultSecret {
+            name: "kbs:///default/secret/1".into(),
+            annotations: HashMap::new(),
+        };
+
+        let unsealer: UnSealer = client.into();
+        for (content, expected) in [
+            (SecretContent::Envelope(envelope), &b"envelope secret"[..]),
+            (SecretContent::Vault(vault), &b"vault secret"[..]),
+        ] {
+            let secret = Secret {
+                version: VERSION.into(),
+                provider: "kbs".into(),
+                r#type: content,
+            };
+            let plaintext = unsealer.unseal(secret).await.expect("unseal failed");
+            assert_eq!(plaintext, expected);
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn unseal_with_kms(
+        #[values(
+            WrapType::Aes256Gcm,
+            WrapType::ChaCha20Poly1305,
+            WrapType::XChaCha20Poly1305,
+            WrapType::Aes256GcmSiv
+        )]
+        wrap_type: WrapType,
+    ) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = local_kms(dir.path()).await;
+
+        let envelope = Envelope::seal_with_kms(
+            "local",
+            "key1".into(),
+            b"envelope secret".to_vec(),
+            wrap_type,
+            kms.clone(),
+        )
+        .await
+        .expect("seal envelope failed");
+        let vault =
+            VaultSecret::seal_with_kms("secret1".into(), b"vault secret".to_vec(), kms.clone())
+                .await
+                .expect("seal vault failed");
+
+        let unsealer: UnSealer = kms.into();
+        for (content, expected) in [
+            (SecretContent::Envelope(envelope), &b"envelope secret"[..]),
+            (SecretContent::Vault(vault), &b"vault secret"[..]),
+        ] {
+            let secret = Secret {
+                version: VERSION.into(),
+                provider: "local".into(),
+                r#type: content,
+            };
+            let plaintext = unsealer.unseal(secret).await.expect("unseal failed");
+            assert_eq!(plaintext, expected);
+        }
+    }
+
+    #[rstest]
+    #[case(|secret: &mut Value| secret["provider"] = json!("other"))]
+    #[case(|secret: &mut Value| secret["version"] = json!(LEGACY_VERSION))]
+    #[case(|secret: &mut Value| secret["version"] = json!("0.3.0"))]
+    #[case(|secret: &mut Value| secret["wrap_type"] = json!("C20P"))]
+    #[case(|secret: &mut Value| secret["annotations"]["extra"] = json!("value"))]
+    #[tokio::test]
+    async fn tampered_envelope(#[case] tamper: fn(&mut Value)) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = local_kms(dir.path()).await;
+
+        let envelope = Envelope::seal_with_kms(
+            "local",
+            "key1".into(),
+            b"envelope secret".to_vec(),
+            WrapType::Aes256Gcm,
+            kms.clone(),
+        )
+        .await
+        .expect("seal envelope failed");
+        let secret = Secret {
+            version: VERSION.into(),
+            provider: "local".into(),
+            r#type: SecretContent::Envelope(envelope),
+        };
+        let mut secret = serde_json::to_value(secret).expect("serialize failed");
+        tamper(&mut secret);
+        let secret = serde_json::from_value(secret).expect("deserialize failed");
+
+        let unsealer: UnSealer = kms.into();
+        unsealer
+            .unseal(secret)
+            .await
+            .expect_err("unseal should fail");
+    }
+
+    #[tokio::test]
+    async fn unseal_legacy_envelope() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = local_kms(dir.path()).await;
+
+        let datakey = kms
+            .generate_data_key("key1", 32)
+            .await
+            .expect("generate data key failed");
+        let iv = vec![1u8; 12];
+        let ciphertext = crypto::encrypt(
+            Zeroizing::new(datakey.plaintext.to_vec()),
+            b"envelope secret".to_vec(),
+            iv.clone(),
+            &[],
+            WrapType::Aes256Gcm,
+        )
+        .expect("encrypt failed");
+        let engine = base64::engine::general_purpose::STANDARD;
+        let secret = Secret {
+            version: LEGACY_VERSION.into(),
+            provider: "local".into(),
+            r#type: SecretContent::Envelope(Envelope {
+                key_id: "key1".into(),
+                encrypted_key: engine.encode(datakey.ciphertext),
+                encrypted_data: engine.encode(ciphertext),
+                wrap_type: WrapType::Aes256Gcm,
+                iv: engine.encode(iv),
+                annotations: datakey.annotations,
+            }),
+        };
+
+        let unsealer: UnSealer = kms.into();
+        let plaintext = unsealer.unseal(secret).await.expect("unseal failed");
+        assert_eq!(plaintext, b"envelope secret");
+    }
+}
@@ -3,18 +3,24 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf};
 
 use clap::Parser;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// The socket address to listen to
+    /// Path to the configuration file in TOML or JSON format
     #[arg(short, long)]
-    pub socket: SocketAddr,
+    pub config: Option<PathBuf>,
 
-    /// KBS endpoint to connect to
+    /// The socket address to listen to. Overrides `socket` of the
+    /// configuration file
     #[arg(short, long)]
-    pub kbs_addr: String,
+    pub socket: Option<SocketAddr>,
+
+    /// KBS endpoint to connect to. Overrides `kbs.url` of the
+    /// configuration file
+    #[arg(short, long)]
+    pub kbs_addr: Option<String>,
 }
@@ -0,0 +1,52 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! ocicrypt keyprovider in command mode. ocicrypt launches this binary for
+//! each request, writes the `KeyProviderInput` JSON to its stdin and reads
+//! the output JSON from its stdout. An example ocicrypt configuration
+//!
+//! ```json
+//! {
+//!     "key-providers": {
+//!         "attestation-agent": {
+//!             "cmd": {
+//!                 "path": "/usr/local/bin/cdh-keyprovider",
+//!                 "args": ["--config", "/etc/confidential-datahub.toml"]
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::io::{Read, Write};
+
+use anyhow::*;
+use clap::Parser;
+use confidential_datahub::{service::services::keyprovider, Args, DataHub, HubConfig};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // stdout carries the output to ocicrypt, so the logs go to stderr.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .target(env_logger::Target::Stderr)
+        .init();
+
+    let args = Args::parse();
+    let config = HubConfig::from_args(&args)?;
+
+    let mut input = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut input)
+        .context("read KeyProviderInput from stdin")?;
+    let input = keyprovider::parse_input(&input)?;
+
+    let core = DataHub::start(&config).await.context("launch datahub")?;
+    let output = keyprovider::handle(&core, &input).await?;
+
+    std::io::stdout()
+        .write_all(&output)
+        .context("write output to stdout")?;
+    Ok(())
+}
@@ -0,0 +1,650 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Configuration of the Confidential DataHub.
+//!
+//! The configuration is read from a TOML or JSON file given by `--config`.
+//! A file with `.json` extension is parsed as JSON, otherwise as TOML.
+//! An example TOML configuration file
+//!
+//! ```toml
+//! socket = "127.0.0.1:50000"
+//! services = ["keyprovider", "getresource", "sealed_secret"]
+//! evidence_provider = "attestation_agent"
+//!
+//! [kbs]
+//! url = "http://127.0.0.1:8080"
+//! timeout = 60
+//! allow_unlisted_hosts = false
+//!
+//! tee_key_algorithms = ["EC-P256", "RSA1_5"]
+//!
+//! [kbs.hosts]
+//! "kbs.example.io" = "https://kbs.example.io:8443"
+//!
+//! [kbs.cache]
+//! ttl = 300
+//! max_entries = 64
+//! max_size = 1048576
+//!
+//! [attestation_agent]
+//! endpoint = "unix:///run/confidential-containers/attestation-agent.sock"
+//!
+//! [kms.ali]
+//! region = "cn-hangzhou"
+//!
+//! [kms_plugins.vendor]
+//! path = "/usr/libexec/vendor-kms-plugin"
+//! settings = { endpoint = "https://kms.vendor.io" }
+//! ```
+//!
+//! The `[kbs]` section can be left out for a hub unsealing everything with
+//! KMSes, which can then serve the GetResource requests by the secrets of the
+//! KMS named by `resource_provider`.
+//!
+//! Every key can be overridden by an environment variable named after the
+//! path of the key with prefix `CDH_`, where the sections are separated by
+//! double underscores, e.g. `CDH_SOCKET` and `CDH_KBS__TIMEOUT`. The value of
+//! the environment variable is taken as a string, except for the numeric and
+//! boolean keys like `CDH_KBS__TIMEOUT=10`, and for the arrays and tables
+//! given as JSON like `CDH_SERVICES='["keyprovider"]'`.
+//!
+//! The keys defined by the DataHub are matched case-insensitively, but the
+//! names chosen by the user are taken as written, i.e. the KMS names and
+//! settings, the KMS plugin names and settings, and the KBS host names, e.g.
+//! `CDH_KMS_PLUGINS__vendor__SETTINGS__Region` sets `Region` inside the
+//! `settings` of the KMS plugin `vendor`.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use kbs_protocol::{attestation_agent_client::AA_POD_DOMAIN, tee_pubkey::TeeKeyAlgorithm};
+
+use crate::Args;
+
+/// Prefix of the environment variables to override the configuration.
+const ENV_PREFIX: &str = "CDH_";
+
+/// Separator of the sections inside the environment variable names.
+const ENV_SEPARATOR: &str = "__";
+
+/// Default timeout in seconds of each request to the KBS.
+const DEFAULT_KBS_TIMEOUT_SEC: u64 = 60;
+
+/// Default timeout in seconds of each request to a KMS plugin.
+const DEFAULT_KMS_PLUGIN_TIMEOUT_SEC: u64 = 30;
+
+/// Default time in seconds that a KBS resource is cached.
+const DEFAULT_CACHE_TTL_SEC: u64 = 300;
+
+/// Default maximum number of the cached KBS resources.
+const DEFAULT_CACHE_MAX_ENTRIES: usize = 64;
+
+/// Default maximum total size in bytes of the cached KBS resources.
+const DEFAULT_CACHE_MAX_SIZE: usize = 1024 * 1024;
+
+/// The gRPC services that the DataHub can serve.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    #[serde(rename = "keyprovider")]
+    KeyProvider,
+
+    #[serde(rename = "getresource")]
+    GetResource,
+
+    #[serde(rename = "sealed_secret")]
+    SealedSecret,
+}
+
+/// Where the evidence to attest to the KBS is got from.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvidenceProviderType {
+    /// The local attestation-agent configured by `attestation_agent`
+    #[default]
+    #[serde(rename = "attestation_agent")]
+    AttestationAgent,
+
+    /// The built-in sample TEE, which needs no TEE hardware. Only for testing.
+    #[serde(rename = "sample")]
+    Sample,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HubConfig {
+    /// The socket address to listen to. Only required when serving the
+    /// gRPC services.
+    pub socket: Option<SocketAddr>,
+
+    /// The services to serve. All services are served by default.
+    #[serde(default = "default_services")]
+    pub services: Vec<Service>,
+
+    /// The KBS to connect to. Without it, the secrets and images can only
+    /// be unsealed by KMSes.
+    pub kbs: Option<KbsConfig>,
+
+    /// Where the evidence is got from
+    #[serde(default)]
+    pub evidence_provider: EvidenceProviderType,
+
+    /// The local attestation-agent used to get evidence
+    #[serde(default)]
+    pub attestation_agent: AttestationAgentConfig,
+
+    /// The KMS drivers to launch, keyed by driver name. The value is the
+    /// settings of the driver, e.g. credentials.
+    #[serde(default)]
+    pub kms: HashMap<String, Map<String, Value>>,
+
+    /// The external KMS plugins to spawn, keyed by the provider name that
+    /// the secrets and images refer to.
+    #[serde(default)]
+    pub kms_plugins: HashMap<String, KmsPluginConfig>,
+
+    /// The KMS serving the GetResource requests instead of the KBS, by its
+    /// secrets named after the resource paths like `default/key/1`.
+    pub resource_provider: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KbsConfig {
+    /// URL of the default KBS, e.g. `http://127.0.0.1:8080`. It is used
+    /// for the resource URIs without KBS address, like `kbs:///a/b/c`.
+    pub url: String,
+
+    /// URLs of the other KBSes keyed by their address in the resource URIs.
+    #[serde(default)]
+    pub hosts: HashMap<String, String>,
+
+    /// Whether the resource URIs may name KBSes not listed in `hosts`,
+    /// which are then connected with the scheme of `url`. Only the default
+    /// KBS and the listed ones are connected if not set.
+    #[serde(default)]
+    pub allow_unlisted_hosts: bool,
+
+    /// Timeout in seconds of each request to the KBS
+    #[serde(default = "default_kbs_timeout")]
+    pub timeout: u64,
+
+    /// Cache of the resources got from the KBSes. Resources are not cached
+    /// unless this section is given. Cached resources are dropped by the
+    /// InvalidateResource API of the GetResource service.
+    pub cache: Option<ResourceCacheConfig>,
+
+    /// The TEE key algorithms offered to the KBSes in order of preference,
+    /// among `EC-P256`, `EC-P384`, `RSA-OAEP-256` and `RSA1_5`. All of them
+    /// are offered if not given. The KBSes not negotiating the algorithm
+    /// only support `RSA1_5`.
+    pub tee_key_algorithms: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceCacheConfig {
+    /// Time in seconds that a resource is cached after being got
+    #[serde(default = "default_cache_ttl")]
+    pub ttl: u64,
+
+    /// Maximum number of the cached resources
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+
+    /// Maximum total size in bytes of the cached resources
+    #[serde(default = "default_cache_max_size")]
+    pub max_size: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KmsPluginConfig {
+    /// Path of the plugin executable
+    pub path: PathBuf,
+
+    /// Arguments of the plugin executable
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Timeout in seconds of each request to the plugin
+    #[serde(default = "default_kms_plugin_timeout")]
+    pub timeout: u64,
+
+    /// Settings passed to the plugin, defined by each plugin
+    #[serde(default)]
+    pub settings: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AttestationAgentConfig {
+    /// Endpoint of the attestation-agent. Either a URI like
+    /// `http://127.0.0.1:50002`, or a Unix domain socket like
+    /// `unix:///run/attestation-agent.sock` or `/run/attestation-agent.sock`
+    #[serde(default = "default_aa_endpoint")]
+    pub endpoint: String,
+}
+
+impl Default for AttestationAgentConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: default_aa_endpoint(),
+        }
+    }
+}
+
+fn default_services() -> Vec<Service> {
+    vec![
+        Service::KeyProvider,
+        Service::GetResource,
+        Service::SealedSecret,
+    ]
+}
+
+fn default_kbs_timeout() -> u64 {
+    DEFAULT_KBS_TIMEOUT_SEC
+}
+
+fn default_kms_plugin_timeout() -> u64 {
+    DEFAULT_KMS_PLUGIN_TIMEOUT_SEC
+}
+
+fn default_cache_ttl() -> u64 {
+    DEFAULT_CACHE_TTL_SEC
+}
+
+fn default_cache_max_entries() -> usize {
+    DEFAULT_CACHE_MAX_ENTRIES
+}
+
+fn default_cache_max_size() -> usize {
+    DEFAULT_CACHE_MAX_SIZE
+}
+
+fn default_aa_endpoint() -> String {
+    AA_POD_DOMAIN.into()
+}
+
+impl HubConfig {
+    /// Build the configuration from the command line arguments. The config
+    /// file is overridden by the environment variables, and then by the
+    /// command line flags.
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let mut raw = match &args.config {
+            Some(path) => read_config_file(path)?,
+            None => Value::Object(Map::new()),
+        };
+
+        apply_env_overrides(&mut raw, std::env::vars())?;
+
+        if let Some(socket) = args.socket {
+            set_value(&mut raw, &["socket"], socket.to_string().into())?;
+        }
+
+        if let Some(kbs_addr) = &args.kbs_addr {
+            set_value(&mut raw, &["kbs", "url"], kbs_addr.clone().into())?;
+        }
+
+        Self::parse(raw)
+    }
+
+    /// Parse and validate the configuration.
+    fn parse(raw: Value) -> Result<Self> {
+        let config: HubConfig = serde_path_to_error::deserialize(raw)
+            .map_err(|e| anyhow!("invalid config key `{}`: {}", e.path(), e.inner()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.services.is_empty() {
+            bail!("invalid config key `services`: at least one service must be enabled");
+        }
+
+        if let Some(kbs) = &self.kbs {
+            kbs.validate()?;
+        }
+
+        for (name, plugin) in &self.kms_plugins {
+            if name == "kbs" || self.kms.contains_key(name) {
+                bail!("invalid config key `kms_plugins.{name}`: provider {name} is already used");
+            }
+
+            if plugin.timeout == 0 {
+                bail!("invalid config key `kms_plugins.{name}.timeout`: must be greater than 0");
+            }
+        }
+
+        if let Some(provider) = &self.resource_provider {
+            if !self.kms.contains_key(provider) && !self.kms_plugins.contains_key(provider) {
+                bail!("invalid config key `resource_provider`: no KMS named {provider}");
+            }
+        }
+
+        // The endpoint of the attestation-agent can also be the absolute path
+        // of a Unix domain socket.
+        let aa_endpoint = &self.attestation_agent.endpoint;
+        if !aa_endpoint.starts_with('/') {
+            url::Url::parse(aa_endpoint)
+                .map_err(|e| anyhow!("invalid config key `attestation_agent.endpoint`: {e}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl KbsConfig {
+    fn validate(&self) -> Result<()> {
+        url::Url::parse(&self.url).map_err(|e| anyhow!("invalid config key `kbs.url`: {e}"))?;
+
+        for (addr, url) in &self.hosts {
+            url::Url::parse(url)
+                .map_err(|e| anyhow!("invalid config key `kbs.hosts.{addr}`: {e}"))?;
+        }
+
+        if self.timeout == 0 {
+            bail!("invalid config key `kbs.timeout`: must be greater than 0");
+        }
+
+        if self.cache.as_ref().is_some_and(|cache| cache.ttl == 0) {
+            bail!("invalid config key `kbs.cache.ttl`: must be greater than 0");
+        }
+
+        if let Some(algorithms) = &self.tee_key_algorithms {
+            if algorithms.is_empty() {
+                bail!("invalid config key `kbs.tee_key_algorithms`: must not be empty");
+            }
+
+            for name in algorithms {
+                name.parse::<TeeKeyAlgorithm>().map_err(|_| {
+                    anyhow!("invalid config key `kbs.tee_key_algorithms`: unknown {name}")
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<Value> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("read config file {}", path.display()))?;
+    let raw = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content)
+            .with_context(|| format!("parse JSON config file {}", path.display()))?,
+        _ => toml::from_str(&content)
+            .with_context(|| format!("parse TOML config file {}", path.display()))?,
+    };
+
+    Ok(raw)
+}
+
+/// Override the keys of the `raw` configuration by the environment
+/// variables prefixed with [`ENV_PREFIX`].
+fn apply_env_overrides(
+    raw: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<()> {
+    for (name, value) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+
+        let path = env_key_path(key);
+        if path.iter().any(|section| section.is_empty()) {
+            bail!("invalid config environment variable {name}");
+        }
+        let path: Vec<&str> = path.iter().map(|section| &section[..]).collect();
+
+        let value = env_value(&path, value);
+        set_value(raw, &path, value)
+            .with_context(|| format!("apply environment variable {name}"))?;
+    }
+
+    Ok(())
+}
+
+/// Convert the `value` of the environment variable setting the key of
+/// `path`. It is kept as a string unless the key is numeric or boolean, or
+/// the value is a JSON array or table, so that the free-form settings like
+/// PINs are not turned into numbers.
+fn env_value(path: &[&str], value: String) -> Value {
+    let typed = matches!(
+        path,
+        ["kbs", "timeout" | "allow_unlisted_hosts"]
+            | ["kbs", "cache", "ttl" | "max_entries" | "max_size"]
+            | ["kms_plugins", _, "timeout"]
+    );
+    if typed || value.starts_with('[') || value.starts_with('{') {
+        return serde_json::from_str(&value).unwrap_or(Value::String(value));
+    }
+
+    Value::String(value)
+}
+
+/// Split the environment variable `key` into the path of a config key. The
+/// sections defined by the DataHub are lowercased, while the ones inside the
+/// tables keyed by user chosen names keep their case.
+fn env_key_path(key: &str) -> Vec<String> {
+    let mut path: Vec<String> = Vec::new();
+    for section in key.split(ENV_SEPARATOR) {
+        let parent: Vec<&str> = path.iter().map(String::as_str).collect();
+        let user_defined = matches!(
+            parent[..],
+            ["kms", ..]
+                | ["kms_plugins"]
+                | ["kms_plugins", _, "settings", ..]
+                | ["kbs", "hosts", ..]
+        );
+        path.push(if user_defined {
+            section.to_string()
+        } else {
+            section.to_lowercase()
+        });
+    }
+
+    path
+}
+
+/// Set the key of `path` inside `raw` to `value`. Missing sections are created.
+fn set_value(raw: &mut Value, path: &[&str], value: Value) -> Result<()> {
+    let (key, sections) = path
+        .split_last()
+        .ok_or_else(|| anyhow!("empty config key"))?;
+
+    let mut table = raw;
+    for (i, section) in sections.iter().enumerate() {
+        table = table
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("config key `{}` is not a table", path[..i].join(".")))?
+            .entry(section.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    table
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("config key `{}` is not a table", sections.join(".")))?
+        .insert(key.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::{json, Value};
+
+    use super::{apply_env_overrides, EvidenceProviderType, HubConfig, Service};
+
+    #[test]
+    fn parse_toml() {
+        let raw: Value = toml::from_str(
+            r#"
+            socket = "127.0.0.1:50000"
+            services = ["sealed_secret"]
+
+            [kbs]
+            url = "http://127.0.0.1:8080"
+            tee_key_algorithms = ["EC-P256", "RSA1_5"]
+
+            [kbs.hosts]
+            "kbs.example.io" = "https://kbs.example.io:8443"
+
+            [kbs.cache]
+            ttl = 10
+
+            [kms.ali]
+            region = "cn-hangzhou"
+
+            [kms_plugins.vendor]
+            path = "/usr/libexec/vendor-kms-plugin"
+            settings = { endpoint = "https://kms.vendor.io" }
+            "#,
+        )
+        .expect("parse toml failed");
+        let config = HubConfig::parse(raw).expect("parse config failed");
+        assert_eq!(config.services, vec![Service::SealedSecret]);
+        assert_eq!(
+            config.evidence_provider,
+            EvidenceProviderType::AttestationAgent
+        );
+        let kbs = config.kbs.as_ref().expect("kbs should be configured");
+        assert_eq!(kbs.timeout, 60);
+        assert!(!kbs.allow_unlisted_hosts);
+        let cache = kbs.cache.as_ref().expect("cache should be enabled");
+        assert_eq!(cache.ttl, 10);
+        assert_eq!(cache.max_entries, 64);
+        assert_eq!(kbs.hosts["kbs.example.io"], "https://kbs.example.io:8443");
+        assert_eq!(
+            kbs.tee_key_algorithms,
+            Some(vec!["EC-P256".to_string(), "RSA1_5".to_string()])
+        );
+        assert_eq!(
+            config.attestation_agent.endpoint,
+            "http://attestation-agent"
+        );
+        assert_eq!(config.kms["ali"]["region"], "cn-hangzhou");
+        let plugin = &config.kms_plugins["vendor"];
+        assert_eq!(plugin.timeout, 30);
+        assert_eq!(plugin.settings["endpoint"], "https://kms.vendor.io");
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut raw = json!({
+            "socket": "127.0.0.1:50000",
+            "kbs": {
+                "url": "http://127.0.0.1:8080"
+            }
+        });
+        let vars = [
+            ("CDH_KBS__TIMEOUT", "10"),
+            ("CDH_EVIDENCE_PROVIDER", "sample"),
+            ("CDH_ATTESTATION_AGENT__ENDPOINT", "http://127.0.0.1:50002"),
+            ("CDH_KMS__ali__region", "cn-beijing"),
+            ("PATH", "/usr/bin"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        apply_env_overrides(&mut raw, vars).expect("apply env failed");
+        let config = HubConfig::parse(raw).expect("parse config failed");
+        let kbs = config.kbs.as_ref().expect("kbs should be configured");
+        assert_eq!(kbs.timeout, 10);
+        assert_eq!(kbs.cache, None);
+        assert_eq!(config.evidence_provider, EvidenceProviderType::Sample);
+        assert_eq!(config.attestation_agent.endpoint, "http://127.0.0.1:50002");
+        assert_eq!(config.kms["ali"]["region"], "cn-beijing");
+    }
+
+    #[rstest]
+    #[case("CDH_KBS__URL", &["kbs", "url"])]
+    #[case("CDH_KBS__CACHE__TTL", &["kbs", "cache", "ttl"])]
+    #[case("CDH_KMS__Vault__AppRole", &["kms", "Vault", "AppRole"])]
+    #[case("CDH_KBS__HOSTS__KBS.example.io", &["kbs", "hosts", "KBS.example.io"])]
+    #[case("CDH_KMS_PLUGINS__Vendor__PATH", &["kms_plugins", "Vendor", "path"])]
+    #[case(
+        "CDH_KMS_PLUGINS__vendor__SETTINGS__Region",
+        &["kms_plugins", "vendor", "settings", "Region"]
+    )]
+    fn env_key_case(#[case] name: &str, #[case] expected: &[&str]) {
+        let mut raw = json!({});
+        let vars = [(name.to_string(), "value".to_string())].into_iter();
+        apply_env_overrides(&mut raw, vars).expect("apply env failed");
+        assert_eq!(
+            raw.pointer(&format!("/{}", expected.join("/"))),
+            Some(&json!("value"))
+        );
+    }
+
+    #[rstest]
+    #[case("CDH_SOCKET", "true", "/socket", json!("true"))]
+    #[case("CDH_KMS__ali__pin", "1234", "/kms/ali/pin", json!("1234"))]
+    #[case(
+        "CDH_KMS_PLUGINS__x__SETTINGS__pin",
+        "01234",
+        "/kms_plugins/x/settings/pin",
+        json!("01234")
+    )]
+    #[case("CDH_KBS__URL", "null", "/kbs/url", json!("null"))]
+    #[case("CDH_KBS__TIMEOUT", "10", "/kbs/timeout", json!(10))]
+    #[case("CDH_KBS__ALLOW_UNLISTED_HOSTS", "true", "/kbs/allow_unlisted_hosts", json!(true))]
+    #[case("CDH_KBS__CACHE__MAX_SIZE", "1024", "/kbs/cache/max_size", json!(1024))]
+    #[case("CDH_KMS_PLUGINS__x__TIMEOUT", "5", "/kms_plugins/x/timeout", json!(5))]
+    #[case("CDH_KBS__TIMEOUT", "1s", "/kbs/timeout", json!("1s"))]
+    #[case("CDH_SERVICES", r#"["keyprovider"]"#, "/services", json!(["keyprovider"]))]
+    #[case("CDH_KMS__ali", r#"{"region": "cn-beijing"}"#, "/kms/ali", json!({"region": "cn-beijing"}))]
+    #[case("CDH_KMS__ali__region", "[cn-beijing", "/kms/ali/region", json!("[cn-beijing"))]
+    fn env_value_type(
+        #[case] name: &str,
+        #[case] value: &str,
+        #[case] pointer: &str,
+        #[case] expected: Value,
+    ) {
+        let mut raw = json!({});
+        let vars = [(name.to_string(), value.to_string())].into_iter();
+        apply_env_overrides(&mut raw, vars).expect("apply env failed");
+        assert_eq!(raw.pointer(pointer), Some(&expected));
+    }
+
+    #[test]
+    fn kms_only() {
+        let raw = json!({
+            "socket": "127.0.0.1:50000",
+            "kms": { "local": { "dir": "/run/cdh" } },
+            "resource_provider": "local"
+        });
+        let config = HubConfig::parse(raw).expect("parse config failed");
+        assert_eq!(config.kbs, None);
+        assert_eq!(config.resource_provider.as_deref(), Some("local"));
+    }
+
+    #[rstest]
+    #[case(json!({"socket": "127.0.0.1:50000", "resource_provider": "local"}), "`resource_provider`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080", "timeout": "1s"}}), "`kbs.timeout`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080", "port": 1}}), "`kbs.port`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "no scheme"}}), "`kbs.url`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080", "cache": {"ttl": 0}}}), "`kbs.cache.ttl`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080", "tee_key_algorithms": []}}), "`kbs.tee_key_algorithms`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080", "tee_key_algorithms": ["EC-P256", "X25519"]}}), "`kbs.tee_key_algorithms`: unknown X25519")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080", "hosts": {"kbs.example.io": "kbs.example.io"}}}), "`kbs.hosts.kbs.example.io`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080"}, "attestation_agent": {"endpoint": "aa.sock"}}), "`attestation_agent.endpoint`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "evidence_provider": "tdx", "kbs": {"url": "http://127.0.0.1:8080"}}), "`evidence_provider`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080"}, "kms": {"ali": {}}, "kms_plugins": {"ali": {"path": "/plugin"}}}), "`kms_plugins.ali`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "kbs": {"url": "http://127.0.0.1:8080"}, "kms_plugins": {"vendor": {"path": "/plugin", "timeout": 0}}}), "`kms_plugins.vendor.timeout`")]
+    #[case(json!({"socket": "127.0.0.1:50000", "services": ["unknown"], "kbs": {"url": "http://127.0.0.1:8080"}}), "`services[0]`")]
+    fn invalid_config(#[case] raw: Value, #[case] expected: &str) {
+        let err = HubConfig::parse(raw).expect_err("config should be invalid");
+        assert!(
+            err.to_string().contains(expected),
+            "`{err}` does not contain `{expected}`"
+        );
+    }
+}
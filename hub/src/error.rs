@@ -0,0 +1,122 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Errors of the DataHub, and their gRPC status codes
+
+use thiserror::Error;
+use tonic::{Code, Status};
+
+/// Failures caused by the requests to the DataHub themselves. The errors of
+/// the secrets, AnnotationPackets, KBS and KMS are typed by their own crates.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    InvalidRequest(String),
+
+    /// The request cannot be served by the features and configuration of
+    /// this DataHub.
+    #[error("{0}")]
+    Unsupported(String),
+}
+
+/// The gRPC status code of the `err`, told by the first typed error in its
+/// chain. Untyped errors are internal. The typed errors are never attached
+/// as context, so each of them is a cause of the chain.
+pub fn code(err: &anyhow::Error) -> Code {
+    for cause in err.chain() {
+        #[cfg(feature = "kbs")]
+        if let Some(e) = cause.downcast_ref::<kbs_client::Error>() {
+            return match e {
+                kbs_client::Error::Unavailable(_) => Code::Unavailable,
+                kbs_client::Error::Unauthorized(_) => Code::PermissionDenied,
+                kbs_client::Error::ResourceNotFound(_) => Code::NotFound,
+                kbs_client::Error::InvalidResponse(_) => Code::Internal,
+                kbs_client::Error::UnknownKbs(_) => Code::PermissionDenied,
+            };
+        }
+
+        #[cfg(feature = "kms")]
+        if let Some(e) = cause.downcast_ref::<kms_client::Error>() {
+            return match e {
+                kms_client::Error::Unsupported { .. } => Code::Unimplemented,
+                kms_client::Error::NotFound(_) => Code::NotFound,
+                kms_client::Error::PermissionDenied(_) => Code::PermissionDenied,
+                kms_client::Error::Unavailable(_) => Code::Unavailable,
+                kms_client::Error::InvalidArgument(_) => Code::InvalidArgument,
+            };
+        }
+
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            return match e {
+                Error::InvalidRequest(_) => Code::InvalidArgument,
+                Error::Unsupported(_) => Code::Unimplemented,
+            };
+        }
+
+        if let Some(crypto::Error::Unsupported(_)) = cause.downcast_ref::<crypto::Error>() {
+            return Code::Unimplemented;
+        }
+
+        if cause.is::<secret::Error>() || cause.is::<image::Error>() || cause.is::<crypto::Error>()
+        {
+            return Code::InvalidArgument;
+        }
+    }
+
+    Code::Internal
+}
+
+/// The gRPC status of the `err` failing the `what` operation.
+pub fn to_status(what: &str, err: &anyhow::Error) -> Status {
+    Status::new(code(err), format!("[ERROR] {what} failed: {err:#}"))
+}
+
+#[cfg(all(test, feature = "kbs", feature = "kms"))]
+mod tests {
+    use anyhow::{anyhow, Context};
+    use rstest::rstest;
+    use tonic::Code;
+
+    use super::{code, Error};
+
+    #[rstest]
+    #[case(anyhow!("unknown"), Code::Internal)]
+    #[case(Error::InvalidRequest("bad".into()).into(), Code::InvalidArgument)]
+    #[case(Error::Unsupported("no KBS".into()).into(), Code::Unimplemented)]
+    #[case(
+        anyhow::Error::from(secret::Error::InvalidSecret("bad".into())).context("unseal"),
+        Code::InvalidArgument
+    )]
+    #[case(
+        anyhow::Error::from(crypto::Error::DecryptionFailed("bad".into())),
+        Code::InvalidArgument
+    )]
+    #[case(
+        anyhow::Error::from(crypto::Error::Unsupported("XC20P".into())),
+        Code::Unimplemented
+    )]
+    #[case(
+        Err::<(), _>(kbs_client::Error::ResourceNotFound("key".into()))
+            .context("get resource")
+            .unwrap_err(),
+        Code::NotFound
+    )]
+    #[case(
+        kbs_client::Error::Unauthorized("rejected".into()).into(),
+        Code::PermissionDenied
+    )]
+    #[case(
+        kbs_client::Error::UnknownKbs("kbs.example.io".into()).into(),
+        Code::PermissionDenied
+    )]
+    #[case(kms_client::Error::Unavailable("down".into()).into(), Code::Unavailable)]
+    #[case(
+        kbs_client::Error::InvalidResponse("bad".into()).into(),
+        Code::Internal
+    )]
+    fn status_code(#[case] err: anyhow::Error, #[case] expected: Code) {
+        assert_eq!(code(&err), expected);
+    }
+}
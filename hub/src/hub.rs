@@ -3,112 +3,482 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::{collections::HashMap, sync::Arc};
+#[cfg(feature = "kms")]
+use std::collections::HashMap;
+#[cfg(any(feature = "kbs", feature = "kms"))]
+use std::{sync::Arc, time::Duration};
 
 use anyhow::*;
-use image::annotation_packet::{v2::Unwrapper, AnnotationPacket};
-use kbs_client::Client as KbsClient;
-use kms_client::KMS;
+#[cfg(feature = "kbs")]
+use image::annotation_packet::v1::AnnotationPacketV1;
+#[cfg(any(feature = "kbs", feature = "kms"))]
+use image::annotation_packet::v2::Unwrapper;
+use image::annotation_packet::{
+    v2::{AnnotationPacketV2, Wrapper},
+    AnnotationPacket, PacketVersion, WrapParameters,
+};
+#[cfg(feature = "kbs")]
+use kbs_client::{
+    AaClient, CacheConfig, Client as KbsClient, EvidenceProvider, SampleProvider, TeeKeyAlgorithm,
+};
+#[cfg(feature = "kms")]
+use kms_client::{
+    plugins::external::{self, PluginConfig},
+    Registry, KMS,
+};
+#[cfg(any(feature = "kbs", feature = "kms"))]
+use log::info;
+use log::warn;
 use resource_uri::ResourceUri;
-use secret::{secret::Secret, unsealer::UnSealer};
-use tokio::sync::Mutex;
+use secret::secret::Secret;
+#[cfg(any(feature = "kbs", feature = "kms"))]
+use secret::unsealer::UnSealer;
+
+#[cfg(feature = "kbs")]
+use crate::EvidenceProviderType;
+use crate::{Error, HubConfig};
 
 pub struct DataHub {
     #[cfg(feature = "kms")]
-    kms_manager: HashMap<String, Arc<Mutex<dyn KMS>>>,
+    kms_manager: HashMap<String, Arc<dyn KMS>>,
 
+    /// The client of the KBSes, if configured
     #[cfg(feature = "kbs")]
-    kbs_client: Arc<Mutex<KbsClient>>,
+    kbs_client: Option<Arc<KbsClient>>,
+
+    /// The KMS serving the GetResource requests instead of the KBS
+    resource_provider: Option<String>,
 }
 
 impl DataHub {
-    #[cfg(feature = "kbs")]
-    pub async fn start(kbs_host_url: String) -> Result<Self> {
-        // We should think about the given parameter here. Also, we
-        // should think about how the `auth` layer runs.
+    /// Launch the DataHub with the given configuration.
+    pub async fn start(config: &HubConfig) -> Result<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "kbs")] {
+                let kbs_client = match &config.kbs {
+                    Some(_) => Some(Arc::new(Self::connect_kbs(config).await?)),
+                    None => {
+                        info!("No KBS configured, only the KMSes are used.");
+                        None
+                    }
+                };
+            } else {
+                if config.kbs.is_some() {
+                    warn!("The KBS configured is ignored, because feature `kbs` of hub is disabled.");
+                }
+            }
+        }
 
-        let kbs_client = KbsClient::new(kbs_host_url).await?;
         Ok(Self {
-            kbs_client: Arc::new(Mutex::new(kbs_client)),
+            #[cfg(feature = "kbs")]
+            kbs_client,
 
             #[cfg(feature = "kms")]
-            kms_manager: HashMap::new(),
+            kms_manager: Self::launch_kms_drivers(config).await?,
+
+            resource_provider: config.resource_provider.clone(),
         })
     }
 
+    /// Create the client of the KBSes of the `config`. No KBS is connected
+    /// until a resource is requested.
+    #[cfg(feature = "kbs")]
+    async fn connect_kbs(config: &HubConfig) -> Result<KbsClient> {
+        let kbs = config.kbs.as_ref().expect("KBS is configured");
+        // We should think about how the `auth` layer runs.
+        let evidence_provider: Arc<dyn EvidenceProvider> = match config.evidence_provider {
+            EvidenceProviderType::AttestationAgent => {
+                Arc::new(AaClient::new(&config.attestation_agent.endpoint).await?)
+            }
+            EvidenceProviderType::Sample => {
+                warn!("Sample evidence provider is used, which is only for testing");
+                Arc::new(SampleProvider)
+            }
+        };
+        let mut kbs_client = KbsClient::new(
+            kbs.url.clone(),
+            evidence_provider,
+            Duration::from_secs(kbs.timeout),
+        )?
+        .with_hosts(kbs.hosts.clone())
+        .with_unlisted_hosts(kbs.allow_unlisted_hosts);
+        if let Some(cache) = &kbs.cache {
+            kbs_client = kbs_client.with_cache(CacheConfig {
+                ttl: Duration::from_secs(cache.ttl),
+                max_entries: cache.max_entries,
+                max_size: cache.max_size,
+            });
+        }
+
+        if let Some(algorithms) = &kbs.tee_key_algorithms {
+            // The names are checked when the config is validated.
+            let algorithms = algorithms
+                .iter()
+                .map(|name| name.parse::<TeeKeyAlgorithm>())
+                .collect::<Result<_, _>>()?;
+            kbs_client = kbs_client.with_tee_key_algorithms(algorithms);
+        }
+
+        Ok(kbs_client)
+    }
+
+    /// Launch the built-in KMS drivers and the KMS plugins of the `config`,
+    /// keyed by the provider name.
+    #[cfg(feature = "kms")]
+    async fn launch_kms_drivers(config: &HubConfig) -> Result<HashMap<String, Arc<dyn KMS>>> {
+        let registry = Registry::builtin();
+        let mut kms_manager = HashMap::new();
+        for (name, settings) in &config.kms {
+            let driver = registry
+                .new_client(name, settings)
+                .await
+                .with_context(|| format!("launch KMS driver {name}"))?;
+            info!("KMS driver {name} launched.");
+            kms_manager.insert(name.clone(), driver);
+        }
+
+        for (name, plugin) in &config.kms_plugins {
+            let plugin_config = PluginConfig {
+                path: plugin.path.clone(),
+                args: plugin.args.clone(),
+                timeout: Duration::from_secs(plugin.timeout),
+                settings: plugin.settings.clone(),
+            };
+            let driver = external::launch(name, plugin_config)
+                .await
+                .with_context(|| format!("launch KMS plugin {name}"))?;
+            info!("KMS plugin {name} launched.");
+            kms_manager.insert(name.clone(), driver);
+        }
+
+        Ok(kms_manager)
+    }
+
+    /// The KBS client, if the `provider` is `kbs` and a KBS is configured.
+    #[cfg(feature = "kbs")]
+    fn kbs(&self, provider: &str) -> Option<Arc<KbsClient>> {
+        match provider {
+            "kbs" => self.kbs_client.clone(),
+            _ => None,
+        }
+    }
+
+    /// The KMS driver of the `provider`, if launched.
+    #[cfg(feature = "kms")]
+    fn kms(&self, provider: &str) -> Option<Arc<dyn KMS>> {
+        self.kms_manager.get(provider).cloned()
+    }
+
     pub async fn unseal_secret(&self, secret: Secret) -> Result<Vec<u8>> {
         #[cfg(feature = "kbs")]
-        if secret.provider == "kbs" {
-            let unsealer: UnSealer = self.kbs_client.clone().into();
+        if let Some(kbs_client) = self.kbs(&secret.provider) {
+            let unsealer: UnSealer = kbs_client.into();
             let plaintext = unsealer.unseal(secret).await?;
             return Ok(plaintext);
         }
 
         #[cfg(feature = "kms")]
-        if !self.kms_manager.is_empty() {
-            let driver = self.kms_manager.get(&secret.provider).ok_or_else(|| {
-                anyhow!(
-                    "No KMS driver named {} found to unseal the secret.",
-                    secret.provider
-                )
-            })?;
-            let unsealer = Into::<UnSealer>::into(driver.clone());
+        if let Some(driver) = self.kms(&secret.provider) {
+            let unsealer = Into::<UnSealer>::into(driver);
             let plaintext = unsealer.unseal(secret).await?;
             return Ok(plaintext);
         }
 
-        bail!("Unseal failed!")
+        Err(secret::Error::UnknownProvider(secret.provider).into())
     }
 
     pub async fn unwrap_key(&self, annotation: &[u8]) -> Result<Vec<u8>> {
-        let annotation_packet =
-            serde_json::from_slice(annotation).context("parse AnnotationPacket failed")?;
+        let annotation_packet = serde_json::from_slice(annotation).map_err(|e| {
+            image::Error::InvalidAnnotationPacket(format!("parse AnnotationPacket failed: {e}"))
+        })?;
         match annotation_packet {
             AnnotationPacket::V1(v1) => {
-                cfg_if::cfg_if! {
-                    if #[cfg(feature = "kbs")] {
-                        v1.unwrap_key_with(self.kbs_client.clone()).await
-                    } else {
-                        bail!("Cannot unwrap key of a AnnotationV1 without kbs-client. Please enable feature `kbs` of hub.")
-                    }
+                #[cfg(feature = "kbs")]
+                if let Some(kbs_client) = self.kbs("kbs") {
+                    return v1.unwrap_key_with(kbs_client).await;
                 }
+
+                let _ = v1;
+                Err(image::Error::UnknownProvider("kbs".into()).into())
             }
             AnnotationPacket::V2(v2) => {
                 #[cfg(feature = "kbs")]
-                if v2.provider == "kbs" {
-                    let lek = v2
-                        .unwrap_key_with(Unwrapper::Kbs(self.kbs_client.clone()))
-                        .await?;
+                if let Some(kbs_client) = self.kbs(&v2.provider) {
+                    let lek = v2.unwrap_key_with(Unwrapper::Kbs(kbs_client)).await?;
                     return Ok(lek);
                 }
 
                 #[cfg(feature = "kms")]
-                if !self.kms_manager.is_empty() {
-                    let driver = self.kms_manager.get(&v2.provider).ok_or_else(|| {
-                        anyhow!(
-                            "No KMS driver named {} found to unwrap the image's lek.",
-                            v2.provider
-                        )
-                    })?;
-                    let lek = v2.unwrap_key_with(Unwrapper::Kms(driver.clone())).await?;
+                if let Some(driver) = self.kms(&v2.provider) {
+                    let lek = v2.unwrap_key_with(Unwrapper::Kms(driver)).await?;
                     return Ok(lek);
                 }
 
-                bail!("Unwrap failed!")
+                Err(image::Error::UnknownProvider(v2.provider).into())
             }
         }
     }
 
+    /// Wrap the `lek` following the JSON encoded [`WrapParameters`] in
+    /// `parameters`, and return the JSON encoded [`AnnotationPacket`].
+    pub async fn wrap_key(&self, parameters: &[u8], lek: &[u8]) -> Result<Vec<u8>> {
+        let parameters: WrapParameters = serde_json::from_slice(parameters).map_err(|e| {
+            image::Error::InvalidWrapParameters(format!("parse WrapParameters failed: {e}"))
+        })?;
+        let wrap_type = parameters.wrap_type()?;
+        let annotation_packet = match parameters.packet {
+            PacketVersion::V1 => {
+                if parameters.provider != "kbs" {
+                    bail!(image::Error::InvalidWrapParameters(format!(
+                        "AnnotationV1 only supports provider `kbs`, but {} is given.",
+                        parameters.provider
+                    )));
+                }
+
+                let kid = ResourceUri::try_from(&parameters.kid[..]).map_err(|e| {
+                    image::Error::InvalidWrapParameters(format!(
+                        "cannot parse the kid into a KBS Resource URI: {e}"
+                    ))
+                })?;
+                cfg_if::cfg_if! {
+                    if #[cfg(feature = "kbs")] {
+                        let kbs_client = self
+                            .kbs("kbs")
+                            .ok_or_else(|| image::Error::UnknownProvider("kbs".into()))?;
+                        let v1 = AnnotationPacketV1::wrap_key_with(lek, kid, wrap_type, kbs_client)
+                            .await?;
+                        AnnotationPacket::V1(v1)
+                    } else {
+                        let _ = kid;
+                        bail!(image::Error::UnknownProvider("kbs".into()))
+                    }
+                }
+            }
+            PacketVersion::V2 => {
+                let wrapper = self.get_wrapper(&parameters.provider)?;
+                if matches!(wrapper, Wrapper::Kms(_)) && parameters.wrap_type.is_some() {
+                    bail!(image::Error::InvalidWrapParameters(format!(
+                        "wrap type cannot be given with provider {}",
+                        parameters.provider
+                    )));
+                }
+                let v2 = AnnotationPacketV2::wrap_key_with(lek, parameters.kid, wrap_type, wrapper)
+                    .await?;
+                AnnotationPacket::V2(v2)
+            }
+        };
+
+        serde_json::to_vec(&annotation_packet).context("serialize AnnotationPacket failed")
+    }
+
+    fn get_wrapper(&self, provider: &str) -> Result<Wrapper> {
+        #[cfg(feature = "kbs")]
+        if let Some(kbs_client) = self.kbs(provider) {
+            return Ok(Wrapper::Kbs(kbs_client));
+        }
+
+        #[cfg(feature = "kms")]
+        if let Some(driver) = self.kms(provider) {
+            return Ok(Wrapper::Kms(driver));
+        }
+
+        Err(image::Error::UnknownProvider(provider.to_string()).into())
+    }
+
+    /// Remove the resource of the JSON encoded resource URI `uri` from the
+    /// cache of the KBS resources, or all the resources if `uri` is `None`.
+    /// Nothing is cached without a KBS.
+    pub fn invalidate_resource(&self, uri: Option<String>) -> Result<()> {
+        let resource_uri: Option<ResourceUri> = uri
+            .map(|uri| serde_json::from_str(&uri))
+            .transpose()
+            .map_err(|e| Error::InvalidRequest(format!("parse resource URI failed: {e}")))?;
+
+        #[cfg(feature = "kbs")]
+        if let Some(kbs_client) = &self.kbs_client {
+            match resource_uri {
+                Some(resource_uri) => kbs_client.invalidate(&resource_uri)?,
+                None => kbs_client.invalidate_all(),
+            }
+        }
+
+        let _ = resource_uri;
+        Ok(())
+    }
+
+    /// Get the resource of the JSON encoded resource URI `uri`, from the KMS
+    /// of `resource_provider` if configured, otherwise from the KBS.
     pub async fn get_resource(&self, uri: String) -> Result<Vec<u8>> {
-        let resource_uri: ResourceUri =
-            serde_json::from_str(&uri).context("parse resource URI failed")?;
-        let resource = self
-            .kbs_client
-            .clone()
-            .lock()
+        let resource_uri: ResourceUri = serde_json::from_str(&uri)
+            .map_err(|e| Error::InvalidRequest(format!("parse resource URI failed: {e}")))?;
+
+        if let Some(provider) = &self.resource_provider {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "kms")] {
+                    let driver = self
+                        .kms(provider)
+                        .ok_or_else(|| Error::Unsupported(format!("no KMS named {provider} to get resources")))?;
+                    return driver
+                        .get_secret(&resource_uri.resource_path(), &HashMap::new())
+                        .await;
+                } else {
+                    bail!(Error::Unsupported(format!(
+                        "cannot get resources from KMS {provider}, because feature `kms` of hub is disabled"
+                    )))
+                }
+            }
+        }
+
+        #[cfg(feature = "kbs")]
+        if let Some(kbs_client) = &self.kbs_client {
+            let resource = kbs_client.get_resource(resource_uri).await?;
+            return Ok(resource);
+        }
+
+        let _ = resource_uri;
+        Err(Error::Unsupported(
+            "GetResource needs a KBS or `resource_provider` to be configured".into(),
+        )
+        .into())
+    }
+}
+
+#[cfg(all(test, feature = "kms"))]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::Path};
+
+    use serde_json::json;
+    use tonic::Code;
+
+    use crate::{error::code, HubConfig};
+
+    use super::DataHub;
+
+    /// A KMS plugin whose secrets are their own names.
+    const ECHO_PLUGIN: &str = r#"#!/bin/sh
+while read -r req; do
+    id=$(echo "$req" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+    name=$(echo "$req" | sed -n 's/.*"name":"\([^"]*\)".*/\1/p')
+    case "$req" in
+    *'"init"'*) echo "{\"id\":$id,\"result\":{\"version\":1}}" ;;
+    *) echo "{\"id\":$id,\"result\":{\"secret\":\"$(printf %s "$name" | base64)\"}}" ;;
+    esac
+done
+"#;
+
+    /// A DataHub without KBS, with the KMS plugin `echo`.
+    async fn kms_only_hub(dir: &Path, resource_provider: Option<&str>) -> DataHub {
+        let path = dir.join("plugin");
+        std::fs::write(&path, ECHO_PLUGIN).expect("write plugin failed");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("chmod plugin failed");
+        let config: HubConfig = serde_json::from_value(json!({
+            "kms_plugins": { "echo": { "path": path } },
+            "resource_provider": resource_provider,
+        }))
+        .expect("parse config failed");
+        DataHub::start(&config).await.expect("start hub failed")
+    }
+
+    #[tokio::test]
+    async fn get_resource_from_kms() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let hub = kms_only_hub(dir.path(), Some("echo")).await;
+
+        let resource = hub
+            .get_resource("\"kbs:///default/key/1\"".into())
             .await
-            .get_resource(resource_uri)
-            .await?;
-        Ok(resource)
+            .expect("get resource failed");
+        assert_eq!(resource, b"default/key/1");
+    }
+
+    #[tokio::test]
+    async fn kms_only_hub_rejects_kbs() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let hub = kms_only_hub(dir.path(), None).await;
+
+        let err = hub
+            .get_resource("\"kbs:///default/key/1\"".into())
+            .await
+            .expect_err("get resource should fail");
+        assert_eq!(code(&err), Code::Unimplemented);
+
+        let secret = serde_json::from_value(json!({
+            "version": "0.1.0",
+            "type": "Vault",
+            "provider": "kbs",
+            "name": "kbs:///default/key/1",
+            "annotations": {}
+        }))
+        .expect("parse secret failed");
+        let err = hub
+            .unseal_secret(secret)
+            .await
+            .expect_err("unseal should fail");
+        assert_eq!(code(&err), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn wrap_type_with_kms() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let hub = kms_only_hub(dir.path(), None).await;
+
+        let parameters = json!({
+            "provider": "echo",
+            "kid": "key-1",
+            "packet": "v2",
+            "wrap_type": "C20P",
+        });
+        let err = hub
+            .wrap_key(parameters.to_string().as_bytes(), b"lek")
+            .await
+            .expect_err("wrap should fail");
+        assert_eq!(code(&err), Code::InvalidArgument);
+
+        let parameters = json!({
+            "provider": "kbs",
+            "kid": "kbs:///default/key/1",
+            "wrap_type": "A128GCM",
+        });
+        let err = hub
+            .wrap_key(parameters.to_string().as_bytes(), b"lek")
+            .await
+            .expect_err("wrap should fail");
+        assert_eq!(code(&err), Code::InvalidArgument);
+    }
+
+    #[cfg(feature = "kbs")]
+    #[tokio::test]
+    async fn invalidate_cached_resource() {
+        let kbs = mock_kbs::MockKbs::start().expect("start mock kbs failed");
+        kbs.set_resource("default/key/1", b"1".to_vec());
+        let config: HubConfig = serde_json::from_value(json!({
+            "evidence_provider": "sample",
+            "kbs": { "url": kbs.url(), "cache": {} },
+        }))
+        .expect("parse config failed");
+        let hub = DataHub::start(&config).await.expect("start hub failed");
+
+        let uri = "\"kbs:///default/key/1\"";
+        // Each step optionally invalidates the cache, then gets the resource
+        // and checks the requests received by the KBS so far.
+        for (invalidate, requests) in [(None, 1), (None, 1), (Some(Some(uri)), 2), (Some(None), 3)]
+        {
+            if let Some(uri) = invalidate {
+                hub.invalidate_resource(uri.map(String::from))
+                    .expect("invalidate failed");
+            }
+            let resource = hub
+                .get_resource(uri.into())
+                .await
+                .expect("get resource failed");
+            assert_eq!(resource, b"1");
+            assert_eq!(kbs.requests(mock_kbs::Endpoint::Resource), requests);
+        }
+
+        let err = hub
+            .invalidate_resource(Some("kbs:///default/key/1".into()))
+            .expect_err("invalidate should fail");
+        assert_eq!(code(&err), Code::InvalidArgument);
     }
 }
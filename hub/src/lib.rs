@@ -0,0 +1,21 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Confidential DataHub, which serves the sealed secrets, image layer keys
+//! and KBS resources to the guest.
+
+pub mod args;
+pub mod config;
+
+pub mod error;
+pub use error::Error;
+
+pub mod hub;
+pub use hub::*;
+
+pub mod service;
+
+pub use args::*;
+pub use config::*;
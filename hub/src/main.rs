@@ -6,17 +6,8 @@
 use anyhow::*;
 
 use clap::Parser;
+use confidential_datahub::{service::Server, Args, HubConfig};
 use log::{error, info};
-use service::Server;
-
-pub mod args;
-
-pub mod hub;
-pub use hub::*;
-
-pub mod service;
-
-pub use args::*;
 
 #[tokio::main]
 async fn main() {
@@ -33,7 +24,8 @@ async fn main() {
 
 async fn real_main() -> Result<()> {
     let args = Args::parse();
+    let config = HubConfig::from_args(&args)?;
 
-    let server = Server::new(args).await?;
+    let server = Server::new(config).await?;
     server.serve().await
 }
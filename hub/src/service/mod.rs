@@ -10,7 +10,7 @@ use std::sync::Arc;
 use anyhow::*;
 use tonic::transport::Server as TonicServer;
 
-use crate::{Args, DataHub};
+use crate::{DataHub, HubConfig, Service};
 
 use self::services::{
     getresource::getresource_proto::get_resource_service_server::GetResourceServiceServer,
@@ -20,24 +20,30 @@ use self::services::{
 
 pub struct Server {
     core: DataHub,
-    args: Args,
+    config: HubConfig,
 }
 
 impl Server {
-    pub async fn new(args: Args) -> Result<Self> {
-        let core = DataHub::start(args.kbs_addr.clone())
-            .await
-            .context("launch datahub")?;
-        Ok(Self { core, args })
+    pub async fn new(config: HubConfig) -> Result<Self> {
+        let core = DataHub::start(&config).await.context("launch datahub")?;
+        Ok(Self { core, config })
     }
 
     pub async fn serve(self) -> Result<()> {
-        let socket = self.args.socket;
+        let socket = self
+            .config
+            .socket
+            .ok_or_else(|| anyhow!("config key `socket` is required to serve"))?;
+        let enabled = |service| self.config.services.contains(&service);
+        let keyprovider = enabled(Service::KeyProvider);
+        let getresource = enabled(Service::GetResource);
+        let sealed_secret = enabled(Service::SealedSecret);
+
         let s = Arc::new(self);
         TonicServer::builder()
-            .add_service(KeyProviderServiceServer::new(s.clone()))
-            .add_service(GetResourceServiceServer::new(s.clone()))
-            .add_service(SealedSecretServiceServer::new(s))
+            .add_optional_service(keyprovider.then(|| KeyProviderServiceServer::new(s.clone())))
+            .add_optional_service(getresource.then(|| GetResourceServiceServer::new(s.clone())))
+            .add_optional_service(sealed_secret.then(|| SealedSecretServiceServer::new(s.clone())))
             .serve(socket)
             .await?;
 
@@ -8,10 +8,11 @@ use std::sync::Arc;
 use log::{debug, error};
 use tonic::{Response, Status};
 
-use crate::service::Server;
+use crate::{error::to_status, service::Server};
 
 use self::getresource_proto::{
     get_resource_service_server::GetResourceService, GetResourceRequest, GetResourceResponse,
+    InvalidateResourceRequest, InvalidateResourceResponse,
 };
 
 pub mod getresource_proto {
@@ -37,8 +38,8 @@ impl GetResourceService for Arc<Server> {
             .get_resource(req.resource_path)
             .await
             .map_err(|e| {
-                error!("Call CDH to get resource failed: {}", e);
-                Status::internal(format!("[ERROR] CDH get resource failed: {e}",))
+                error!("Call CDH to get resource failed: {:#}", e);
+                to_status("CDH get resource", &e)
             })?;
 
         debug!("Resource retrieved.");
@@ -48,4 +49,20 @@ impl GetResourceService for Arc<Server> {
 
         Ok(Response::new(reply))
     }
+
+    async fn invalidate_resource(
+        &self,
+        request: tonic::Request<InvalidateResourceRequest>,
+    ) -> Result<Response<InvalidateResourceResponse>, Status> {
+        debug!("The InvalidateResource API is called...");
+
+        let req = request.into_inner();
+        let uri = (!req.resource_path.is_empty()).then_some(req.resource_path);
+        self.core.invalidate_resource(uri).map_err(|e| {
+            error!("Call CDH to invalidate resource failed: {:#}", e);
+            to_status("CDH invalidate resource", &e)
+        })?;
+
+        Ok(Response::new(InvalidateResourceResponse {}))
+    }
 }
@@ -9,54 +9,121 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str;
 use std::vec::Vec;
+use strum::EnumString;
+
+use crate::Error;
 
 const ANNOTATION_KEY_NAME: &str = "attestation-agent";
 
+/// Decode a base64 encoded field of the request.
+fn decode(value: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| Error::InvalidRequest(format!("Illegal base64 encoding: {e}")).into())
+}
+
+/// Operations of ocicrypt's keyprovider protocol
+#[derive(EnumString, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operation {
+    #[strum(serialize = "keywrap")]
+    KeyWrap,
+
+    #[strum(serialize = "keyunwrap")]
+    KeyUnwrap,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
 pub struct KeyProviderInput {
     // Operation is either "keywrap" or "keyunwrap"
-    // attestation-agent can only handle the case of "keyunwrap"
     op: String,
-    // For attestation-agent, keywrapparams should be empty.
+    // For "keyunwrap", keywrapparams should be empty.
     pub keywrapparams: KeyWrapParams,
     pub keyunwrapparams: KeyUnwrapParams,
 }
 
 impl KeyProviderInput {
+    pub fn op(&self) -> Result<Operation> {
+        Operation::try_from(&self.op[..])
+            .map_err(|_| Error::InvalidRequest(format!("Unknown operation {}", self.op)).into())
+    }
+
+    /// Get the AnnotationPacket to unwrap. ocicrypt gives it base64 encoded
+    /// as `keyunwrapparams.annotation`, while the legacy CDH clients give it
+    /// as the `attestation-agent` parameter of `keyunwrapparams.dc`.
     pub fn get_annotation(&self) -> Result<Vec<u8>> {
         let annotation_base64 = self
             .keyunwrapparams
-            .dc
+            .annotation
             .as_ref()
-            .and_then(|dc| dc.parameters.get(ANNOTATION_KEY_NAME))
-            .and_then(|paras| paras.get(0))
-            .ok_or_else(|| anyhow!("Illegal UnwrapKey request: no AnnotationPacket given."))?;
+            .filter(|annotation| !annotation.is_empty())
+            .or_else(|| {
+                self.keyunwrapparams
+                    .dc
+                    .as_ref()
+                    .and_then(|dc| dc.parameters.get(ANNOTATION_KEY_NAME))
+                    .and_then(|paras| paras.first())
+            })
+            .ok_or_else(|| {
+                Error::InvalidRequest(
+                    "Illegal UnwrapKey request: no AnnotationPacket given.".into(),
+                )
+            })?;
 
-        let engine = base64::engine::general_purpose::STANDARD;
-        let annotation = engine.decode(annotation_base64)?;
+        let annotation = decode(annotation_base64)?;
         Ok(annotation)
     }
+
+    /// Get the parameters to wrap the key, which is given as the value of
+    /// `provider:attestation-agent:<parameters>` in ocicrypt.
+    pub fn get_wrap_parameters(&self) -> Result<Vec<u8>> {
+        let parameters_base64 = self
+            .keywrapparams
+            .ec
+            .as_ref()
+            .and_then(|ec| ec.parameters.get(ANNOTATION_KEY_NAME))
+            .and_then(|paras| paras.first())
+            .ok_or_else(|| {
+                Error::InvalidRequest("Illegal WrapKey request: no wrap parameters given.".into())
+            })?;
+
+        let parameters = decode(parameters_base64)?;
+        Ok(parameters)
+    }
+
+    /// Get the private options data to be wrapped, s.t. the LEK.
+    pub fn get_optsdata(&self) -> Result<Vec<u8>> {
+        let optsdata_base64 = self.keywrapparams.optsdata.as_ref().ok_or_else(|| {
+            Error::InvalidRequest("Illegal WrapKey request: no optsdata given.".into())
+        })?;
+
+        let optsdata = decode(optsdata_base64)?;
+        Ok(optsdata)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
 pub struct KeyWrapParams {
-    // For attestation-agent, ec is null
+    // For "keyunwrap", ec is null
     pub ec: Option<Ec>,
-    // For attestation-agent, optsdata is null
+    // For "keyunwrap", optsdata is null.
+    // Values are expected to be base-64 encoded.
     pub optsdata: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
 pub struct Ec {
+    // Name is expected to be "attestation-agent".
+    // Values are expected to be base-64 encoded.
     #[serde(rename = "Parameters")]
     pub parameters: HashMap<String, Vec<String>>,
-    #[serde(rename = "DecryptConfig")]
+    #[serde(rename = "DecryptConfig", default)]
     pub decrypt_config: Dc,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
 pub struct KeyUnwrapParams {
     pub dc: Option<Dc>,
+    // The base-64 encoded AnnotationPacket, as given by ocicrypt.
     pub annotation: Option<String>,
 }
 
@@ -87,3 +154,59 @@ pub struct KeyUnwrapOutput {
 pub struct KeyUnwrapResults {
     pub optsdata: Vec<u8>,
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::{KeyProviderInput, Operation};
+
+    #[test]
+    fn parse_keywrap_input() {
+        let input = r#"{
+            "op": "keywrap",
+            "keywrapparams": {
+                "ec": {
+                    "Parameters": {
+                        "attestation-agent": ["eyJwcm92aWRlciI6ImticyJ9"]
+                    },
+                    "DecryptConfig": {
+                        "Parameters": {}
+                    }
+                },
+                "optsdata": "bGVr"
+            },
+            "keyunwrapparams": {
+                "dc": null,
+                "annotation": null
+            }
+        }"#;
+
+        let input: KeyProviderInput = serde_json::from_str(input).expect("parse input failed");
+        assert_eq!(input.op().expect("parse op failed"), Operation::KeyWrap);
+        let parameters = input.get_wrap_parameters().expect("get parameters failed");
+        assert_eq!(parameters, br#"{"provider":"kbs"}"#);
+        let optsdata = input.get_optsdata().expect("get optsdata failed");
+        assert_eq!(optsdata, b"lek");
+        assert!(input.get_annotation().is_err());
+    }
+
+    #[rstest]
+    // ocicrypt, whose decrypt config is not an AnnotationPacket
+    #[case(r#"{"dc": {"Parameters": {"attestation-agent": ["e30="]}}, "annotation": "YW5ub3RhdGlvbg=="}"#)]
+    // legacy CDH clients
+    #[case(r#"{"dc": {"Parameters": {"attestation-agent": ["YW5ub3RhdGlvbg=="]}}, "annotation": null}"#)]
+    #[case(
+        r#"{"dc": {"Parameters": {"attestation-agent": ["YW5ub3RhdGlvbg=="]}}, "annotation": ""}"#
+    )]
+    fn parse_keyunwrap_input(#[case] keyunwrapparams: &str) {
+        let input = format!(
+            r#"{{"op": "keyunwrap", "keywrapparams": {{}}, "keyunwrapparams": {keyunwrapparams}}}"#
+        );
+
+        let input: KeyProviderInput = serde_json::from_str(&input).expect("parse input failed");
+        assert_eq!(input.op().expect("parse op failed"), Operation::KeyUnwrap);
+        let annotation = input.get_annotation().expect("get annotation failed");
+        assert_eq!(annotation, b"annotation");
+    }
+}
@@ -11,9 +11,16 @@ use anyhow::*;
 use log::{debug, error};
 use tonic::{Request, Response, Status};
 
-use crate::service::{
-    services::keyprovider::message::{KeyProviderInput, KeyUnwrapOutput, KeyUnwrapResults},
-    Server,
+use crate::{
+    error::to_status,
+    service::{
+        services::keyprovider::message::{
+            KeyProviderInput, KeyUnwrapOutput, KeyUnwrapResults, KeyWrapOutput, KeyWrapResults,
+            Operation,
+        },
+        Server,
+    },
+    DataHub, Error,
 };
 
 use self::keyprovider_proto::{
@@ -25,17 +32,85 @@ pub mod keyprovider_proto {
     tonic::include_proto!("keyprovider");
 }
 
+/// Parse the JSON encoded [`KeyProviderInput`] of ocicrypt.
+pub fn parse_input(input: &[u8]) -> Result<KeyProviderInput> {
+    serde_json::from_slice(input)
+        .map_err(|e| Error::InvalidRequest(format!("parse KeyProviderInput failed: {e}")).into())
+}
+
+/// Handle a request of ocicrypt's keyprovider protocol, either "keywrap"
+/// or "keyunwrap", and return the serialized output.
+pub async fn handle(core: &DataHub, input: &KeyProviderInput) -> Result<Vec<u8>> {
+    match input.op()? {
+        Operation::KeyWrap => wrap_key(core, input).await,
+        Operation::KeyUnwrap => unwrap_key(core, input).await,
+    }
+}
+
+/// Handle a "keywrap" request of ocicrypt's keyprovider protocol, and
+/// return the serialized [`KeyWrapOutput`].
+pub async fn wrap_key(core: &DataHub, input: &KeyProviderInput) -> Result<Vec<u8>> {
+    let parameters = input.get_wrap_parameters()?;
+    let optsdata = input.get_optsdata()?;
+
+    debug!("Call CDH to encrypt...");
+    let annotation = core.wrap_key(&parameters, &optsdata).await?;
+
+    debug!("Wrap key successfully, get the AnnotationPacket");
+
+    let output_struct = KeyWrapOutput {
+        keywrapresults: KeyWrapResults { annotation },
+    };
+    serde_json::to_vec(&output_struct).context("serialize KeyWrapOutput failed")
+}
+
+/// Handle a "keyunwrap" request of ocicrypt's keyprovider protocol, and
+/// return the serialized [`KeyUnwrapOutput`].
+pub async fn unwrap_key(core: &DataHub, input: &KeyProviderInput) -> Result<Vec<u8>> {
+    let annotation = input.get_annotation()?;
+
+    debug!("Call CDH to decrypt...");
+    let decrypted_optsdata = core.unwrap_key(&annotation).await?;
+
+    debug!("Provide key successfully, get the plain PLBCO");
+
+    let output_struct = KeyUnwrapOutput {
+        keyunwrapresults: KeyUnwrapResults {
+            optsdata: decrypted_optsdata,
+        },
+    };
+    serde_json::to_vec(&output_struct).context("serialize KeyUnwrapOutput failed")
+}
+
 #[tonic::async_trait]
 impl KeyProviderService for Arc<Server> {
     async fn wrap_key(
         &self,
-        _request: Request<KeyProviderKeyWrapProtocolInput>,
+        request: Request<KeyProviderKeyWrapProtocolInput>,
     ) -> Result<Response<KeyProviderKeyWrapProtocolOutput>, Status> {
         debug!("The WrapKey API is called...");
-        debug!("WrapKey API is unimplemented!");
-        Err(Status::unimplemented(
-            "WrapKey API is unimplemented!".to_string(),
-        ))
+
+        // Deserialize and parse the gRPC input to get the wrap parameters and the key.
+        let key_provider_input: KeyProviderInput =
+            serde_json::from_slice(&request.into_inner().key_provider_key_wrap_protocol_input)
+                .map_err(|e| {
+                    error!("Parse request failed: {}", e);
+                    Status::invalid_argument(format!("[ERROR] Parse request failed: {e}",))
+                })?;
+
+        let output = wrap_key(&self.core, &key_provider_input)
+            .await
+            .map_err(|e| {
+                error!("Call CDH to wrap key failed: {:#}", e);
+                to_status("CDH key provider", &e)
+            })?;
+
+        let reply = KeyProviderKeyWrapProtocolOutput {
+            key_provider_key_wrap_protocol_output: output,
+        };
+        debug!("Reply successfully!");
+
+        Result::Ok(Response::new(reply))
     }
 
     async fn un_wrap_key(
@@ -49,39 +124,15 @@ impl KeyProviderService for Arc<Server> {
             serde_json::from_slice(&request.into_inner().key_provider_key_wrap_protocol_input)
                 .map_err(|e| {
                     error!("Parse request failed: {}", e);
-                    Status::internal(format!("[ERROR] Parse request failed: {e}",))
+                    Status::invalid_argument(format!("[ERROR] Parse request failed: {e}",))
                 })?;
 
-        debug!("Call CDH to decrypt...");
-
-        let annotation = key_provider_input.get_annotation().map_err(|e| {
-            error!("Parse request failed: {}", e);
-            Status::internal(format!("[ERROR] Parse request failed: {e}",))
-        })?;
-
-        let decrypted_optsdata = self.core.unwrap_key(&annotation).await.map_err(|e| {
-            error!("Call CDH to provide key failed: {}", e);
-            Status::internal(format!("[ERROR] CDH key provider failed: {e}",))
-        })?;
-
-        debug!("Provide key successfully, get the plain PLBCO");
-
-        // Construct output structure and serialize it as the return value of gRPC
-        let output_struct = KeyUnwrapOutput {
-            keyunwrapresults: KeyUnwrapResults {
-                optsdata: decrypted_optsdata,
-            },
-        };
-
-        let output = serde_json::to_string(&output_struct)
-            .unwrap()
-            .as_bytes()
-            .to_vec();
-
-        debug!(
-            "UnWrapKey API output: {}",
-            serde_json::to_string(&output_struct).unwrap()
-        );
+        let output = unwrap_key(&self.core, &key_provider_input)
+            .await
+            .map_err(|e| {
+                error!("Call CDH to provide key failed: {:#}", e);
+                to_status("CDH key provider", &e)
+            })?;
 
         let reply = KeyProviderKeyWrapProtocolOutput {
             key_provider_key_wrap_protocol_output: output,
@@ -91,3 +142,87 @@ impl KeyProviderService for Arc<Server> {
         Result::Ok(Response::new(reply))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "kms")]
+    use base64::Engine;
+    use rstest::rstest;
+    use serde_json::json;
+    use tonic::Code;
+
+    use crate::{error::code, DataHub, HubConfig};
+
+    #[cfg(feature = "kms")]
+    use super::message::{KeyUnwrapOutput, KeyWrapOutput};
+    use super::{handle, parse_input};
+
+    /// A DataHub without KBS, with the `local` KMS keeping its keys in `dir`.
+    async fn kms_only_hub(dir: &std::path::Path) -> DataHub {
+        let config: HubConfig = serde_json::from_value(json!({
+            "kms": { "local": { "dir": dir } },
+        }))
+        .expect("parse config failed");
+        DataHub::start(&config).await.expect("start hub failed")
+    }
+
+    #[cfg(feature = "kms")]
+    #[tokio::test]
+    async fn wrap_unwrap() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let hub = kms_only_hub(dir.path()).await;
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        let parameters = json!({ "provider": "local", "kid": "key-1" });
+        let input = json!({
+            "op": "keywrap",
+            "keywrapparams": {
+                "ec": { "Parameters": { "attestation-agent": [engine.encode(parameters.to_string())] } },
+                "optsdata": engine.encode(b"layer encryption key"),
+            },
+            "keyunwrapparams": {},
+        });
+        let input = parse_input(input.to_string().as_bytes()).expect("parse input failed");
+        let output = handle(&hub, &input).await.expect("wrap failed");
+        let output: KeyWrapOutput = serde_json::from_slice(&output).expect("parse output failed");
+
+        // ocicrypt returns the AnnotationPacket of WrapKey as the
+        // `annotation` of UnWrapKey.
+        let input = json!({
+            "op": "keyunwrap",
+            "keywrapparams": {},
+            "keyunwrapparams": {
+                "dc": { "Parameters": {} },
+                "annotation": engine.encode(output.keywrapresults.annotation),
+            },
+        });
+        let input = parse_input(input.to_string().as_bytes()).expect("parse input failed");
+        let output = handle(&hub, &input).await.expect("unwrap failed");
+        let output: KeyUnwrapOutput = serde_json::from_slice(&output).expect("parse output failed");
+        assert_eq!(output.keyunwrapresults.optsdata, b"layer encryption key");
+    }
+
+    #[rstest]
+    #[case(r#"{"op": "keywrap""#)]
+    #[case(r#"{"op": "keysign", "keywrapparams": {}, "keyunwrapparams": {}}"#)]
+    #[case(r#"{"op": "keywrap", "keywrapparams": {"optsdata": "bGVr"}, "keyunwrapparams": {}}"#)]
+    #[case(r#"{"op": "keyunwrap", "keywrapparams": {}, "keyunwrapparams": {}}"#)]
+    #[case(
+        r#"{"op": "keyunwrap", "keywrapparams": {}, "keyunwrapparams": {"dc": {"Parameters": {"attestation-agent": ["%%%"]}}}}"#
+    )]
+    #[case(
+        r#"{"op": "keyunwrap", "keywrapparams": {}, "keyunwrapparams": {"dc": {"Parameters": {"attestation-agent": ["e30="]}}}}"#
+    )]
+    #[case(r#"{"op": "keyunwrap", "keywrapparams": {}, "keyunwrapparams": {"annotation": "%%%"}}"#)]
+    #[tokio::test]
+    async fn invalid_input(#[case] input: &str) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let hub = kms_only_hub(dir.path()).await;
+
+        let err = match parse_input(input.as_bytes()) {
+            Ok(input) => handle(&hub, &input).await.expect_err("request should fail"),
+            Err(e) => e,
+        };
+        assert_eq!(code(&err), Code::InvalidArgument, "{err:#}");
+    }
+}
@@ -9,7 +9,7 @@ use anyhow::Context;
 use log::{debug, error};
 use tonic::{Response, Status};
 
-use crate::service::Server;
+use crate::{error::to_status, service::Server};
 
 use self::keyprovider::{
     sealed_secret_service_server::SealedSecretService, UnSealSecretInput, UnSealSecretOutput,
@@ -31,14 +31,14 @@ impl SealedSecretService for Arc<Server> {
         let secret = serde_json::from_slice(&secret.secret)
             .context("parse SealedSecret")
             .map_err(|e| {
-                error!("Parse request failed: {}", e);
-                Status::internal(format!("[ERROR] Parse request failed: {e}",))
+                error!("Parse request failed: {:#}", e);
+                Status::invalid_argument(format!("[ERROR] Parse request failed: {e:#}",))
             })?;
 
         debug!("Starting to unseal...");
         let plaintext = self.core.unseal_secret(secret).await.map_err(|e| {
-            error!("Unseal Secret failed: {}", e);
-            Status::internal(format!("[ERROR] Unseal Secret failed: {e}",))
+            error!("Unseal Secret failed: {:#}", e);
+            to_status("Unseal Secret", &e)
         })?;
 
         debug!("Unsealing succeeded.");
@@ -0,0 +1,100 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Tests of the `cdh-keyprovider` binary, fed by stdin like ocicrypt does.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+#[cfg(feature = "kms")]
+use base64::Engine;
+use serde_json::json;
+#[cfg(feature = "kms")]
+use serde_json::Value;
+
+/// Run `cdh-keyprovider` with a KMS-only configuration in `dir`, writing
+/// `input` to its stdin.
+fn keyprovider(dir: &Path, input: &[u8]) -> Output {
+    let config = dir.join("config.json");
+    let kms_dir = dir.join("kms");
+    std::fs::write(
+        &config,
+        json!({ "kms": { "local": { "dir": kms_dir } } }).to_string(),
+    )
+    .expect("write config failed");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cdh-keyprovider"))
+        .arg("--config")
+        .arg(&config)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("launch cdh-keyprovider failed");
+    child
+        .stdin
+        .take()
+        .expect("no stdin")
+        .write_all(input)
+        .expect("write stdin failed");
+    child
+        .wait_with_output()
+        .expect("wait cdh-keyprovider failed")
+}
+
+#[cfg(feature = "kms")]
+#[test]
+fn wrap_unwrap() {
+    let dir = tempfile::tempdir().expect("create temp dir failed");
+    let engine = base64::engine::general_purpose::STANDARD;
+
+    let parameters = json!({ "provider": "local", "kid": "key-1" });
+    let input = json!({
+        "op": "keywrap",
+        "keywrapparams": {
+            "ec": { "Parameters": { "attestation-agent": [engine.encode(parameters.to_string())] } },
+            "optsdata": engine.encode(b"layer encryption key"),
+        },
+        "keyunwrapparams": {},
+    });
+    let output = keyprovider(dir.path(), input.to_string().as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    let output: Value = serde_json::from_slice(&output.stdout).expect("parse output failed");
+    let annotation: Vec<u8> =
+        serde_json::from_value(output["keywrapresults"]["annotation"].clone())
+            .expect("parse annotation failed");
+
+    // Unwrap the way ocicrypt does, giving the AnnotationPacket as the
+    // `annotation` and the decrypt config as `dc`.
+    let input = json!({
+        "op": "keyunwrap",
+        "keywrapparams": { "ec": null, "optsdata": null },
+        "keyunwrapparams": {
+            "dc": { "Parameters": { "attestation-agent": [] } },
+            "annotation": engine.encode(annotation),
+        },
+    });
+    let output = keyprovider(dir.path(), input.to_string().as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    let output: Value = serde_json::from_slice(&output.stdout).expect("parse output failed");
+    assert_eq!(
+        output["keyunwrapresults"]["optsdata"],
+        json!(b"layer encryption key")
+    );
+}
+
+#[test]
+fn invalid_input() {
+    let dir = tempfile::tempdir().expect("create temp dir failed");
+
+    let output = keyprovider(dir.path(), b"not json");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("parse KeyProviderInput failed"), "{stderr}");
+}
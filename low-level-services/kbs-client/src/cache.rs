@@ -0,0 +1,192 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! An in-memory cache of the resources got from the KBSes. The bytes of a
+//! resource are zeroized when it is evicted, invalidated or expires.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use zeroize::Zeroizing;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// How long a resource stays in the cache after being fetched
+    pub ttl: Duration,
+
+    /// Maximum number of the cached resources
+    pub max_entries: usize,
+
+    /// Maximum total size in bytes of the cached resources. A resource
+    /// larger than this is never cached.
+    pub max_size: usize,
+}
+
+/// A resource is cached by the URL of its KBS and its path, so that the
+/// resource URIs naming the same KBS by different addresses share an entry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceKey {
+    pub kbs_url: String,
+    pub resource_path: String,
+}
+
+struct Entry {
+    content: Zeroizing<Vec<u8>>,
+    expires_at: Instant,
+}
+
+pub struct ResourceCache {
+    config: CacheConfig,
+    entries: HashMap<ResourceKey, Entry>,
+    size: usize,
+}
+
+impl ResourceCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Get the cached resource of `key` if it has not expired.
+    pub fn get(&mut self, key: &ResourceKey) -> Option<Vec<u8>> {
+        let expired = self.entries.get(key)?.expires_at <= Instant::now();
+        if expired {
+            self.invalidate(key);
+            return None;
+        }
+
+        self.entries.get(key).map(|entry| entry.content.to_vec())
+    }
+
+    /// Cache the resource of `key`. Entries are evicted in the order of
+    /// expiration to keep the cache within the limits.
+    pub fn insert(&mut self, key: ResourceKey, content: &[u8]) {
+        self.invalidate(&key);
+        if content.len() > self.config.max_size || self.config.max_entries == 0 {
+            return;
+        }
+
+        self.remove_expired();
+        while self.entries.len() >= self.config.max_entries
+            || self.size + content.len() > self.config.max_size
+        {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.invalidate(&oldest);
+        }
+
+        self.size += content.len();
+        self.entries.insert(
+            key,
+            Entry {
+                content: Zeroizing::new(content.to_vec()),
+                expires_at: Instant::now() + self.config.ttl,
+            },
+        );
+    }
+
+    /// Remove the resource of `key` from the cache.
+    pub fn invalidate(&mut self, key: &ResourceKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.content.len();
+        }
+    }
+
+    /// Remove all the resources from the cache.
+    pub fn invalidate_all(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<ResourceKey> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.invalidate(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CacheConfig, ResourceCache, ResourceKey};
+
+    fn uri(tag: &str) -> ResourceKey {
+        ResourceKey {
+            kbs_url: "http://127.0.0.1:8080".into(),
+            resource_path: format!("default/key/{tag}"),
+        }
+    }
+
+    fn cache(ttl: Duration) -> ResourceCache {
+        ResourceCache::new(CacheConfig {
+            ttl,
+            max_entries: 2,
+            max_size: 8,
+        })
+    }
+
+    #[test]
+    fn expire() {
+        let mut cache = cache(Duration::from_millis(50));
+        cache.insert(uri("1"), b"1111");
+        assert_eq!(cache.get(&uri("1")), Some(b"1111".to_vec()));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.get(&uri("1")), None);
+        assert_eq!(cache.size, 0);
+    }
+
+    #[test]
+    fn evict_over_limits() {
+        let mut cache = cache(Duration::from_secs(60));
+        cache.insert(uri("1"), b"1");
+        cache.insert(uri("2"), b"2");
+        cache.insert(uri("3"), b"3");
+        assert_eq!(cache.get(&uri("1")), None);
+        assert_eq!(cache.get(&uri("3")), Some(b"3".to_vec()));
+
+        // Exceed the size limit, so both the other entries are evicted.
+        cache.insert(uri("4"), b"44444444");
+        assert_eq!(cache.get(&uri("2")), None);
+        assert_eq!(cache.get(&uri("3")), None);
+        assert_eq!(cache.get(&uri("4")), Some(b"44444444".to_vec()));
+
+        // Too large to be cached.
+        cache.insert(uri("5"), b"555555555");
+        assert_eq!(cache.get(&uri("5")), None);
+        assert_eq!(cache.size, 8);
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut cache = cache(Duration::from_secs(60));
+        cache.insert(uri("1"), b"1");
+        cache.insert(uri("2"), b"2");
+        cache.invalidate(&uri("1"));
+        assert_eq!(cache.get(&uri("1")), None);
+        assert_eq!(cache.get(&uri("2")), Some(b"2".to_vec()));
+        cache.invalidate_all();
+        assert_eq!(cache.get(&uri("2")), None);
+        assert_eq!(cache.size, 0);
+    }
+}
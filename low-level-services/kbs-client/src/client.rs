@@ -3,33 +3,403 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use anyhow::*;
-use kbs_protocol::client::Handshaker;
+use kbs_protocol::{
+    client::Handshaker, evidence_provider::EvidenceProvider, tee_pubkey::TeeKeyAlgorithm,
+};
+use log::info;
 use resource_uri::ResourceUri;
+use tokio::sync::OnceCell;
+
+use crate::{
+    cache::{CacheConfig, ResourceCache, ResourceKey},
+    Error,
+};
 
+/// A pool of connections to KBSes. A KBS is attested to the first time a
+/// resource of it is requested, and the connection is reused later.
+///
+/// The client can be shared by concurrent requests, which only wait for each
+/// other when attesting to the same KBS.
 pub struct Client {
-    handshaker: Handshaker,
-    _token: String,
+    /// URL of the KBS used when the resource URI does not give the address
+    /// of a KBS.
+    default_kbs_url: url::Url,
+
+    /// URLs of the KBSes keyed by the address in the resource URIs.
+    hosts: HashMap<String, String>,
+
+    /// Whether the KBSes neither listed in `hosts` nor the default one can be
+    /// connected, with the scheme of the default KBS.
+    allow_unlisted_hosts: bool,
+
+    evidence_provider: Arc<dyn EvidenceProvider>,
+
+    timeout: Duration,
+
+    /// The TEE key algorithms offered to the KBSes in order of preference
+    tee_key_algorithms: Vec<TeeKeyAlgorithm>,
+
+    /// Attested connections keyed by the URL of the KBS. A connection is
+    /// initialized by the first request to the KBS.
+    handshakers: Mutex<HashMap<String, Arc<OnceCell<Handshaker>>>>,
+
+    /// Cache of the got resources, if enabled
+    cache: Option<Mutex<ResourceCache>>,
 }
 
 impl Client {
-    pub async fn new(kbs_host_url: String) -> Result<Self> {
-        let mut handshaker = Handshaker::new().await?;
-        let _token = handshaker.handshake(kbs_host_url).await?;
-        Ok(Self { handshaker, _token })
-    }
-
-    pub async fn new_with_handshaker(
-        mut handshaker: Handshaker,
+    /// Create a client with the default KBS `kbs_host_url`. The evidence
+    /// is got from the `evidence_provider`, and `timeout` is the timeout of
+    /// each request to the KBSes. No KBS is connected until a resource is
+    /// requested.
+    pub fn new(
         kbs_host_url: String,
+        evidence_provider: Arc<dyn EvidenceProvider>,
+        timeout: Duration,
     ) -> Result<Self> {
-        let _token = handshaker.handshake(kbs_host_url).await?;
-        Ok(Self { handshaker, _token })
+        let default_kbs_url = url::Url::parse(&kbs_host_url)
+            .with_context(|| format!("parse KBS url {kbs_host_url}"))?;
+        Ok(Self {
+            default_kbs_url,
+            hosts: HashMap::new(),
+            allow_unlisted_hosts: false,
+            evidence_provider,
+            timeout,
+            tee_key_algorithms: TeeKeyAlgorithm::defaults(),
+            handshakers: Mutex::new(HashMap::new()),
+            cache: None,
+        })
+    }
+
+    /// Set the URLs of the KBSes keyed by their address in the resource URIs,
+    /// e.g. `kbs.example.io` to `https://kbs.example.io:8443`.
+    pub fn with_hosts(mut self, hosts: HashMap<String, String>) -> Self {
+        self.hosts = hosts
+            .into_iter()
+            .map(|(kbs_addr, url)| {
+                let kbs_addr = self
+                    .addr_url(&kbs_addr)
+                    .map(|addr_url| normalized_kbs_addr(&addr_url))
+                    .unwrap_or(kbs_addr);
+                (kbs_addr, url)
+            })
+            .collect();
+        self
+    }
+
+    /// Allow the resource URIs to name any KBS, which is connected with the
+    /// scheme of the default KBS if not listed in the hosts. Only the
+    /// default KBS and the listed ones are connected by default, so that
+    /// the evidence is not sent to an arbitrary KBS named by a resource URI.
+    pub fn with_unlisted_hosts(mut self, allow: bool) -> Self {
+        self.allow_unlisted_hosts = allow;
+        self
+    }
+
+    /// Cache the got resources following `config`. The resources are not
+    /// cached by default.
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache = Some(Mutex::new(ResourceCache::new(config)));
+        self
+    }
+
+    /// Set the TEE key algorithms offered to the KBSes in order of
+    /// preference. All the algorithms are offered by default.
+    pub fn with_tee_key_algorithms(mut self, algorithms: Vec<TeeKeyAlgorithm>) -> Self {
+        self.tee_key_algorithms = algorithms;
+        self
+    }
+
+    /// Remove the resource of `resource_url` from the cache, so that it is
+    /// got from the KBS again next time.
+    pub fn invalidate(&self, resource_url: &ResourceUri) -> Result<()> {
+        if let Some(cache) = &self.cache {
+            let key = self.cache_key(resource_url)?;
+            lock(cache).invalidate(&key);
+        }
+
+        Ok(())
+    }
+
+    /// Remove all the resources from the cache.
+    pub fn invalidate_all(&self) {
+        if let Some(cache) = &self.cache {
+            lock(cache).invalidate_all();
+        }
+    }
+
+    /// Get the URL of the KBS with address `kbs_addr` in a resource URI.
+    /// The addresses are compared with the known default port of the
+    /// scheme filled in, e.g. `kbs.example.io:443` names the default KBS
+    /// `https://kbs.example.io`.
+    fn kbs_url(&self, kbs_addr: &str) -> Result<String> {
+        let default_kbs_url = || {
+            self.default_kbs_url
+                .as_str()
+                .trim_end_matches('/')
+                .to_string()
+        };
+        if kbs_addr.is_empty() {
+            return Ok(default_kbs_url());
+        }
+
+        let addr_url = self.addr_url(kbs_addr)?;
+        let normalized_addr = normalized_kbs_addr(&addr_url);
+        if let Some(url) = self.hosts.get(&normalized_addr) {
+            return Ok(url.trim_end_matches('/').to_string());
+        }
+
+        if normalized_addr == normalized_kbs_addr(&self.default_kbs_url) {
+            return Ok(default_kbs_url());
+        }
+
+        if !self.allow_unlisted_hosts {
+            return Err(Error::UnknownKbs(format!(
+                "KBS {kbs_addr} is neither the default KBS nor a listed host"
+            ))
+            .into());
+        }
+
+        Ok(addr_url.as_str().trim_end_matches('/').to_string())
+    }
+
+    /// Parse the KBS address `kbs_addr` of a resource URI as a URL with the
+    /// scheme of the default KBS, by which the unlisted KBSes are connected.
+    fn addr_url(&self, kbs_addr: &str) -> Result<url::Url> {
+        let url = format!("{}://{kbs_addr}", self.default_kbs_url.scheme());
+        url::Url::parse(&url).with_context(|| format!("parse KBS address {kbs_addr}"))
+    }
+
+    /// The key of the resource of `resource_url` in the cache
+    fn cache_key(&self, resource_url: &ResourceUri) -> Result<ResourceKey> {
+        Ok(ResourceKey {
+            kbs_url: self.kbs_url(&resource_url.kbs_addr)?,
+            resource_path: resource_url.resource_path(),
+        })
     }
 
-    /// Get the resource of the given KBS Resource URI.
-    /// We need to change the API when it is time to support multiple KBSes.
-    pub async fn get_resource(&mut self, resource_url: ResourceUri) -> Result<Vec<u8>> {
-        self.handshaker.get(resource_url, true).await
+    /// Get the resource of the given KBS Resource URI from the KBS it names,
+    /// or from the default KBS if it names none.
+    pub async fn get_resource(&self, resource_url: ResourceUri) -> Result<Vec<u8>> {
+        let key = self.cache_key(&resource_url)?;
+        if let Some(resource) = self.cache.as_ref().and_then(|cache| lock(cache).get(&key)) {
+            return Ok(resource);
+        }
+
+        let kbs_url = key.kbs_url.clone();
+        let handshaker = lock(&self.handshakers)
+            .entry(kbs_url.clone())
+            .or_default()
+            .clone();
+        let handshaker = handshaker
+            .get_or_try_init(|| async {
+                info!("attest to KBS {kbs_url}");
+                let handshaker = Handshaker::new(self.evidence_provider.clone(), self.timeout)
+                    .await?
+                    .with_tee_key_algorithms(self.tee_key_algorithms.clone());
+                handshaker
+                    .handshake(kbs_url.clone())
+                    .await
+                    .with_context(|| format!("attest to KBS {kbs_url}"))?;
+                Ok(handshaker)
+            })
+            .await?;
+
+        let resource = handshaker.get(resource_url, true).await?;
+        if let Some(cache) = &self.cache {
+            lock(cache).insert(key, &resource);
+        }
+
+        Ok(resource)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The address of the KBS of `url` in the resource URIs, i.e. `host:port`
+/// with the known default port of the scheme if no port is given.
+fn normalized_kbs_addr(url: &url::Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port_or_known_default() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use kbs_protocol::evidence_provider::SampleProvider;
+    use mock_kbs::{Endpoint, MockKbs};
+    use resource_uri::ResourceUri;
+    use rstest::rstest;
+
+    use crate::{CacheConfig, Error};
+
+    use super::Client;
+
+    fn client(default_kbs_url: &str) -> Client {
+        Client::new(
+            default_kbs_url.into(),
+            Arc::new(SampleProvider),
+            Duration::from_secs(5),
+        )
+        .expect("create client failed")
+    }
+
+    #[rstest]
+    #[case("", false, Some("http://127.0.0.1:8080"))]
+    #[case("127.0.0.1:8080", false, Some("http://127.0.0.1:8080"))]
+    #[case(
+        "other.example.io:8443",
+        false,
+        Some("https://other.example.io:8443/prefix")
+    )]
+    #[case("kbs.example.io", false, None)]
+    #[case("kbs.example.io", true, Some("http://kbs.example.io"))]
+    fn resolve_kbs_url(
+        #[case] kbs_addr: &str,
+        #[case] allow_unlisted_hosts: bool,
+        #[case] expected: Option<&str>,
+    ) {
+        let hosts = HashMap::from([(
+            "other.example.io:8443".to_string(),
+            "https://other.example.io:8443/prefix/".to_string(),
+        )]);
+        let client = client("http://127.0.0.1:8080/")
+            .with_hosts(hosts)
+            .with_unlisted_hosts(allow_unlisted_hosts);
+        match expected {
+            Some(expected) => assert_eq!(
+                client.kbs_url(kbs_addr).expect("resolve KBS url failed"),
+                expected
+            ),
+            None => {
+                let err = client.kbs_url(kbs_addr).unwrap_err();
+                assert!(matches!(err.downcast_ref(), Some(Error::UnknownKbs(_))));
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("kbs.example", Some("https://kbs.example"))]
+    #[case("kbs.example:443", Some("https://kbs.example"))]
+    #[case("kbs.example:8443", None)]
+    #[case("other.example", Some("https://other.example:8443"))]
+    #[case("other.example:443", Some("https://other.example:8443"))]
+    fn resolve_kbs_url_with_default_port(#[case] kbs_addr: &str, #[case] expected: Option<&str>) {
+        let hosts = HashMap::from([(
+            "other.example:443".to_string(),
+            "https://other.example:8443".to_string(),
+        )]);
+        let client = client("https://kbs.example").with_hosts(hosts);
+        match expected {
+            Some(expected) => {
+                assert_eq!(
+                    client.kbs_url(kbs_addr).expect("resolve KBS url failed"),
+                    expected
+                );
+
+                // The resources of the same KBS share the cache keys.
+                let uri = ResourceUri::try_from(&format!("kbs://{kbs_addr}/default/key/1")[..])
+                    .expect("parse resource uri failed");
+                let key = client.cache_key(&uri).expect("get cache key failed");
+                assert_eq!(key.kbs_url, expected);
+            }
+            None => {
+                let err = client.kbs_url(kbs_addr).unwrap_err();
+                assert!(matches!(err.downcast_ref(), Some(Error::UnknownKbs(_))));
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("kbs.example:80", "http://kbs.example")]
+    #[case("kbs.example:8080", "http://kbs.example:8080")]
+    fn resolve_unlisted_kbs_url(#[case] kbs_addr: &str, #[case] expected: &str) {
+        let client = client("http://127.0.0.1:8080").with_unlisted_hosts(true);
+        assert_eq!(
+            client.kbs_url(kbs_addr).expect("resolve KBS url failed"),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_unlisted_kbs() {
+        let default_kbs = MockKbs::start().expect("start mock kbs failed");
+        let other_kbs = MockKbs::start().expect("start mock kbs failed");
+        other_kbs.set_resource("default/key/1", b"other".to_vec());
+        let client = client(&default_kbs.url());
+
+        let other_addr = other_kbs.url().trim_start_matches("http://").to_string();
+        let uri = ResourceUri::try_from(&format!("kbs://{other_addr}/default/key/1")[..])
+            .expect("parse resource uri failed");
+        let err = client.get_resource(uri).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::UnknownKbs(_))));
+        assert_eq!(other_kbs.requests(Endpoint::Auth), 0);
+    }
+
+    #[tokio::test]
+    async fn get_resources_from_multiple_kbses() {
+        let default_kbs = MockKbs::start().expect("start mock kbs failed");
+        default_kbs.set_resource("default/key/1", b"default".to_vec());
+        let other_kbs = MockKbs::start().expect("start mock kbs failed");
+        other_kbs.set_resource("default/key/1", b"other".to_vec());
+        let other_addr = other_kbs.url().trim_start_matches("http://").to_string();
+        let hosts = HashMap::from([(other_addr.clone(), other_kbs.url())]);
+        let client = client(&default_kbs.url()).with_hosts(hosts);
+
+        for (kbs_addr, expected) in [("", "default"), (&other_addr[..], "other"), ("", "default")] {
+            let uri = ResourceUri::try_from(&format!("kbs://{kbs_addr}/default/key/1")[..])
+                .expect("parse resource uri failed");
+            let resource = client.get_resource(uri).await.expect("get resource failed");
+            assert_eq!(resource, expected.as_bytes());
+        }
+
+        // Each KBS is attested to only once.
+        assert_eq!(default_kbs.requests(Endpoint::Attest), 1);
+        assert_eq!(other_kbs.requests(Endpoint::Attest), 1);
+    }
+
+    #[tokio::test]
+    async fn get_cached_resources() {
+        let kbs = MockKbs::start().expect("start mock kbs failed");
+        kbs.set_resource("default/key/1", b"1".to_vec());
+        let client = client(&kbs.url()).with_cache(CacheConfig {
+            ttl: Duration::from_secs(60),
+            max_entries: 8,
+            max_size: 1024,
+        });
+
+        let uri = ResourceUri::try_from("kbs:///default/key/1").expect("parse resource uri failed");
+        for _ in 0..2 {
+            let resource = client
+                .get_resource(uri.clone())
+                .await
+                .expect("get resource failed");
+            assert_eq!(resource, b"1");
+        }
+        assert_eq!(kbs.requests(Endpoint::Resource), 1);
+
+        client.invalidate(&uri).expect("invalidate failed");
+        client.get_resource(uri).await.expect("get resource failed");
+        assert_eq!(kbs.requests(Endpoint::Resource), 2);
+
+        // The default KBS named by its address shares the cached resource.
+        let kbs_addr = kbs.url().trim_start_matches("http://").to_string();
+        let uri = ResourceUri::try_from(&format!("kbs://{kbs_addr}/default/key/1")[..])
+            .expect("parse resource uri failed");
+        client.get_resource(uri).await.expect("get resource failed");
+        assert_eq!(kbs.requests(Endpoint::Resource), 2);
     }
 }
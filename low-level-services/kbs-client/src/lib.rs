@@ -3,5 +3,16 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+pub mod cache;
+pub use cache::CacheConfig;
+
 pub mod client;
 pub use client::*;
+
+pub use kbs_protocol::{
+    attestation_agent_client::{Client as AaClient, AA_POD_DOMAIN},
+    client::KBS_REQ_TIMEOUT_SEC,
+    evidence_provider::{EvidenceProvider, SampleProvider},
+    tee_pubkey::TeeKeyAlgorithm,
+    Error,
+};
@@ -7,44 +7,135 @@
 
 use std::collections::HashMap;
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use zeroize::Zeroizing;
+
+use crate::Error;
 
 /// Annotations is extra information of this encryption/decryption.
 /// Because the fields are unknowned, we put them into a key-value map.
 type Annotations = HashMap<String, String>;
 
+/// The operations of a KMS driver. Each driver supports a part of them,
+/// reported by [`KMS::capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Capability {
+    Encrypt,
+    Decrypt,
+    GenerateDataKey,
+    Sign,
+    Verify,
+    GetPublicKey,
+    GetSecret,
+    SetSecret,
+    ListSecrets,
+    DeleteSecret,
+}
+
+/// A data key generated inside KMS.
+pub struct DataKey {
+    /// The plaintext of the key, which should be dropped once used.
+    pub plaintext: Zeroizing<Vec<u8>>,
+
+    /// The key encrypted by the key inside KMS. It can be decrypted by
+    /// [`KMS::decrypt`] with the same `keyid` and the `annotations`.
+    pub ciphertext: Vec<u8>,
+
+    pub annotations: Annotations,
+}
+
+/// The error of the operations not supported by the KMS `name`.
+fn unsupported(name: &str, capability: Capability) -> anyhow::Error {
+    Error::Unsupported {
+        driver: name.to_string(),
+        capability,
+    }
+    .into()
+}
+
+/// A KMS driver is shared by concurrent requests, so all the methods take
+/// `&self`. Drivers with mutable state should synchronize it internally.
 #[async_trait]
 pub trait KMS: Send + Sync {
     /// The name of this KMS.
     fn name(&self) -> &str;
 
+    /// The operations supported by this KMS. The other operations return
+    /// errors.
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![Capability::Decrypt, Capability::GetSecret]
+    }
+
+    /// Whether this KMS supports the `capability`.
+    fn supports(&self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+
     /// Use the key of `keyid` to encrypt the `data` slice inside KMS, and then
     /// return the ciphertext of the `data`. The encryption operation should occur
     /// inside KMS. This function only works as a wrapper for different KMS APIs.
     ///
     /// Extra parameters can be included in `annotations`.
-    async fn encrypt(&mut self, _data: &[u8], _keyid: &str) -> Result<(Vec<u8>, Annotations)> {
-        bail!("Unimplemented!")
+    async fn encrypt(&self, _data: &[u8], _keyid: &str) -> Result<(Vec<u8>, Annotations)> {
+        Err(unsupported(self.name(), Capability::Encrypt))
     }
 
     /// Use the key of `keyid` to decrypt the `ciphertext` slice inside KMS, and then
     /// return the plaintext of the `data`. The decryption operation should occur
     /// inside KMS. This function only works as a wrapper for different KMS APIs
     async fn decrypt(
-        &mut self,
+        &self,
         ciphertext: &[u8],
         keyid: &str,
         annotations: &Annotations,
     ) -> Result<Vec<u8>>;
 
+    /// Generate a random data key of `len` bytes inside KMS, and return it
+    /// both in plaintext and encrypted by the key of `keyid`.
+    async fn generate_data_key(&self, _keyid: &str, _len: usize) -> Result<DataKey> {
+        Err(unsupported(self.name(), Capability::GenerateDataKey))
+    }
+
+    /// Sign the `message` with the private key of `keyid`. The message is
+    /// hashed inside KMS following the algorithm of the key.
+    async fn sign(&self, _keyid: &str, _message: &[u8]) -> Result<Vec<u8>> {
+        Err(unsupported(self.name(), Capability::Sign))
+    }
+
+    /// Verify the `signature` of the `message` with the key of `keyid`.
+    /// Returns whether the signature is valid.
+    async fn verify(&self, _keyid: &str, _message: &[u8], _signature: &[u8]) -> Result<bool> {
+        Err(unsupported(self.name(), Capability::Verify))
+    }
+
+    /// Get the PEM encoded public key of `keyid`.
+    async fn get_public_key(&self, _keyid: &str) -> Result<String> {
+        Err(unsupported(self.name(), Capability::GetPublicKey))
+    }
+
     /// Get secret. Different secret manager will use different parameters inside
     /// `annotations`.
-    async fn get_secret(&mut self, name: &str, annotations: &Annotations) -> Result<Vec<u8>>;
+    async fn get_secret(&self, name: &str, annotations: &Annotations) -> Result<Vec<u8>>;
 
     /// Set secret. The information to specify the identity of the
     /// secret is included in the `annotations`
-    async fn set_secret(&mut self, _content: Vec<u8>, _name: String) -> Result<Annotations> {
-        bail!("Unimplemented!")
+    async fn set_secret(&self, _content: Vec<u8>, _name: String) -> Result<Annotations> {
+        Err(unsupported(self.name(), Capability::SetSecret))
+    }
+
+    /// List the names of the secrets.
+    async fn list_secrets(&self) -> Result<Vec<String>> {
+        Err(unsupported(self.name(), Capability::ListSecrets))
+    }
+
+    /// Delete the secret of `name`, located by the `annotations` returned by
+    /// [`KMS::set_secret`].
+    async fn delete_secret(&self, _name: &str, _annotations: &Annotations) -> Result<()> {
+        Err(unsupported(self.name(), Capability::DeleteSecret))
     }
 }
@@ -0,0 +1,83 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Errors of the KMS drivers
+
+use thiserror::Error;
+
+use crate::Capability;
+
+/// The kinds of failures of a KMS that the callers may handle differently.
+/// The drivers return them inside [`anyhow::Error`], so they can be found by
+/// downcasting the error or any error of its chain.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("KMS {driver} does not support {capability}")]
+    Unsupported {
+        driver: String,
+        capability: Capability,
+    },
+
+    /// The key or secret does not exist.
+    #[error("{0}")]
+    NotFound(String),
+
+    /// The KMS rejects the credentials or the access to the key or secret.
+    #[error("{0}")]
+    PermissionDenied(String),
+
+    /// The KMS cannot be reached, or is temporarily failing.
+    #[error("{0}")]
+    Unavailable(String),
+
+    /// The KMS rejects the request, e.g. for a malformed ciphertext.
+    #[error("{0}")]
+    InvalidArgument(String),
+}
+
+/// The error of a request to an HTTP KMS that is not sent or responded.
+#[cfg(feature = "reqwest")]
+pub(crate) fn unavailable(e: reqwest::Error) -> anyhow::Error {
+    Error::Unavailable(e.to_string()).into()
+}
+
+/// The error of an HTTP KMS responding the `status` with the `message`.
+#[cfg(feature = "reqwest")]
+pub(crate) fn http_error(status: reqwest::StatusCode, message: String) -> anyhow::Error {
+    match status.as_u16() {
+        400 | 422 => Error::InvalidArgument(message).into(),
+        401 | 403 => Error::PermissionDenied(message).into(),
+        404 => Error::NotFound(message).into(),
+        429 | 502..=504 => Error::Unavailable(message).into(),
+        _ => anyhow::anyhow!(message),
+    }
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod tests {
+    use reqwest::StatusCode;
+    use rstest::rstest;
+
+    use super::{http_error, Error};
+
+    #[rstest]
+    #[case(StatusCode::BAD_REQUEST, Some("InvalidArgument"))]
+    #[case(StatusCode::FORBIDDEN, Some("PermissionDenied"))]
+    #[case(StatusCode::NOT_FOUND, Some("NotFound"))]
+    #[case(StatusCode::SERVICE_UNAVAILABLE, Some("Unavailable"))]
+    #[case(StatusCode::INTERNAL_SERVER_ERROR, None)]
+    fn http_status(#[case] status: StatusCode, #[case] expected: Option<&str>) {
+        let error = http_error(status, "m".into());
+        let kind = error.downcast_ref::<Error>().map(|e| match e {
+            Error::Unsupported { .. } => "Unsupported",
+            Error::NotFound(_) => "NotFound",
+            Error::PermissionDenied(_) => "PermissionDenied",
+            Error::Unavailable(_) => "Unavailable",
+            Error::InvalidArgument(_) => "InvalidArgument",
+        });
+        assert_eq!(kind, expected);
+        assert_eq!(error.to_string(), "m");
+    }
+}
@@ -5,3 +5,12 @@
 
 pub mod api;
 pub use api::*;
+
+pub mod error;
+pub use error::Error;
+
+pub mod plugins;
+pub use plugins::{KmsBuilder, ProviderSettings, Registry};
+
+#[cfg(all(test, any(feature = "aws", feature = "vault")))]
+mod test_util;
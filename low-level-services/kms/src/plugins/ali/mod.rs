@@ -0,0 +1,415 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # Alibaba Cloud KMS
+//!
+//! The driver calls the Alibaba Cloud KMS through a client binary, whose
+//! path is given by the `binary` setting or the `KMS_BINARY_PATH` env. For
+//! each request, the client is launched as `<binary> <Action>`, where the
+//! action is an API of the KMS, i.e. `Encrypt`, `Decrypt`,
+//! `GenerateDataKey` or `GetSecretValue`. The request parameters are written to its stdin as a
+//! JSON object, and the response is read from its stdout as a JSON object.
+//! The parameters and response fields follow the KMS API, e.g.
+//!
+//! ```text
+//! $ echo '{"RegionId":"cn-hangzhou","KeyId":"key","Plaintext":"MTIz"}' | client Encrypt
+//! {"KeyId":"key","CiphertextBlob":"..."}
+//! ```
+//!
+//! A client exiting with non-zero status fails the request, with its stderr
+//! as the error message.
+//!
+//! Settings of the driver:
+//! - `region`: the region of the KMS instance, required.
+//! - `instanceid`: the id of the dedicated KMS instance, optional.
+//! - `binary`: path of the client binary, defaults to `KMS_BINARY_PATH`.
+//! - `timeout`: timeout in seconds of each request, defaults to 30.
+//!
+//! The `region` and `instanceid` are also put into the annotations of the
+//! encryption results, and the ones in the annotations override the settings
+//! when decrypting or getting secrets.
+
+use std::{collections::HashMap, path::PathBuf, process::Stdio, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, process::Command};
+use zeroize::Zeroizing;
+
+use crate::{Capability, DataKey, Error, ProviderSettings, KMS};
+
+/// Name of the driver
+pub const NAME: &str = "ali";
+
+/// Env of the path of the client binary
+pub const KMS_BINARY_PATH_ENV: &str = "KMS_BINARY_PATH";
+
+const DEFAULT_TIMEOUT_SEC: u64 = 30;
+
+const REGION_ANNOTATION: &str = "region";
+const INSTANCE_ID_ANNOTATION: &str = "instanceid";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    region: String,
+    instanceid: Option<String>,
+    binary: Option<PathBuf>,
+    timeout: Option<u64>,
+}
+
+pub struct AliKms {
+    region: String,
+    instance_id: Option<String>,
+    binary: PathBuf,
+    timeout: Duration,
+}
+
+/// Builder of the driver registered in [`crate::Registry`].
+pub fn build(settings: &ProviderSettings) -> Result<Arc<dyn KMS>> {
+    let settings: Settings =
+        serde_json::from_value(settings.clone().into()).context("invalid settings of ali KMS")?;
+    let binary = match settings.binary {
+        Some(binary) => binary,
+        None => std::env::var_os(KMS_BINARY_PATH_ENV)
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                anyhow!("ali KMS requires setting `binary` or env {KMS_BINARY_PATH_ENV}")
+            })?,
+    };
+
+    Ok(Arc::new(AliKms {
+        region: settings.region,
+        instance_id: settings.instanceid,
+        binary,
+        timeout: Duration::from_secs(settings.timeout.unwrap_or(DEFAULT_TIMEOUT_SEC)),
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Location<'a> {
+    region_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance_id: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EncryptRequest<'a> {
+    #[serde(flatten)]
+    location: Location<'a>,
+    key_id: &'a str,
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EncryptResponse {
+    ciphertext_blob: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DecryptRequest<'a> {
+    #[serde(flatten)]
+    location: Location<'a>,
+    ciphertext_blob: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DecryptResponse {
+    plaintext: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct GenerateDataKeyRequest<'a> {
+    #[serde(flatten)]
+    location: Location<'a>,
+    key_id: &'a str,
+    number_of_bytes: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GenerateDataKeyResponse {
+    plaintext: String,
+    ciphertext_blob: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSecretValueRequest<'a> {
+    #[serde(flatten)]
+    location: Location<'a>,
+    secret_name: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSecretValueResponse {
+    secret_data: String,
+}
+
+impl AliKms {
+    /// The location of the KMS instance, where the `annotations` override the
+    /// settings.
+    fn location<'a>(&'a self, annotations: &'a HashMap<String, String>) -> Location<'a> {
+        Location {
+            region_id: annotations.get(REGION_ANNOTATION).unwrap_or(&self.region),
+            instance_id: annotations
+                .get(INSTANCE_ID_ANNOTATION)
+                .or(self.instance_id.as_ref())
+                .map(|id| &id[..]),
+        }
+    }
+
+    /// Annotations of the encryption results, locating the KMS instance.
+    fn annotations(&self) -> HashMap<String, String> {
+        let mut annotations = HashMap::from([(REGION_ANNOTATION.into(), self.region.clone())]);
+        if let Some(instance_id) = &self.instance_id {
+            annotations.insert(INSTANCE_ID_ANNOTATION.into(), instance_id.clone());
+        }
+
+        annotations
+    }
+
+    /// Call the `action` API of the KMS by the client binary.
+    async fn call<Req: Serialize, Res: DeserializeOwned>(
+        &self,
+        action: &str,
+        request: &Req,
+    ) -> Result<Res> {
+        let request = serde_json::to_vec(request)?;
+        let mut child = Command::new(&self.binary)
+            .arg(action)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("launch ali KMS client {}", self.binary.display()))?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let output = tokio::time::timeout(self.timeout, async move {
+            stdin.write_all(&request).await?;
+            drop(stdin);
+            child.wait_with_output().await
+        })
+        .await
+        .map_err(|_| Error::Unavailable(format!("ali KMS {action} timed out")))?
+        .with_context(|| format!("run ali KMS client for {action}"))?;
+
+        if !output.status.success() {
+            bail!(
+                "ali KMS {action} failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("parse response of ali KMS {action}"))
+    }
+}
+
+#[async_trait]
+impl KMS for AliKms {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::Encrypt,
+            Capability::Decrypt,
+            Capability::GenerateDataKey,
+            Capability::GetSecret,
+        ]
+    }
+
+    async fn encrypt(
+        &self,
+        data: &[u8],
+        keyid: &str,
+    ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let no_annotations = HashMap::new();
+        let request = EncryptRequest {
+            location: self.location(&no_annotations),
+            key_id: keyid,
+            plaintext: engine.encode(data),
+        };
+        let response: EncryptResponse = self.call("Encrypt", &request).await?;
+        let ciphertext = engine
+            .decode(response.ciphertext_blob)
+            .context("decode CiphertextBlob")?;
+        Ok((ciphertext, self.annotations()))
+    }
+
+    async fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        _keyid: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let request = DecryptRequest {
+            location: self.location(annotations),
+            ciphertext_blob: engine.encode(ciphertext),
+        };
+        let response: DecryptResponse = self.call("Decrypt", &request).await?;
+        engine
+            .decode(response.plaintext)
+            .context("decode Plaintext")
+    }
+
+    async fn generate_data_key(&self, keyid: &str, len: usize) -> Result<DataKey> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let no_annotations = HashMap::new();
+        let request = GenerateDataKeyRequest {
+            location: self.location(&no_annotations),
+            key_id: keyid,
+            number_of_bytes: len,
+        };
+        let response: GenerateDataKeyResponse = self.call("GenerateDataKey", &request).await?;
+        let plaintext = Zeroizing::new(
+            engine
+                .decode(response.plaintext)
+                .context("decode Plaintext")?,
+        );
+        let ciphertext = engine
+            .decode(response.ciphertext_blob)
+            .context("decode CiphertextBlob")?;
+        Ok(DataKey {
+            plaintext,
+            ciphertext,
+            annotations: self.annotations(),
+        })
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let request = GetSecretValueRequest {
+            location: self.location(annotations),
+            secret_name: name,
+        };
+        let response: GetSecretValueResponse = self.call("GetSecretValue", &request).await?;
+        Ok(response.secret_data.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, os::unix::fs::PermissionsExt, path::Path};
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::{Registry, KMS};
+
+    /// A stand-in of the client binary. It "encrypts" by keeping the data
+    /// unchanged, tagged with the region, and logs the requests.
+    const FAKE_CLIENT: &str = r#"#!/bin/sh
+req=$(cat)
+echo "$1 $req" >> "$(dirname "$0")/requests.log"
+field() {
+    echo "$req" | sed -n "s/.*\"$1\":\"\([^\"]*\)\".*/\1/p"
+}
+case "$1" in
+Encrypt)
+    echo "{\"KeyId\":\"$(field KeyId)\",\"CiphertextBlob\":\"$(field Plaintext)\"}" ;;
+Decrypt)
+    echo "{\"Plaintext\":\"$(field CiphertextBlob)\"}" ;;
+GenerateDataKey)
+    key=$(head -c "$(echo "$req" | sed -n 's/.*"NumberOfBytes":\([0-9]*\).*/\1/p')" /dev/urandom | base64 -w0)
+    echo "{\"KeyId\":\"$(field KeyId)\",\"Plaintext\":\"$key\",\"CiphertextBlob\":\"$key\"}" ;;
+GetSecretValue)
+    [ "$(field SecretName)" = "missing" ] && { echo "secret not found" >&2; exit 1; }
+    echo "{\"SecretData\":\"data of $(field SecretName) in $(field RegionId)\"}" ;;
+esac
+"#;
+
+    fn fake_client(dir: &Path) -> String {
+        let path = dir.join("client");
+        std::fs::write(&path, FAKE_CLIENT).expect("write fake client failed");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("chmod fake client failed");
+        path.to_string_lossy().into()
+    }
+
+    async fn ali_kms(dir: &Path) -> std::sync::Arc<dyn KMS> {
+        let settings = json!({
+            "region": "cn-hangzhou",
+            "instanceid": "kst-1",
+            "binary": fake_client(dir),
+        });
+        Registry::builtin()
+            .new_client("ali", settings.as_object().unwrap())
+            .await
+            .expect("create ali kms failed")
+    }
+
+    #[tokio::test]
+    async fn encrypt_decrypt() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = ali_kms(dir.path()).await;
+
+        let (ciphertext, annotations) = kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        assert_eq!(annotations["region"], "cn-hangzhou");
+        assert_eq!(annotations["instanceid"], "kst-1");
+
+        let plaintext = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let data_key = kms
+            .generate_data_key("key1", 32)
+            .await
+            .expect("generate data key failed");
+        assert_eq!(data_key.plaintext.len(), 32);
+        let plaintext = kms
+            .decrypt(&data_key.ciphertext, "key1", &data_key.annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, *data_key.plaintext);
+
+        let log = std::fs::read_to_string(dir.path().join("requests.log")).unwrap();
+        assert!(log
+            .contains(r#"Encrypt {"RegionId":"cn-hangzhou","InstanceId":"kst-1","KeyId":"key1""#));
+    }
+
+    #[rstest]
+    #[case("name", HashMap::new(), Some("data of name in cn-hangzhou"))]
+    #[case("name", HashMap::from([("region".to_string(), "cn-beijing".to_string())]), Some("data of name in cn-beijing"))]
+    #[case("missing", HashMap::new(), None)]
+    #[tokio::test]
+    async fn get_secret(
+        #[case] name: &str,
+        #[case] annotations: HashMap<String, String>,
+        #[case] expected: Option<&str>,
+    ) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = ali_kms(dir.path()).await;
+
+        let secret = kms.get_secret(name, &annotations).await;
+        match expected {
+            Some(expected) => assert_eq!(secret.expect("get secret failed"), expected.as_bytes()),
+            None => {
+                let err = secret.expect_err("get secret should fail");
+                assert!(err.to_string().contains("secret not found"), "{err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,854 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # AWS KMS and Secrets Manager
+//!
+//! The driver maps the encryption, data keys and signing to AWS KMS, and the
+//! secrets to AWS Secrets Manager. The requests are signed with
+//! [Signature Version 4](sigv4).
+//!
+//! Settings of the driver:
+//! - `region`: the AWS region, required.
+//! - `access_key_id`, `secret_access_key` and `session_token`: the
+//!   credentials, default to the env `AWS_ACCESS_KEY_ID`,
+//!   `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+//! - `kms_endpoint` and `secrets_manager_endpoint`: URLs of the services,
+//!   default to the public endpoints of the region. Both can be set by
+//!   `endpoint` at once, e.g. `http://localhost:4566` of LocalStack.
+//! - `encryption_context`: the [encryption context] of the encryption, a
+//!   table of strings, optional.
+//! - `signing_algorithm`: the algorithm to sign with the asymmetric keys,
+//!   defaults to `ECDSA_SHA_256`.
+//! - `timeout`: timeout in seconds of each request, defaults to 30.
+//!
+//! The encryption context is put into the annotations of the encryption
+//! results, as the annotations prefixed by `context.`, e.g.
+//! `context.purpose = "image"`, and is given to KMS when decrypting.
+//!
+//! The annotations of the secrets are
+//! - `version_id`: the version of the secret, defaults to the current one.
+//! - `version_stage`: the staging label of the secret.
+//!
+//! Deleting a secret schedules the deletion with the default recovery window
+//! of Secrets Manager.
+//!
+//! [encryption context]: https://docs.aws.amazon.com/kms/latest/developerguide/concepts.html#encrypt_context
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+use zeroize::Zeroizing;
+
+use crate::{
+    error::{http_error, unavailable},
+    Capability, DataKey, Error, ProviderSettings, KMS,
+};
+
+pub mod sigv4;
+
+use sigv4::Credentials;
+
+/// Name of the driver
+pub const NAME: &str = "aws";
+
+const ACCESS_KEY_ID_ENV: &str = "AWS_ACCESS_KEY_ID";
+const SECRET_ACCESS_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+const SESSION_TOKEN_ENV: &str = "AWS_SESSION_TOKEN";
+
+const DEFAULT_TIMEOUT_SEC: u64 = 30;
+
+const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+
+const DEFAULT_SIGNING_ALGORITHM: &str = "ECDSA_SHA_256";
+
+const CONTEXT_ANNOTATION_PREFIX: &str = "context.";
+const VERSION_ID_ANNOTATION: &str = "version_id";
+const VERSION_STAGE_ANNOTATION: &str = "version_stage";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    region: String,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+    endpoint: Option<String>,
+    kms_endpoint: Option<String>,
+    secrets_manager_endpoint: Option<String>,
+    #[serde(default)]
+    encryption_context: BTreeMap<String, String>,
+    signing_algorithm: Option<String>,
+    timeout: Option<u64>,
+}
+
+/// An AWS service called by the driver
+struct Service {
+    /// Name of the service in the signature
+    name: &'static str,
+
+    /// Prefix of the `X-Amz-Target` of the APIs
+    target_prefix: &'static str,
+
+    endpoint: url::Url,
+}
+
+pub struct AwsKms {
+    region: String,
+    credentials: Credentials,
+    kms: Service,
+    secrets_manager: Service,
+    encryption_context: BTreeMap<String, String>,
+    signing_algorithm: String,
+    http_client: reqwest::Client,
+}
+
+fn setting_or_env(setting: Option<String>, env: &str) -> Option<String> {
+    setting.or_else(|| std::env::var(env).ok())
+}
+
+/// Builder of the driver registered in [`crate::Registry`].
+pub fn build(settings: &ProviderSettings) -> Result<Arc<dyn KMS>> {
+    let settings: Settings =
+        serde_json::from_value(settings.clone().into()).context("invalid settings of aws KMS")?;
+
+    let credentials = Credentials {
+        access_key_id: setting_or_env(settings.access_key_id, ACCESS_KEY_ID_ENV).ok_or_else(
+            || anyhow!("aws KMS requires `access_key_id` or env {ACCESS_KEY_ID_ENV}"),
+        )?,
+        secret_access_key: setting_or_env(settings.secret_access_key, SECRET_ACCESS_KEY_ENV)
+            .ok_or_else(|| {
+                anyhow!("aws KMS requires `secret_access_key` or env {SECRET_ACCESS_KEY_ENV}")
+            })?,
+        session_token: setting_or_env(settings.session_token, SESSION_TOKEN_ENV),
+    };
+
+    let region = settings.region;
+    let endpoint = |endpoint: Option<String>, service: &str| -> Result<url::Url> {
+        let endpoint = endpoint
+            .or_else(|| settings.endpoint.clone())
+            .unwrap_or_else(|| format!("https://{service}.{region}.amazonaws.com"));
+        url::Url::parse(&endpoint).with_context(|| format!("parse aws endpoint {endpoint}"))
+    };
+    let kms = Service {
+        name: "kms",
+        target_prefix: "TrentService",
+        endpoint: endpoint(settings.kms_endpoint, "kms")?,
+    };
+    let secrets_manager = Service {
+        name: "secretsmanager",
+        target_prefix: "secretsmanager",
+        endpoint: endpoint(settings.secrets_manager_endpoint, "secretsmanager")?,
+    };
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(
+            settings.timeout.unwrap_or(DEFAULT_TIMEOUT_SEC),
+        ))
+        .build()
+        .context("build http client")?;
+
+    Ok(Arc::new(AwsKms {
+        region,
+        credentials,
+        kms,
+        secrets_manager,
+        encryption_context: settings.encryption_context,
+        signing_algorithm: settings
+            .signing_algorithm
+            .unwrap_or_else(|| DEFAULT_SIGNING_ALGORITHM.into()),
+        http_client,
+    }))
+}
+
+/// Body of the error responses of the AWS JSON protocol
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "__type", default)]
+    r#type: String,
+    #[serde(alias = "Message", default)]
+    message: String,
+}
+
+impl ErrorResponse {
+    /// The error code, without the namespace in `__type` if any.
+    fn code(&self) -> &str {
+        self.r#type.rsplit('#').next().unwrap_or_default()
+    }
+}
+
+/// Error of the AWS APIs
+enum CallError {
+    /// Error returned by the service
+    Service(ErrorResponse),
+    Other(anyhow::Error),
+}
+
+impl From<CallError> for anyhow::Error {
+    fn from(e: CallError) -> Self {
+        match e {
+            CallError::Service(e) => {
+                let message = format!("{}: {}", e.code(), e.message);
+                match e.code() {
+                    "NotFoundException" | "ResourceNotFoundException" => {
+                        Error::NotFound(message).into()
+                    }
+                    "AccessDeniedException"
+                    | "ExpiredTokenException"
+                    | "InvalidSignatureException"
+                    | "UnrecognizedClientException" => Error::PermissionDenied(message).into(),
+                    "IncorrectKeyException"
+                    | "InvalidCiphertextException"
+                    | "InvalidKeyUsageException"
+                    | "InvalidParameterException"
+                    | "InvalidRequestException"
+                    | "ValidationException" => Error::InvalidArgument(message).into(),
+                    "DependencyTimeoutException"
+                    | "InternalServiceError"
+                    | "KMSInternalException"
+                    | "ServiceUnavailableException"
+                    | "ThrottlingException" => Error::Unavailable(message).into(),
+                    _ => anyhow!(message),
+                }
+            }
+            CallError::Other(e) => e,
+        }
+    }
+}
+
+/// Get the string at `pointer` of the response `value`.
+fn str_field<'a>(value: &'a Value, pointer: &str) -> Result<&'a str> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("no `{pointer}` in the response of aws"))
+}
+
+impl AwsKms {
+    /// Call the `action` API of the `service` with the JSON `body`.
+    async fn try_call(
+        &self,
+        service: &Service,
+        action: &str,
+        body: Value,
+    ) -> std::result::Result<Value, CallError> {
+        let payload = serde_json::to_vec(&body).map_err(|e| CallError::Other(e.into()))?;
+        let target = format!("{}.{action}", service.target_prefix);
+        let amz_date = sigv4::amz_date(OffsetDateTime::now_utc());
+        let host = match service.endpoint.port() {
+            Some(port) => format!("{}:{port}", service.endpoint.host_str().unwrap_or_default()),
+            None => service.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+
+        let mut headers = BTreeMap::from([
+            ("content-type".to_string(), CONTENT_TYPE.to_string()),
+            ("host".to_string(), host),
+            ("x-amz-date".to_string(), amz_date.clone()),
+            ("x-amz-target".to_string(), target),
+        ]);
+        if let Some(token) = &self.credentials.session_token {
+            headers.insert("x-amz-security-token".into(), token.clone());
+        }
+        let request = sigv4::Request {
+            method: "POST",
+            path: service.endpoint.path(),
+            query: "",
+            headers,
+            payload: &payload,
+        };
+        let authorization = sigv4::authorization(
+            &self.credentials,
+            &self.region,
+            service.name,
+            &amz_date,
+            &request,
+        );
+
+        // reqwest sets the `host` header from the URL.
+        let mut http_request = self
+            .http_client
+            .post(service.endpoint.clone())
+            .header("authorization", authorization);
+        for (name, value) in request.headers.iter().filter(|(name, _)| *name != "host") {
+            http_request = http_request.header(name, value);
+        }
+
+        let response = http_request
+            .body(payload)
+            .send()
+            .await
+            .map_err(|e| CallError::Other(unavailable(e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            let error = response.json::<ErrorResponse>().await.map_err(|_| {
+                CallError::Other(http_error(
+                    status,
+                    format!("aws {action} failed with {status}"),
+                ))
+            })?;
+            return Err(CallError::Service(error));
+        }
+
+        response
+            .json()
+            .await
+            .with_context(|| format!("parse response of aws {action}"))
+            .map_err(CallError::Other)
+    }
+
+    async fn call(&self, service: &Service, action: &str, body: Value) -> Result<Value> {
+        self.try_call(service, action, body)
+            .await
+            .map_err(|e| anyhow::Error::from(e).context(format!("aws {action} failed")))
+    }
+
+    /// Add the configured encryption context to the request `body`.
+    fn with_encryption_context(&self, mut body: Value) -> Value {
+        if !self.encryption_context.is_empty() {
+            body["EncryptionContext"] = json!(self.encryption_context);
+        }
+
+        body
+    }
+
+    /// Annotations of the encryption results, carrying the encryption context.
+    fn annotations(&self) -> HashMap<String, String> {
+        self.encryption_context
+            .iter()
+            .map(|(k, v)| (format!("{CONTEXT_ANNOTATION_PREFIX}{k}"), v.clone()))
+            .collect()
+    }
+}
+
+/// PEM encode the DER encoded `SubjectPublicKeyInfo`.
+fn public_key_pem(der: &[u8]) -> String {
+    let base64 = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut pem = String::from("-----BEGIN PUBLIC KEY-----\n");
+    for line in base64.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str("-----END PUBLIC KEY-----\n");
+    pem
+}
+
+#[async_trait]
+impl KMS for AwsKms {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::Encrypt,
+            Capability::Decrypt,
+            Capability::GenerateDataKey,
+            Capability::Sign,
+            Capability::Verify,
+            Capability::GetPublicKey,
+            Capability::GetSecret,
+            Capability::SetSecret,
+            Capability::ListSecrets,
+            Capability::DeleteSecret,
+        ]
+    }
+
+    async fn encrypt(
+        &self,
+        data: &[u8],
+        keyid: &str,
+    ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = self
+            .with_encryption_context(json!({ "KeyId": keyid, "Plaintext": engine.encode(data) }));
+        let response = self.call(&self.kms, "Encrypt", body).await?;
+        let ciphertext = engine
+            .decode(str_field(&response, "/CiphertextBlob")?)
+            .context("decode CiphertextBlob")?;
+        Ok((ciphertext, self.annotations()))
+    }
+
+    async fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        keyid: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let encryption_context: BTreeMap<_, _> = annotations
+            .iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(CONTEXT_ANNOTATION_PREFIX)?, v)))
+            .collect();
+        let mut body = json!({ "KeyId": keyid, "CiphertextBlob": engine.encode(ciphertext) });
+        if !encryption_context.is_empty() {
+            body["EncryptionContext"] = json!(encryption_context);
+        }
+
+        let response = self.call(&self.kms, "Decrypt", body).await?;
+        engine
+            .decode(str_field(&response, "/Plaintext")?)
+            .context("decode Plaintext")
+    }
+
+    async fn generate_data_key(&self, keyid: &str, len: usize) -> Result<DataKey> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = self.with_encryption_context(json!({ "KeyId": keyid, "NumberOfBytes": len }));
+        let response = self.call(&self.kms, "GenerateDataKey", body).await?;
+        let plaintext = Zeroizing::new(
+            engine
+                .decode(str_field(&response, "/Plaintext")?)
+                .context("decode Plaintext")?,
+        );
+        let ciphertext = engine
+            .decode(str_field(&response, "/CiphertextBlob")?)
+            .context("decode CiphertextBlob")?;
+        Ok(DataKey {
+            plaintext,
+            ciphertext,
+            annotations: self.annotations(),
+        })
+    }
+
+    async fn sign(&self, keyid: &str, message: &[u8]) -> Result<Vec<u8>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = json!({
+            "KeyId": keyid,
+            "Message": engine.encode(message),
+            "MessageType": "RAW",
+            "SigningAlgorithm": self.signing_algorithm,
+        });
+        let response = self.call(&self.kms, "Sign", body).await?;
+        engine
+            .decode(str_field(&response, "/Signature")?)
+            .context("decode Signature")
+    }
+
+    async fn verify(&self, keyid: &str, message: &[u8], signature: &[u8]) -> Result<bool> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = json!({
+            "KeyId": keyid,
+            "Message": engine.encode(message),
+            "MessageType": "RAW",
+            "Signature": engine.encode(signature),
+            "SigningAlgorithm": self.signing_algorithm,
+        });
+        match self.try_call(&self.kms, "Verify", body).await {
+            Ok(response) => Ok(response
+                .get("SignatureValid")
+                .and_then(Value::as_bool)
+                .unwrap_or_default()),
+            // AWS KMS rejects invalid signatures with an error.
+            Err(CallError::Service(e)) if e.code() == "KMSInvalidSignatureException" => Ok(false),
+            Err(e) => Err(anyhow::Error::from(e).context("aws Verify failed")),
+        }
+    }
+
+    async fn get_public_key(&self, keyid: &str) -> Result<String> {
+        let response = self
+            .call(&self.kms, "GetPublicKey", json!({ "KeyId": keyid }))
+            .await?;
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(str_field(&response, "/PublicKey")?)
+            .context("decode PublicKey")?;
+        Ok(public_key_pem(&der))
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let mut body = json!({ "SecretId": name });
+        if let Some(version_id) = annotations.get(VERSION_ID_ANNOTATION) {
+            body["VersionId"] = json!(version_id);
+        }
+        if let Some(version_stage) = annotations.get(VERSION_STAGE_ANNOTATION) {
+            body["VersionStage"] = json!(version_stage);
+        }
+
+        let response = self
+            .call(&self.secrets_manager, "GetSecretValue", body)
+            .await?;
+        if let Some(secret) = response.get("SecretString").and_then(Value::as_str) {
+            return Ok(secret.as_bytes().to_vec());
+        }
+
+        base64::engine::general_purpose::STANDARD
+            .decode(str_field(&response, "/SecretBinary")?)
+            .context("decode SecretBinary")
+    }
+
+    async fn set_secret(&self, content: Vec<u8>, name: String) -> Result<HashMap<String, String>> {
+        let secret = base64::engine::general_purpose::STANDARD.encode(content);
+        let create = json!({ "Name": name, "SecretBinary": secret });
+        let response = match self
+            .try_call(&self.secrets_manager, "CreateSecret", create)
+            .await
+        {
+            Err(CallError::Service(e)) if e.code() == "ResourceExistsException" => {
+                let put = json!({ "SecretId": name, "SecretBinary": secret });
+                self.call(&self.secrets_manager, "PutSecretValue", put)
+                    .await?
+            }
+            response => {
+                response.map_err(|e| anyhow::Error::from(e).context("aws CreateSecret failed"))?
+            }
+        };
+
+        let version_id = str_field(&response, "/VersionId")?;
+        Ok(HashMap::from([(
+            VERSION_ID_ANNOTATION.into(),
+            version_id.into(),
+        )]))
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut body = json!({});
+        loop {
+            let response = self
+                .call(&self.secrets_manager, "ListSecrets", body)
+                .await?;
+            let secrets = response
+                .get("SecretList")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow!("no `SecretList` in the response of aws"))?;
+            names.extend(
+                secrets
+                    .iter()
+                    .filter_map(|secret| secret.get("Name")?.as_str().map(String::from)),
+            );
+
+            match response.get("NextToken").and_then(Value::as_str) {
+                Some(token) => body = json!({ "NextToken": token }),
+                None => return Ok(names),
+            }
+        }
+    }
+
+    async fn delete_secret(
+        &self,
+        name: &str,
+        _annotations: &HashMap<String, String>,
+    ) -> Result<()> {
+        self.call(
+            &self.secrets_manager,
+            "DeleteSecret",
+            json!({ "SecretId": name }),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use base64::Engine;
+    use hyper::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{test_util, Error, Registry, KMS};
+
+    /// An in-process stand-in of AWS KMS and Secrets Manager. The "ciphertext"
+    /// of KMS is the key id, the encryption context and the plaintext.
+    #[derive(Default)]
+    struct FakeAws {
+        /// Versions of the secrets, keyed by name
+        secrets: HashMap<String, Vec<Value>>,
+    }
+
+    fn error(code: &str) -> (StatusCode, Value) {
+        (
+            StatusCode::BAD_REQUEST,
+            json!({ "__type": format!("com.amazonaws.kms#{code}"), "message": code }),
+        )
+    }
+
+    impl FakeAws {
+        fn handle(&mut self, target: &str, body: Value) -> (StatusCode, Value) {
+            let engine = base64::engine::general_purpose::STANDARD;
+            match target {
+                "TrentService.Encrypt" => {
+                    let blob = json!([body["KeyId"], body["EncryptionContext"], body["Plaintext"]]);
+                    let blob = engine.encode(blob.to_string());
+                    (
+                        StatusCode::OK,
+                        json!({ "CiphertextBlob": blob, "KeyId": body["KeyId"] }),
+                    )
+                }
+                "TrentService.GenerateDataKey" => {
+                    let len = body["NumberOfBytes"].as_u64().unwrap() as usize;
+                    let plaintext = json!(engine.encode(vec![7u8; len]));
+                    let blob = json!([body["KeyId"], body["EncryptionContext"], plaintext]);
+                    let blob = engine.encode(blob.to_string());
+                    (
+                        StatusCode::OK,
+                        json!({ "CiphertextBlob": blob, "Plaintext": plaintext }),
+                    )
+                }
+                "TrentService.Sign" => {
+                    let signature = json!([body["KeyId"], body["Message"]]).to_string();
+                    (
+                        StatusCode::OK,
+                        json!({ "Signature": engine.encode(signature) }),
+                    )
+                }
+                "TrentService.Verify" => {
+                    let signature = json!([body["KeyId"], body["Message"]]).to_string();
+                    if body["Signature"] != engine.encode(signature) {
+                        return error("KMSInvalidSignatureException");
+                    }
+                    (StatusCode::OK, json!({ "SignatureValid": true }))
+                }
+                "TrentService.GetPublicKey" => (
+                    StatusCode::OK,
+                    json!({ "PublicKey": engine.encode([1u8; 64]) }),
+                ),
+                "TrentService.Decrypt" => {
+                    let blob = engine
+                        .decode(body["CiphertextBlob"].as_str().unwrap())
+                        .unwrap();
+                    let blob: Value = serde_json::from_slice(&blob).unwrap();
+                    if blob[0] != body["KeyId"] || blob[1] != body["EncryptionContext"] {
+                        return error("InvalidCiphertextException");
+                    }
+                    (StatusCode::OK, json!({ "Plaintext": blob[2] }))
+                }
+                "secretsmanager.CreateSecret" | "secretsmanager.PutSecretValue" => {
+                    let name = body["Name"].as_str().or(body["SecretId"].as_str()).unwrap();
+                    let exists = self.secrets.contains_key(name);
+                    if target.ends_with("CreateSecret") && exists {
+                        return error("ResourceExistsException");
+                    }
+                    let versions = self.secrets.entry(name.into()).or_default();
+                    versions.push(body);
+                    (
+                        StatusCode::OK,
+                        json!({ "VersionId": format!("v{}", versions.len()) }),
+                    )
+                }
+                "secretsmanager.GetSecretValue" => {
+                    let Some(versions) = self.secrets.get(body["SecretId"].as_str().unwrap())
+                    else {
+                        return error("ResourceNotFoundException");
+                    };
+                    let version = match body["VersionId"].as_str() {
+                        Some(id) => &versions[id[1..].parse::<usize>().unwrap() - 1],
+                        None => versions.last().unwrap(),
+                    };
+                    (StatusCode::OK, version.clone())
+                }
+                "secretsmanager.ListSecrets" => {
+                    // One secret per page
+                    let mut names: Vec<_> = self.secrets.keys().cloned().collect();
+                    names.sort();
+                    let page = body["NextToken"].as_str().map_or(0, |t| t.parse().unwrap());
+                    let mut response = json!({ "SecretList": [] });
+                    if let Some(name) = names.get(page) {
+                        response["SecretList"] = json!([{ "Name": name }]);
+                        response["NextToken"] = json!((page + 1).to_string());
+                    }
+                    (StatusCode::OK, response)
+                }
+                "secretsmanager.DeleteSecret" => {
+                    match self.secrets.remove(body["SecretId"].as_str().unwrap()) {
+                        Some(_) => (StatusCode::OK, json!({ "Name": body["SecretId"] })),
+                        None => error("ResourceNotFoundException"),
+                    }
+                }
+                _ => error("UnknownOperationException"),
+            }
+        }
+
+        /// Serve the `aws` on a random local port and return its address.
+        fn start(aws: Arc<Mutex<FakeAws>>) -> String {
+            test_util::serve(move |req| {
+                if req.method != Method::POST || req.uri.path() != "/" {
+                    return error("UnknownOperationException");
+                }
+                let header = |name| req.header(name).unwrap_or_default();
+                if !header("authorization").starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+                {
+                    return error("MissingAuthenticationTokenException");
+                }
+                let body = serde_json::from_slice(&req.body).unwrap();
+                aws.lock().unwrap().handle(header("x-amz-target"), body)
+            })
+        }
+    }
+
+    async fn aws_kms(endpoint: String, encryption_context: Value) -> Arc<dyn KMS> {
+        let settings = json!({
+            "region": "us-east-1",
+            "access_key_id": "AKIDEXAMPLE",
+            "secret_access_key": "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "endpoint": endpoint,
+            "encryption_context": encryption_context,
+        });
+        Registry::builtin()
+            .new_client("aws", settings.as_object().unwrap())
+            .await
+            .expect("create aws kms failed")
+    }
+
+    #[tokio::test]
+    async fn encrypt_decrypt() {
+        let endpoint = FakeAws::start(Default::default());
+        let kms = aws_kms(endpoint, json!({ "purpose": "test" })).await;
+
+        let (ciphertext, mut annotations) =
+            kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        assert_eq!(annotations["context.purpose"], "test");
+        let plaintext = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        annotations.insert("context.purpose".into(), "other".into());
+        let err = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect_err("decrypt with other context should fail");
+        assert!(format!("{err:#}").contains("InvalidCiphertextException"));
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::InvalidArgument(_))
+        ));
+
+        let data_key = kms
+            .generate_data_key("key1", 32)
+            .await
+            .expect("generate data key failed");
+        assert_eq!(data_key.annotations["context.purpose"], "test");
+        let plaintext = kms
+            .decrypt(&data_key.ciphertext, "key1", &data_key.annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, *data_key.plaintext);
+    }
+
+    #[tokio::test]
+    async fn sign_verify() {
+        let endpoint = FakeAws::start(Default::default());
+        let kms = aws_kms(endpoint, json!({})).await;
+
+        let signature = kms.sign("key1", b"data").await.expect("sign failed");
+        for (message, valid) in [(&b"data"[..], true), (b"other", false)] {
+            let verified = kms
+                .verify("key1", message, &signature)
+                .await
+                .expect("verify failed");
+            assert_eq!(verified, valid);
+        }
+
+        let public_key = kms
+            .get_public_key("key1")
+            .await
+            .expect("get public key failed");
+        let lines: Vec<_> = public_key.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "-----BEGIN PUBLIC KEY-----");
+        assert_eq!(lines[1].len(), 64);
+    }
+
+    #[tokio::test]
+    async fn set_get_secret() {
+        let aws = Arc::new(Mutex::new(FakeAws::default()));
+        let endpoint = FakeAws::start(aws.clone());
+        let kms = aws_kms(endpoint, json!({})).await;
+
+        let v1 = kms
+            .set_secret(b"v1".to_vec(), "app/db".into())
+            .await
+            .expect("set secret failed");
+        kms.set_secret(b"v2".to_vec(), "app/db".into())
+            .await
+            .expect("set secret failed");
+
+        let secret = kms
+            .get_secret("app/db", &v1)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"v1");
+        let secret = kms
+            .get_secret("app/db", &HashMap::new())
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"v2");
+
+        aws.lock().unwrap().secrets.insert(
+            "app/config".into(),
+            vec![json!({ "SecretString": "plain" })],
+        );
+        let secret = kms
+            .get_secret("app/config", &HashMap::new())
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"plain");
+
+        let err = kms
+            .get_secret("missing", &HashMap::new())
+            .await
+            .expect_err("get missing secret should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NotFound(_))
+        ));
+
+        let names = kms.list_secrets().await.expect("list secrets failed");
+        assert_eq!(names, ["app/config", "app/db"]);
+        kms.delete_secret("app/db", &v1)
+            .await
+            .expect("delete secret failed");
+        let names = kms.list_secrets().await.expect("list secrets failed");
+        assert_eq!(names, ["app/config"]);
+    }
+
+    /// Run against LocalStack if its endpoint is given by
+    /// `LOCALSTACK_ENDPOINT`, e.g. `http://localhost:4566`, with the key
+    /// created by `awslocal kms create-key` given by `LOCALSTACK_KEY_ID`.
+    #[tokio::test]
+    async fn localstack() {
+        let (Ok(endpoint), Ok(key_id)) = (
+            std::env::var("LOCALSTACK_ENDPOINT"),
+            std::env::var("LOCALSTACK_KEY_ID"),
+        ) else {
+            return;
+        };
+        let kms = aws_kms(endpoint, json!({ "purpose": "test" })).await;
+
+        let annotations = kms
+            .set_secret(b"secret".to_vec(), "cdh/test".into())
+            .await
+            .expect("set secret failed");
+        let secret = kms
+            .get_secret("cdh/test", &annotations)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"secret");
+
+        let (ciphertext, annotations) =
+            kms.encrypt(b"data", &key_id).await.expect("encrypt failed");
+        let plaintext = kms
+            .decrypt(&ciphertext, &key_id, &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+    }
+}
@@ -0,0 +1,590 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # External KMS plugins
+//!
+//! A plugin is an executable implementing a KMS driver out of the tree. It is
+//! spawned when the driver is launched and kept running, and talks with the
+//! driver over its stdin and stdout, by one JSON object per line. Its stderr
+//! is inherited for logging.
+//!
+//! The driver sends requests one at a time, each waiting for its response:
+//!
+//! ```json
+//! {"id":1,"method":"encrypt","params":{"data":"ZGF0YQ==","keyid":"key1"}}
+//! ```
+//!
+//! and the plugin responds to each request with the same `id`, and either a
+//! `result` or an `error`:
+//!
+//! ```json
+//! {"id":1,"result":{"ciphertext":"...","annotations":{"iv":"..."}}}
+//! {"id":1,"error":{"message":"key key1 not found","code":"not_found"}}
+//! ```
+//!
+//! The optional `code` of an error tells its kind, one of `not_found`,
+//! `permission_denied`, `unavailable` and `invalid_argument`, see [`Error`].
+//!
+//! Binary fields are base64 encoded. The methods are
+//!
+//! | method              | params                                  | result                                   |
+//! |---------------------|-----------------------------------------|------------------------------------------|
+//! | `init`              | `version`, `name`, `settings`           | `version`, `capabilities`                |
+//! | `encrypt`           | `data`, `keyid`                         | `ciphertext`, `annotations`              |
+//! | `decrypt`           | `ciphertext`, `keyid`, `annotations`    | `plaintext`                              |
+//! | `generate_data_key` | `keyid`, `len`                          | `plaintext`, `ciphertext`, `annotations` |
+//! | `sign`              | `keyid`, `message`                      | `signature`                              |
+//! | `verify`            | `keyid`, `message`, `signature`         | `valid`                                  |
+//! | `get_public_key`    | `keyid`                                 | `public_key`                             |
+//! | `get_secret`        | `name`, `annotations`                   | `secret`                                 |
+//! | `set_secret`        | `content`, `name`                       | `annotations`                            |
+//! | `list_secrets`      |                                         | `names`                                  |
+//! | `delete_secret`     | `name`, `annotations`                   |                                          |
+//!
+//! `init` is the first request after spawning, with the provider `name` and
+//! the plugin specific `settings` of the configuration. The plugin responds
+//! with the version of the protocol it speaks, which must be
+//! [`PROTOCOL_VERSION`], and the names of the methods it supports, see
+//! [`Capability`]. The `capabilities` default to `encrypt`, `decrypt`,
+//! `get_secret` and `set_secret`. Methods not supported by a plugin should
+//! return an error.
+//!
+//! A plugin that exits, breaks the protocol or does not respond in time is
+//! killed, and spawned again for the next request.
+
+use std::{collections::HashMap, path::PathBuf, process::Stdio, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+use zeroize::Zeroizing;
+
+use crate::{Capability, DataKey, Error, ProviderSettings, KMS};
+
+/// Version of the protocol between the driver and the plugins
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How to spawn a plugin.
+pub struct PluginConfig {
+    /// Path of the executable
+    pub path: PathBuf,
+
+    /// Arguments of the executable
+    pub args: Vec<String>,
+
+    /// Timeout of each request, including spawning
+    pub timeout: Duration,
+
+    /// Plugin specific settings, sent by `init`
+    pub settings: ProviderSettings,
+}
+
+/// Spawn the plugin of `config` as the provider `name`.
+pub async fn launch(name: &str, config: PluginConfig) -> Result<Arc<dyn KMS>> {
+    let mut plugin = PluginKms {
+        name: name.to_string(),
+        config,
+        capabilities: Vec::new(),
+        process: Mutex::new(None),
+    };
+
+    // Spawn the plugin now to find broken plugins early.
+    let (process, capabilities) = plugin.spawn().await?;
+    plugin.capabilities = capabilities;
+    *plugin.process.lock().await = Some(process);
+    Ok(Arc::new(plugin))
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    id: u64,
+    method: &'a str,
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct ErrorObject {
+    message: String,
+    code: Option<String>,
+}
+
+impl From<ErrorObject> for anyhow::Error {
+    fn from(e: ErrorObject) -> Self {
+        match e.code.as_deref() {
+            Some("not_found") => Error::NotFound(e.message).into(),
+            Some("permission_denied") => Error::PermissionDenied(e.message).into(),
+            Some("unavailable") => Error::Unavailable(e.message).into(),
+            Some("invalid_argument") => Error::InvalidArgument(e.message).into(),
+            _ => anyhow!(e.message),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: u64,
+    result: Option<Value>,
+    error: Option<ErrorObject>,
+}
+
+fn default_capabilities() -> Vec<Capability> {
+    vec![
+        Capability::Encrypt,
+        Capability::Decrypt,
+        Capability::GetSecret,
+        Capability::SetSecret,
+    ]
+}
+
+#[derive(Deserialize)]
+struct InitResult {
+    version: u32,
+    #[serde(default = "default_capabilities")]
+    capabilities: Vec<Capability>,
+}
+
+#[derive(Deserialize)]
+struct EncryptResult {
+    ciphertext: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct DecryptResult {
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+struct GenerateDataKeyResult {
+    plaintext: String,
+    ciphertext: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct SignResult {
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct VerifyResult {
+    valid: bool,
+}
+
+#[derive(Deserialize)]
+struct GetPublicKeyResult {
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+struct ListSecretsResult {
+    names: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct GetSecretResult {
+    secret: String,
+}
+
+#[derive(Deserialize)]
+struct SetSecretResult {
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// A running plugin
+struct Process {
+    /// Killed on drop
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Process {
+    /// Send a request and wait for its response. An `Err` means the process
+    /// is broken, while the error returned by the plugin is the inner `Err`.
+    async fn call(&mut self, method: &str, params: Value) -> Result<Result<Value>> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut request = serde_json::to_vec(&Request { id, method, params })?;
+        request.push(b'\n');
+        self.stdin.write_all(&request).await?;
+        self.stdin.flush().await?;
+
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line).await? == 0 {
+            bail!("plugin exited");
+        }
+        let response: Response =
+            serde_json::from_str(&line).context("invalid response of plugin")?;
+        if response.id != id {
+            bail!("plugin responded {} to request {id}", response.id);
+        }
+
+        Ok(match (response.result, response.error) {
+            (_, Some(error)) => Err(error.into()),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(anyhow!("plugin responded neither result nor error")),
+        })
+    }
+}
+
+pub struct PluginKms {
+    /// The provider name
+    name: String,
+    config: PluginConfig,
+
+    /// Methods supported by the plugin, reported by `init`
+    capabilities: Vec<Capability>,
+
+    /// The running plugin. Requests are sent one at a time.
+    process: Mutex<Option<Process>>,
+}
+
+impl PluginKms {
+    /// Spawn the plugin, returning it with its capabilities.
+    async fn spawn(&self) -> Result<(Process, Vec<Capability>)> {
+        let path = &self.config.path;
+        info!("spawn KMS plugin {} of {}", path.display(), self.name);
+        let mut child = Command::new(path)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("spawn KMS plugin {}", path.display()))?;
+        let mut process = Process {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: BufReader::new(child.stdout.take().expect("stdout is piped")),
+            _child: child,
+            next_id: 0,
+        };
+
+        let params = json!({
+            "version": PROTOCOL_VERSION,
+            "name": self.name,
+            "settings": self.config.settings,
+        });
+        let result = tokio::time::timeout(self.config.timeout, process.call("init", params))
+            .await
+            .map_err(|_| Error::Unavailable("init timed out".into()).into())
+            .and_then(|r| r)
+            .and_then(|r| r)
+            .with_context(|| format!("init KMS plugin {}", path.display()))?;
+        let result: InitResult = serde_json::from_value(result)?;
+        if result.version != PROTOCOL_VERSION {
+            bail!(
+                "KMS plugin {} speaks protocol version {}, but {PROTOCOL_VERSION} is required",
+                path.display(),
+                result.version
+            );
+        }
+
+        Ok((process, result.capabilities))
+    }
+
+    /// Call the `method` of the plugin, spawning it if it is not running.
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let mut process = self.process.lock().await;
+        if process.is_none() {
+            *process = Some(self.spawn().await?.0);
+        }
+
+        let running = process.as_mut().expect("plugin is spawned");
+        let result = match tokio::time::timeout(self.config.timeout, running.call(method, params))
+            .await
+        {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => {
+                warn!("KMS plugin of {} is broken: {e:#}", self.name);
+                *process = None;
+                return Err(e.context(format!("call {method} of KMS plugin")));
+            }
+            Err(_) => {
+                warn!("KMS plugin of {} timed out", self.name);
+                *process = None;
+                return Err(
+                    Error::Unavailable(format!("call {method} of KMS plugin timed out")).into(),
+                );
+            }
+        };
+
+        let result = result.with_context(|| format!("KMS plugin failed to {method}"))?;
+        serde_json::from_value(result)
+            .with_context(|| format!("invalid result of {method} of KMS plugin"))
+    }
+}
+
+#[async_trait]
+impl KMS for PluginKms {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        self.capabilities.clone()
+    }
+
+    async fn encrypt(
+        &self,
+        data: &[u8],
+        keyid: &str,
+    ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let params = json!({ "data": engine.encode(data), "keyid": keyid });
+        let result: EncryptResult = self.call("encrypt", params).await?;
+        let ciphertext = engine
+            .decode(result.ciphertext)
+            .context("decode ciphertext")?;
+        Ok((ciphertext, result.annotations))
+    }
+
+    async fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        keyid: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let params = json!({
+            "ciphertext": engine.encode(ciphertext),
+            "keyid": keyid,
+            "annotations": annotations,
+        });
+        let result: DecryptResult = self.call("decrypt", params).await?;
+        engine.decode(result.plaintext).context("decode plaintext")
+    }
+
+    async fn generate_data_key(&self, keyid: &str, len: usize) -> Result<DataKey> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let params = json!({ "keyid": keyid, "len": len });
+        let result: GenerateDataKeyResult = self.call("generate_data_key", params).await?;
+        let plaintext = Zeroizing::new(
+            engine
+                .decode(result.plaintext)
+                .context("decode plaintext")?,
+        );
+        let ciphertext = engine
+            .decode(result.ciphertext)
+            .context("decode ciphertext")?;
+        Ok(DataKey {
+            plaintext,
+            ciphertext,
+            annotations: result.annotations,
+        })
+    }
+
+    async fn sign(&self, keyid: &str, message: &[u8]) -> Result<Vec<u8>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let params = json!({ "keyid": keyid, "message": engine.encode(message) });
+        let result: SignResult = self.call("sign", params).await?;
+        engine.decode(result.signature).context("decode signature")
+    }
+
+    async fn verify(&self, keyid: &str, message: &[u8], signature: &[u8]) -> Result<bool> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let params = json!({
+            "keyid": keyid,
+            "message": engine.encode(message),
+            "signature": engine.encode(signature),
+        });
+        let result: VerifyResult = self.call("verify", params).await?;
+        Ok(result.valid)
+    }
+
+    async fn get_public_key(&self, keyid: &str) -> Result<String> {
+        let result: GetPublicKeyResult = self
+            .call("get_public_key", json!({ "keyid": keyid }))
+            .await?;
+        Ok(result.public_key)
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let params = json!({ "name": name, "annotations": annotations });
+        let result: GetSecretResult = self.call("get_secret", params).await?;
+        base64::engine::general_purpose::STANDARD
+            .decode(result.secret)
+            .context("decode secret")
+    }
+
+    async fn set_secret(&self, content: Vec<u8>, name: String) -> Result<HashMap<String, String>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let params = json!({ "content": engine.encode(content), "name": name });
+        let result: SetSecretResult = self.call("set_secret", params).await?;
+        Ok(result.annotations)
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<String>> {
+        let result: ListSecretsResult = self.call("list_secrets", json!({})).await?;
+        Ok(result.names)
+    }
+
+    async fn delete_secret(&self, name: &str, annotations: &HashMap<String, String>) -> Result<()> {
+        let params = json!({ "name": name, "annotations": annotations });
+        let _: Value = self.call("delete_secret", params).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::Path, sync::Arc, time::Duration};
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::{Capability, Error, KMS};
+
+    use super::{launch, PluginConfig};
+
+    /// A plugin "encrypting" by keeping the data unchanged and annotating the
+    /// provider name. It speaks the protocol version given by its argument,
+    /// logs each spawn, and exits when getting the secret `crash`.
+    const FAKE_PLUGIN: &str = r#"#!/bin/sh
+echo spawned >> "$(dirname "$0")/spawn.log"
+field() {
+    echo "$req" | sed -n "s/.*\"$1\":\"\([^\"]*\)\".*/\1/p"
+}
+while read -r req; do
+    id=$(echo "$req" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+    case "$(field method)" in
+    init)
+        name=$(field name)
+        echo "{\"id\":$id,\"result\":{\"version\":$1,\"capabilities\":[\"decrypt\",\"generate_data_key\"]}}" ;;
+    encrypt)
+        echo "{\"id\":$id,\"result\":{\"ciphertext\":\"$(field data)\",\"annotations\":{\"by\":\"$name\"}}}" ;;
+    generate_data_key)
+        echo "{\"id\":$id,\"result\":{\"plaintext\":\"a2V5\",\"ciphertext\":\"a2V5\"}}" ;;
+    decrypt)
+        echo "{\"id\":$id,\"result\":{\"plaintext\":\"$(field ciphertext)\"}}" ;;
+    get_secret)
+        [ "$(field name)" = "crash" ] && exit 1
+        if [ "$(field name)" = "missing" ]; then
+            echo "{\"id\":$id,\"error\":{\"message\":\"no secret\",\"code\":\"not_found\"}}"
+            continue
+        fi
+        echo "{\"id\":$id,\"result\":{\"secret\":\"$(printf %s "$(field name)" | base64)\"}}" ;;
+    *)
+        echo "{\"id\":$id,\"error\":{\"message\":\"unsupported\"}}" ;;
+    esac
+done
+"#;
+
+    async fn plugin(dir: &Path, version: &str) -> anyhow::Result<Arc<dyn KMS>> {
+        let path = dir.join("plugin");
+        std::fs::write(&path, FAKE_PLUGIN).expect("write fake plugin failed");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("chmod fake plugin failed");
+        let config = PluginConfig {
+            path,
+            args: vec![version.into()],
+            timeout: Duration::from_secs(5),
+            settings: json!({ "endpoint": "https://kms.example.io" })
+                .as_object()
+                .unwrap()
+                .clone(),
+        };
+        launch("vendor", config).await
+    }
+
+    fn spawns(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join("spawn.log"))
+            .expect("read spawn log failed")
+            .lines()
+            .count()
+    }
+
+    #[tokio::test]
+    async fn encrypt_decrypt() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = plugin(dir.path(), "1").await.expect("launch plugin failed");
+        assert_eq!(kms.name(), "vendor");
+        assert_eq!(
+            kms.capabilities(),
+            [Capability::Decrypt, Capability::GenerateDataKey]
+        );
+
+        let (ciphertext, annotations) = kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        assert_eq!(ciphertext, b"data");
+        assert_eq!(annotations["by"], "vendor");
+        let plaintext = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let err = kms
+            .set_secret(b"secret".to_vec(), "name".into())
+            .await
+            .expect_err("set secret should fail");
+        assert!(format!("{err:#}").contains("unsupported"));
+
+        let data_key = kms
+            .generate_data_key("key1", 3)
+            .await
+            .expect("generate data key failed");
+        assert_eq!(*data_key.plaintext, b"key");
+        assert_eq!(data_key.ciphertext, b"key");
+    }
+
+    #[tokio::test]
+    async fn respawn_crashed_plugin() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = plugin(dir.path(), "1").await.expect("launch plugin failed");
+
+        let annotations = Default::default();
+        assert!(kms.get_secret("crash", &annotations).await.is_err());
+        assert_eq!(spawns(dir.path()), 1);
+
+        let secret = kms
+            .get_secret("name", &annotations)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"name");
+        assert_eq!(spawns(dir.path()), 2);
+    }
+
+    #[tokio::test]
+    async fn typed_plugin_error() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = plugin(dir.path(), "1").await.expect("launch plugin failed");
+
+        let err = kms
+            .get_secret("missing", &Default::default())
+            .await
+            .expect_err("get missing secret should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NotFound(_))
+        ));
+        assert_eq!(spawns(dir.path()), 1);
+    }
+
+    #[rstest]
+    #[case("2")]
+    #[case("\"1\"")]
+    #[tokio::test]
+    async fn unsupported_version(#[case] version: &str) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        assert!(plugin(dir.path(), version).await.is_err());
+    }
+}
@@ -0,0 +1,536 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # Local KMS
+//!
+//! A KMS driver keeping the keys and secrets as files inside a local
+//! directory. It is meant for development and testing, where no real KMS is
+//! available, and should not be used in production.
+//!
+//! The layout of the directory is
+//! - `keys/<keyid>`: AES-256 KEKs. A KEK is generated the first time it is
+//!   used to encrypt.
+//! - `secrets/<name>`: named secrets.
+//! - `salt`: salt of the passphrase, only when a passphrase is given.
+//! - `tmp/`: files being written, which are renamed into place when
+//!   complete, so that a file is never read half written.
+//!
+//! If a `passphrase` is given in the settings, all the files are encrypted
+//! with A256GCM by a key derived from the passphrase with PBKDF2.
+//!
+//! Settings of the driver:
+//! - `dir`: the directory, required.
+//! - `passphrase`: the passphrase protecting the files, optional.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use crypto::WrapType;
+use rand::Rng;
+use serde::Deserialize;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::{Capability, DataKey, Error, ProviderSettings, KMS};
+
+/// Name of the driver
+pub const NAME: &str = "local";
+
+/// Rounds of PBKDF2 to derive the key from the passphrase
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// Name of the annotation carrying the IV of an encryption
+const IV_ANNOTATION: &str = "iv";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    dir: PathBuf,
+    passphrase: Option<String>,
+}
+
+pub struct LocalKms {
+    dir: PathBuf,
+
+    /// Key derived from the passphrase to protect the files, if any
+    file_key: Option<Zeroizing<Vec<u8>>>,
+}
+
+/// Builder of the driver registered in [`crate::Registry`].
+pub fn build(settings: &ProviderSettings) -> Result<Arc<dyn KMS>> {
+    let settings: Settings =
+        serde_json::from_value(settings.clone().into()).context("invalid settings of local KMS")?;
+    Ok(Arc::new(LocalKms::new(settings.dir, settings.passphrase)?))
+}
+
+impl LocalKms {
+    /// Open the local KMS of `dir`, which is created if not existing.
+    pub fn new(dir: PathBuf, passphrase: Option<String>) -> Result<Self> {
+        for sub in ["keys", "secrets", "tmp"] {
+            std::fs::create_dir_all(dir.join(sub))
+                .with_context(|| format!("create directory {}", dir.join(sub).display()))?;
+        }
+
+        let file_key = match passphrase {
+            Some(passphrase) => {
+                let passphrase = Zeroizing::new(passphrase);
+                let salt = load_or_create_salt(&dir)?;
+                let mut key = Zeroizing::new(vec![0u8; KEY_LEN]);
+                pbkdf2::pbkdf2_hmac::<Sha256>(
+                    passphrase.as_bytes(),
+                    &salt,
+                    PBKDF2_ROUNDS,
+                    &mut key,
+                );
+                Some(key)
+            }
+            None => None,
+        };
+
+        Ok(Self { dir, file_key })
+    }
+
+    async fn read_file(&self, path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+        let content = Zeroizing::new(
+            tokio::fs::read(path)
+                .await
+                .map_err(|e| io_error(e, path))
+                .with_context(|| format!("read {}", path.display()))?,
+        );
+        let Some(file_key) = &self.file_key else {
+            return Ok(content);
+        };
+
+        if content.len() < IV_LEN {
+            bail!("{} is corrupted", path.display());
+        }
+        let (iv, ciphertext) = content.split_at(IV_LEN);
+        let plaintext = crypto::decrypt(
+            file_key.clone(),
+            ciphertext.to_vec(),
+            iv.to_vec(),
+            &[],
+            WrapType::Aes256Gcm,
+        )
+        .with_context(|| format!("decrypt {}, is the passphrase right?", path.display()))?;
+        Ok(Zeroizing::new(plaintext))
+    }
+
+    /// Write `content` to `path` through a temporary file, which replaces
+    /// the file of `path` when complete. With `create_new`, an existing
+    /// file of `path` is kept, and the write fails with `AlreadyExists`.
+    async fn write_file(&self, path: &Path, content: &[u8], create_new: bool) -> Result<()> {
+        let content = match &self.file_key {
+            Some(file_key) => {
+                let iv = random_bytes::<IV_LEN>();
+                let ciphertext = crypto::encrypt(
+                    file_key.clone(),
+                    content.to_vec(),
+                    iv.to_vec(),
+                    &[],
+                    WrapType::Aes256Gcm,
+                )?;
+                Zeroizing::new([&iv[..], &ciphertext].concat())
+            }
+            None => Zeroizing::new(content.to_vec()),
+        };
+
+        let tmp = temp_path(&self.dir);
+        write_private(&tmp, &content)
+            .await
+            .with_context(|| format!("write {}", tmp.display()))?;
+        let res = if create_new {
+            tokio::fs::hard_link(&tmp, path).await
+        } else {
+            tokio::fs::rename(&tmp, path).await
+        };
+        let _ = tokio::fs::remove_file(&tmp).await;
+        res.with_context(|| format!("write {}", path.display()))
+    }
+
+    /// Get the KEK of `keyid`, which is generated if `create` is set and the
+    /// KEK does not exist.
+    async fn kek(&self, keyid: &str, create: bool) -> Result<Zeroizing<Vec<u8>>> {
+        let path = self.dir.join("keys").join(file_name(keyid)?);
+        if create && !tokio::fs::try_exists(&path).await? {
+            let kek = Zeroizing::new(random_bytes::<KEY_LEN>().to_vec());
+            match self.write_file(&path, &kek, true).await {
+                Ok(()) => return Ok(kek),
+                // Another request has just created the KEK, which is read
+                // below instead.
+                Err(e) if is_already_exists(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let kek = self.read_file(&path).await?;
+        if kek.len() != KEY_LEN {
+            bail!("KEK {keyid} is not an AES-256 key");
+        }
+
+        Ok(kek)
+    }
+}
+
+#[async_trait]
+impl KMS for LocalKms {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::Encrypt,
+            Capability::Decrypt,
+            Capability::GenerateDataKey,
+            Capability::GetSecret,
+            Capability::SetSecret,
+            Capability::ListSecrets,
+            Capability::DeleteSecret,
+        ]
+    }
+
+    async fn encrypt(
+        &self,
+        data: &[u8],
+        keyid: &str,
+    ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+        let kek = self.kek(keyid, true).await?;
+        let iv = random_bytes::<IV_LEN>();
+        let ciphertext =
+            crypto::encrypt(kek, data.to_vec(), iv.to_vec(), &[], WrapType::Aes256Gcm)?;
+
+        let engine = base64::engine::general_purpose::STANDARD;
+        let annotations = [(IV_ANNOTATION.to_string(), engine.encode(iv))]
+            .into_iter()
+            .collect();
+        Ok((ciphertext, annotations))
+    }
+
+    async fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        keyid: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let iv = annotations.get(IV_ANNOTATION).ok_or_else(|| {
+            Error::InvalidArgument(format!("no `{IV_ANNOTATION}` annotation given"))
+        })?;
+        let iv = base64::engine::general_purpose::STANDARD
+            .decode(iv)
+            .context("decode iv")?;
+        let kek = self.kek(keyid, false).await?;
+        crypto::decrypt(kek, ciphertext.to_vec(), iv, &[], WrapType::Aes256Gcm)
+    }
+
+    async fn generate_data_key(&self, keyid: &str, len: usize) -> Result<DataKey> {
+        let mut plaintext = Zeroizing::new(vec![0u8; len]);
+        rand::thread_rng().fill(&mut plaintext[..]);
+        let (ciphertext, annotations) = self.encrypt(&plaintext, keyid).await?;
+        Ok(DataKey {
+            plaintext,
+            ciphertext,
+            annotations,
+        })
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+        _annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let path = self.dir.join("secrets").join(file_name(name)?);
+        let secret = self.read_file(&path).await?;
+        Ok(secret.to_vec())
+    }
+
+    async fn set_secret(&self, content: Vec<u8>, name: String) -> Result<HashMap<String, String>> {
+        let content = Zeroizing::new(content);
+        let path = self.dir.join("secrets").join(file_name(&name)?);
+        self.write_file(&path, &content, false).await?;
+        Ok(HashMap::new())
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(self.dir.join("secrets")).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    async fn delete_secret(
+        &self,
+        name: &str,
+        _annotations: &HashMap<String, String>,
+    ) -> Result<()> {
+        let path = self.dir.join("secrets").join(file_name(name)?);
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| io_error(e, &path))
+            .with_context(|| format!("delete {}", path.display()))
+    }
+}
+
+/// Check that `name` can be used as a file name inside the directory.
+fn file_name(name: &str) -> Result<&str> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(Error::InvalidArgument(format!(
+            "invalid key or secret name `{name}` for local KMS"
+        ))
+        .into());
+    }
+
+    Ok(name)
+}
+
+/// Tell the missing keys and secrets from the other failures to access
+/// the file of `path`.
+fn io_error(e: std::io::Error, path: &Path) -> anyhow::Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            Error::NotFound(format!("{} not found", path.display())).into()
+        }
+        _ => e.into(),
+    }
+}
+
+fn is_already_exists(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::AlreadyExists)
+}
+
+/// A unique path inside the `tmp/` directory of the KMS directory `dir`.
+fn temp_path(dir: &Path) -> PathBuf {
+    dir.join("tmp")
+        .join(format!("{:032x}", u128::from_ne_bytes(random_bytes())))
+}
+
+/// Load the salt of the KMS directory `dir`, which is created if not
+/// existing. A salt created by another process meanwhile is kept.
+fn load_or_create_salt(dir: &Path) -> Result<Vec<u8>> {
+    let path = dir.join("salt");
+    if !path.exists() {
+        let tmp = temp_path(dir);
+        std::fs::write(&tmp, random_bytes::<SALT_LEN>())
+            .with_context(|| format!("write {}", tmp.display()))?;
+        let res = std::fs::hard_link(&tmp, &path);
+        let _ = std::fs::remove_file(&tmp);
+        if let Err(e) = res {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(e).with_context(|| format!("write {}", path.display()));
+            }
+        }
+    }
+
+    let salt = std::fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+    if salt.len() != SALT_LEN {
+        bail!("{} is corrupted", path.display());
+    }
+    Ok(salt)
+}
+
+/// Create `path` with `content`, only readable by the owner. The file must
+/// not exist.
+async fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(content).await?;
+    file.flush().await
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    rand::thread_rng().fill(&mut bytes[..]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::{Capability, Error, Registry, KMS};
+
+    use super::LocalKms;
+
+    #[rstest]
+    #[case(None)]
+    #[case(Some("passphrase"))]
+    #[tokio::test]
+    async fn encrypt_decrypt(#[case] passphrase: Option<&str>) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = LocalKms::new(dir.path().into(), passphrase.map(String::from))
+            .expect("open local kms failed");
+
+        let (ciphertext, annotations) = kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        assert_ne!(ciphertext, b"data");
+        let plaintext = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        // The KEK is kept in the directory.
+        let kms = LocalKms::new(dir.path().into(), passphrase.map(String::from))
+            .expect("open local kms failed");
+        let plaintext = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        assert!(kms
+            .decrypt(&ciphertext, "key2", &annotations)
+            .await
+            .is_err());
+    }
+
+    #[rstest]
+    #[case(None)]
+    #[case(Some("passphrase"))]
+    #[tokio::test]
+    async fn concurrent_encrypt(#[case] passphrase: Option<&str>) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = Arc::new(
+            LocalKms::new(dir.path().into(), passphrase.map(String::from))
+                .expect("open local kms failed"),
+        );
+
+        // All the requests race to create the KEK of the fresh key id, and
+        // must end up using the same one.
+        let tasks: Vec<_> = (0..16)
+            .map(|_| {
+                let kms = kms.clone();
+                tokio::spawn(async move { kms.encrypt(b"data", "fresh").await })
+            })
+            .collect();
+        for task in tasks {
+            let (ciphertext, annotations) = task
+                .await
+                .expect("join task failed")
+                .expect("encrypt failed");
+            let plaintext = kms
+                .decrypt(&ciphertext, "fresh", &annotations)
+                .await
+                .expect("decrypt failed");
+            assert_eq!(plaintext, b"data");
+        }
+
+        let tmp = std::fs::read_dir(dir.path().join("tmp")).expect("read tmp dir failed");
+        assert_eq!(tmp.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn wrong_passphrase() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = LocalKms::new(dir.path().into(), Some("passphrase".into()))
+            .expect("open local kms failed");
+        kms.set_secret(b"secret".to_vec(), "name".into())
+            .await
+            .expect("set secret failed");
+
+        let kms = LocalKms::new(dir.path().into(), Some("another".into()))
+            .expect("open local kms failed");
+        assert!(kms.get_secret("name", &HashMap::new()).await.is_err());
+    }
+
+    #[rstest]
+    #[case("name", true)]
+    #[case("", false)]
+    #[case("..", false)]
+    #[case("../name", false)]
+    #[tokio::test]
+    async fn secrets(#[case] name: &str, #[case] valid: bool) {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let settings = json!({ "dir": dir.path() });
+        let kms = Registry::builtin()
+            .new_client("local", settings.as_object().unwrap())
+            .await
+            .expect("create local kms failed");
+
+        let res = kms.set_secret(b"secret".to_vec(), name.into()).await;
+        assert_eq!(res.is_ok(), valid);
+        if valid {
+            let secret = kms
+                .get_secret(name, &HashMap::new())
+                .await
+                .expect("get secret failed");
+            assert_eq!(secret, b"secret");
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_data_key() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = LocalKms::new(dir.path().into(), None).expect("open local kms failed");
+
+        let data_key = kms
+            .generate_data_key("key1", 32)
+            .await
+            .expect("generate data key failed");
+        assert_eq!(data_key.plaintext.len(), 32);
+        let plaintext = kms
+            .decrypt(&data_key.ciphertext, "key1", &data_key.annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, *data_key.plaintext);
+    }
+
+    #[tokio::test]
+    async fn list_delete_secrets() {
+        let dir = tempfile::tempdir().expect("create temp dir failed");
+        let kms = LocalKms::new(dir.path().into(), None).expect("open local kms failed");
+        assert!(!kms.supports(Capability::Sign));
+        let err = kms
+            .sign("key1", b"data")
+            .await
+            .expect_err("sign should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Unsupported { .. })
+        ));
+
+        for name in ["b", "a"] {
+            kms.set_secret(b"secret".to_vec(), name.into())
+                .await
+                .expect("set secret failed");
+        }
+        assert_eq!(kms.list_secrets().await.expect("list failed"), ["a", "b"]);
+
+        kms.delete_secret("a", &HashMap::new())
+            .await
+            .expect("delete secret failed");
+        assert_eq!(kms.list_secrets().await.expect("list failed"), ["b"]);
+        let err = kms
+            .get_secret("a", &HashMap::new())
+            .await
+            .expect_err("get deleted secret should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Registry of the KMS drivers. Each concrete [`KMS`] implementation
+//! registers a builder under its [`KMS::name`], and users can instantiate
+//! drivers by name together with the driver specific settings.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::KMS;
+
+#[cfg(feature = "ali")]
+pub mod ali;
+
+#[cfg(feature = "aws")]
+pub mod aws;
+
+#[cfg(feature = "external")]
+pub mod external;
+
+#[cfg(feature = "local")]
+pub mod local;
+
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+
+#[cfg(feature = "vault")]
+pub mod vault;
+
+/// Driver specific settings, e.g. credentials and endpoints. The keys
+/// are defined by each driver.
+pub type ProviderSettings = serde_json::Map<String, serde_json::Value>;
+
+/// Function to create a KMS driver from its [`ProviderSettings`].
+pub type KmsBuilder = fn(&ProviderSettings) -> Result<Arc<dyn KMS>>;
+
+/// Registry of KMS drivers, keyed by the name of the driver.
+#[derive(Default)]
+pub struct Registry {
+    builders: HashMap<String, KmsBuilder>,
+}
+
+impl Registry {
+    /// Create a registry with all the drivers built into this crate.
+    pub fn builtin() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::default();
+
+        #[cfg(feature = "ali")]
+        registry.register(ali::NAME, ali::build);
+
+        #[cfg(feature = "aws")]
+        registry.register(aws::NAME, aws::build);
+
+        #[cfg(feature = "local")]
+        registry.register(local::NAME, local::build);
+
+        #[cfg(feature = "pkcs11")]
+        registry.register(pkcs11::NAME, pkcs11::build);
+
+        #[cfg(feature = "vault")]
+        registry.register(vault::NAME, vault::build);
+
+        registry
+    }
+
+    /// Register a driver builder under `name`. The driver created by the
+    /// builder must report the same name by [`KMS::name`]. A builder already
+    /// registered with the same name will be replaced.
+    pub fn register(&mut self, name: &str, builder: KmsBuilder) {
+        self.builders.insert(name.to_string(), builder);
+    }
+
+    /// Names of all the registered drivers.
+    pub fn drivers(&self) -> Vec<&str> {
+        self.builders.keys().map(|k| &k[..]).collect()
+    }
+
+    /// Create a new driver instance of the given `name` with the `settings`.
+    pub async fn new_client(
+        &self,
+        name: &str,
+        settings: &ProviderSettings,
+    ) -> Result<Arc<dyn KMS>> {
+        let builder = self
+            .builders
+            .get(name)
+            .ok_or_else(|| anyhow!("KMS driver {name} is not supported"))?;
+        let client = builder(settings)?;
+        let driver_name = client.name();
+        if driver_name != name {
+            bail!("KMS driver registered as {name} reports name {driver_name}");
+        }
+
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use rstest::rstest;
+
+    use crate::KMS;
+
+    use super::{ProviderSettings, Registry};
+
+    struct Dummy {
+        name: String,
+    }
+
+    #[async_trait]
+    impl KMS for Dummy {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn decrypt(
+            &self,
+            ciphertext: &[u8],
+            _keyid: &str,
+            _annotations: &HashMap<String, String>,
+        ) -> Result<Vec<u8>> {
+            Ok(ciphertext.to_vec())
+        }
+
+        async fn get_secret(
+            &self,
+            name: &str,
+            _annotations: &HashMap<String, String>,
+        ) -> Result<Vec<u8>> {
+            Ok(name.as_bytes().to_vec())
+        }
+    }
+
+    fn dummy(settings: &ProviderSettings) -> Result<Arc<dyn KMS>> {
+        let name = settings
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or("dummy")
+            .to_string();
+        Ok(Arc::new(Dummy { name }))
+    }
+
+    #[rstest]
+    #[case("dummy", true)]
+    #[case("another", false)]
+    #[tokio::test]
+    async fn new_client(#[case] reported_name: &str, #[case] succeed: bool) {
+        let mut registry = Registry::default();
+        registry.register("dummy", dummy);
+        let settings = [("name".to_string(), reported_name.into())]
+            .into_iter()
+            .collect();
+        let client = registry.new_client("dummy", &settings).await;
+        assert_eq!(client.is_ok(), succeed);
+    }
+
+    #[tokio::test]
+    async fn unknown_driver() {
+        let registry = Registry::default();
+        let client = registry
+            .new_client("not-exist", &ProviderSettings::new())
+            .await;
+        assert!(client.is_err());
+    }
+}
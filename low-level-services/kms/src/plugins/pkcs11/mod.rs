@@ -0,0 +1,459 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # PKCS#11
+//!
+//! The driver wraps data with the keys held in a PKCS#11 token, e.g. an HSM,
+//! so the keys never leave the token. The key id is the label of the key,
+//! and the key decides the mechanism:
+//! - an AES secret key wraps with `CKM_AES_GCM`, with a random 96-bit IV and
+//!   a 128-bit tag.
+//! - an RSA key pair wraps with `CKM_RSA_PKCS_OAEP`, with SHA-256 and
+//!   MGF1-SHA-256. The public key encrypts and the private key decrypts.
+//!
+//! The data keys are generated by the random number generator of the token.
+//! The secrets are the `CKO_DATA` objects, with the name as label.
+//!
+//! Settings of the driver:
+//! - `module`: path of the PKCS#11 module, e.g.
+//!   `/usr/lib/softhsm/libsofthsm2.so`, required.
+//! - `slot`: id of the slot of the token, or
+//! - `token`: label of the token. One of `slot` and `token` is required.
+//! - `pin`: the user PIN, defaults to the env `PKCS11_PIN`.
+//!
+//! The annotations of the encryption results are
+//! - `mechanism`: `aes-gcm` or `rsa-oaep`.
+//! - `iv`: the base64 encoded IV of `aes-gcm`.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    error::{Error as Pkcs11Error, RvError},
+    mechanism::{
+        aead::GcmParams,
+        rsa::{PkcsMgfType, PkcsOaepParams, PkcsOaepSource},
+        Mechanism, MechanismType,
+    },
+    object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    slot::Slot,
+    types::AuthPin,
+};
+use rand::Rng;
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use crate::{Capability, DataKey, Error, ProviderSettings, KMS};
+
+/// Name of the driver
+pub const NAME: &str = "pkcs11";
+
+/// Env of the user PIN
+pub const PKCS11_PIN_ENV: &str = "PKCS11_PIN";
+
+const MECHANISM_ANNOTATION: &str = "mechanism";
+const IV_ANNOTATION: &str = "iv";
+
+const AES_GCM: &str = "aes-gcm";
+const RSA_OAEP: &str = "rsa-oaep";
+
+const GCM_IV_LEN: usize = 12;
+const GCM_TAG_BITS: u64 = 128;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    module: PathBuf,
+    slot: Option<u64>,
+    token: Option<String>,
+    pin: Option<String>,
+}
+
+pub struct Pkcs11Kms {
+    pkcs11: Pkcs11,
+    slot: Slot,
+    pin: AuthPin,
+}
+
+/// Builder of the driver registered in [`crate::Registry`].
+pub fn build(settings: &ProviderSettings) -> Result<Arc<dyn KMS>> {
+    let settings: Settings = serde_json::from_value(settings.clone().into())
+        .context("invalid settings of pkcs11 KMS")?;
+    let pin = match settings.pin {
+        Some(pin) => pin,
+        None => std::env::var(PKCS11_PIN_ENV)
+            .map_err(|_| anyhow!("pkcs11 KMS requires `pin` or env {PKCS11_PIN_ENV}"))?,
+    };
+
+    let pkcs11 = Pkcs11::new(&settings.module)
+        .with_context(|| format!("load PKCS#11 module {}", settings.module.display()))?;
+    match pkcs11.initialize(CInitializeArgs::OsThreads) {
+        Err(Pkcs11Error::Pkcs11(RvError::CryptokiAlreadyInitialized)) => {}
+        other => other.context("initialize PKCS#11 module")?,
+    }
+
+    let slots = pkcs11.get_slots_with_token()?;
+    let slot = match (settings.slot, settings.token) {
+        (Some(id), None) => slots
+            .into_iter()
+            .find(|slot| slot.id() == id)
+            .ok_or_else(|| anyhow!("no PKCS#11 token found in slot {id}"))?,
+        (None, Some(label)) => slots
+            .into_iter()
+            .find(|slot| {
+                pkcs11
+                    .get_token_info(*slot)
+                    .is_ok_and(|info| info.label() == label)
+            })
+            .ok_or_else(|| anyhow!("no PKCS#11 token labeled `{label}` found"))?,
+        _ => bail!("pkcs11 KMS requires exactly one of `slot` and `token`"),
+    };
+
+    Ok(Arc::new(Pkcs11Kms {
+        pkcs11,
+        slot,
+        pin: AuthPin::new(pin),
+    }))
+}
+
+/// Find the only object of `class` with `label`.
+fn find_object(session: &Session, class: ObjectClass, label: &str) -> Result<Option<ObjectHandle>> {
+    let objects = session.find_objects(&[
+        Attribute::Class(class),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ])?;
+    if objects.len() > 1 {
+        bail!("more than one PKCS#11 object of {class} labeled {label}");
+    }
+
+    Ok(objects.into_iter().next())
+}
+
+fn oaep_params() -> PkcsOaepParams<'static> {
+    PkcsOaepParams::new(
+        MechanismType::SHA256,
+        PkcsMgfType::MGF1_SHA256,
+        PkcsOaepSource::empty(),
+    )
+}
+
+/// Wrap `data` with the key labeled `label`, choosing the mechanism by the
+/// type of the key.
+fn wrap(session: &Session, label: &str, data: &[u8]) -> Result<(Vec<u8>, HashMap<String, String>)> {
+    if let Some(key) = find_object(session, ObjectClass::SECRET_KEY, label)? {
+        let mut iv = [0u8; GCM_IV_LEN];
+        rand::thread_rng().fill(&mut iv[..]);
+        let params = GcmParams::new(&iv, &[], GCM_TAG_BITS.into());
+        let ciphertext = session.encrypt(&Mechanism::AesGcm(params), key, data)?;
+        let annotations = HashMap::from([
+            (MECHANISM_ANNOTATION.into(), AES_GCM.into()),
+            (
+                IV_ANNOTATION.into(),
+                base64::engine::general_purpose::STANDARD.encode(iv),
+            ),
+        ]);
+        return Ok((ciphertext, annotations));
+    }
+
+    if let Some(key) = find_object(session, ObjectClass::PUBLIC_KEY, label)? {
+        let mechanism = Mechanism::RsaPkcsOaep(oaep_params());
+        let ciphertext = session.encrypt(&mechanism, key, data)?;
+        let annotations = HashMap::from([(MECHANISM_ANNOTATION.into(), RSA_OAEP.into())]);
+        return Ok((ciphertext, annotations));
+    }
+
+    Err(Error::NotFound(format!(
+        "no PKCS#11 secret key or public key labeled {label}"
+    ))
+    .into())
+}
+
+impl Pkcs11Kms {
+    /// Run `f` with a session logged in as the user. The PKCS#11 calls block,
+    /// so they run on the blocking threads.
+    async fn with_session<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Session) -> Result<T> + Send + 'static,
+    {
+        let pkcs11 = self.pkcs11.clone();
+        let slot = self.slot;
+        let pin = self.pin.clone();
+        tokio::task::spawn_blocking(move || {
+            let session = pkcs11
+                .open_rw_session(slot)
+                .context("open PKCS#11 session")?;
+            match session.login(UserType::User, Some(&pin)) {
+                Err(Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn)) => {}
+                Err(Pkcs11Error::Pkcs11(e @ (RvError::PinIncorrect | RvError::PinLocked))) => {
+                    return Err(
+                        Error::PermissionDenied(format!("log in to PKCS#11 token: {e}")).into(),
+                    )
+                }
+                other => other.context("log in to PKCS#11 token")?,
+            }
+            f(&session)
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl KMS for Pkcs11Kms {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::Encrypt,
+            Capability::Decrypt,
+            Capability::GenerateDataKey,
+            Capability::GetSecret,
+            Capability::SetSecret,
+        ]
+    }
+
+    async fn encrypt(
+        &self,
+        data: &[u8],
+        keyid: &str,
+    ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+        let data = data.to_vec();
+        let label = keyid.to_string();
+        self.with_session(move |session| wrap(session, &label, &data))
+            .await
+    }
+
+    async fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        keyid: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let ciphertext = ciphertext.to_vec();
+        let label = keyid.to_string();
+        let mechanism = annotations.get(MECHANISM_ANNOTATION).ok_or_else(|| {
+            Error::InvalidArgument(format!("no `{MECHANISM_ANNOTATION}` in the annotations"))
+        })?;
+        match &mechanism[..] {
+            AES_GCM => {
+                let iv = annotations.get(IV_ANNOTATION).ok_or_else(|| {
+                    Error::InvalidArgument(format!("no `{IV_ANNOTATION}` in the annotations"))
+                })?;
+                let iv = base64::engine::general_purpose::STANDARD.decode(iv)?;
+                self.with_session(move |session| {
+                    let key = find_object(session, ObjectClass::SECRET_KEY, &label)?.ok_or_else(
+                        || Error::NotFound(format!("no PKCS#11 secret key labeled {label}")),
+                    )?;
+                    let params = GcmParams::new(&iv, &[], GCM_TAG_BITS.into());
+                    Ok(session.decrypt(&Mechanism::AesGcm(params), key, &ciphertext)?)
+                })
+                .await
+            }
+            RSA_OAEP => {
+                self.with_session(move |session| {
+                    let key = find_object(session, ObjectClass::PRIVATE_KEY, &label)?.ok_or_else(
+                        || Error::NotFound(format!("no PKCS#11 private key labeled {label}")),
+                    )?;
+                    let mechanism = Mechanism::RsaPkcsOaep(oaep_params());
+                    Ok(session.decrypt(&mechanism, key, &ciphertext)?)
+                })
+                .await
+            }
+            other => {
+                Err(Error::InvalidArgument(format!("unsupported PKCS#11 mechanism {other}")).into())
+            }
+        }
+    }
+
+    async fn generate_data_key(&self, keyid: &str, len: usize) -> Result<DataKey> {
+        let label = keyid.to_string();
+        let len = u32::try_from(len).context("data key too long")?;
+        self.with_session(move |session| {
+            let plaintext = Zeroizing::new(session.generate_random_vec(len)?);
+            let (ciphertext, annotations) = wrap(session, &label, &plaintext)?;
+            Ok(DataKey {
+                plaintext,
+                ciphertext,
+                annotations,
+            })
+        })
+        .await
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+        _annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let label = name.to_string();
+        self.with_session(move |session| {
+            let object = find_object(session, ObjectClass::DATA, &label)?.ok_or_else(|| {
+                Error::NotFound(format!("no PKCS#11 data object labeled {label}"))
+            })?;
+            match session
+                .get_attributes(object, &[AttributeType::Value])?
+                .pop()
+            {
+                Some(Attribute::Value(value)) => Ok(value),
+                _ => bail!("no value of PKCS#11 data object labeled {label}"),
+            }
+        })
+        .await
+    }
+
+    async fn set_secret(&self, content: Vec<u8>, name: String) -> Result<HashMap<String, String>> {
+        self.with_session(move |session| {
+            if find_object(session, ObjectClass::DATA, &name)?.is_some() {
+                bail!("PKCS#11 data object labeled {name} already exists");
+            }
+
+            session.create_object(&[
+                Attribute::Class(ObjectClass::DATA),
+                Attribute::Token(true),
+                Attribute::Private(true),
+                Attribute::Label(name.into_bytes()),
+                Attribute::Value(content),
+            ])?;
+            Ok(HashMap::new())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use cryptoki::{
+        context::{CInitializeArgs, Pkcs11},
+        mechanism::Mechanism,
+        object::Attribute,
+        session::UserType,
+        types::AuthPin,
+    };
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::{Registry, KMS};
+
+    /// Settings of the token to test, given by `PKCS11_MODULE`, `PKCS11_TOKEN`
+    /// and `PKCS11_PIN`, e.g. a token of SoftHSMv2 created by
+    ///
+    /// ```shell
+    /// softhsm2-util --init-token --free --label cdh --pin 1234 --so-pin 1234
+    /// ```
+    ///
+    /// The tests are ignored by default, run them by
+    /// `cargo test -p kms pkcs11 -- --ignored`.
+    fn token() -> (String, String, String) {
+        let var = |name| std::env::var(name).unwrap_or_else(|_| panic!("{name} is not set"));
+        (var("PKCS11_MODULE"), var("PKCS11_TOKEN"), var("PKCS11_PIN"))
+    }
+
+    /// Generate a non-extractable key of `mechanism` labeled `label` in the
+    /// token.
+    fn generate_key(module: &str, token: &str, pin: &str, mechanism: &str, label: &str) {
+        let pkcs11 = Pkcs11::new(module).expect("load module failed");
+        let _ = pkcs11.initialize(CInitializeArgs::OsThreads);
+        let slot = pkcs11
+            .get_slots_with_token()
+            .expect("get slots failed")
+            .into_iter()
+            .find(|slot| pkcs11.get_token_info(*slot).unwrap().label() == token)
+            .expect("token not found");
+        let session = pkcs11.open_rw_session(slot).expect("open session failed");
+        let _ = session.login(UserType::User, Some(&AuthPin::new(pin.into())));
+
+        let private = [
+            Attribute::Label(label.as_bytes().to_vec()),
+            Attribute::Token(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Decrypt(true),
+        ];
+        if mechanism == "aes-gcm" {
+            let mut template = private.to_vec();
+            template.extend([Attribute::ValueLen(32.into()), Attribute::Encrypt(true)]);
+            session
+                .generate_key(&Mechanism::AesKeyGen, &template)
+                .expect("generate AES key failed");
+        } else {
+            let public = [
+                Attribute::Label(label.as_bytes().to_vec()),
+                Attribute::Token(true),
+                Attribute::ModulusBits(2048.into()),
+                Attribute::PublicExponent(vec![1, 0, 1]),
+                Attribute::Encrypt(true),
+            ];
+            session
+                .generate_key_pair(&Mechanism::RsaPkcsKeyPairGen, &public, &private)
+                .expect("generate RSA key pair failed");
+        }
+    }
+
+    async fn pkcs11_kms(module: &str, token: &str, pin: &str) -> Arc<dyn KMS> {
+        let settings = json!({ "module": module, "token": token, "pin": pin });
+        Registry::builtin()
+            .new_client("pkcs11", settings.as_object().unwrap())
+            .await
+            .expect("create pkcs11 kms failed")
+    }
+
+    #[rstest]
+    #[case("aes-gcm")]
+    #[case("rsa-oaep")]
+    #[tokio::test]
+    #[ignore = "needs SoftHSMv2 (PKCS11_MODULE/TOKEN/PIN)"]
+    async fn encrypt_decrypt(#[case] mechanism: &str) {
+        let (module, token, pin) = token();
+        let label = format!("cdh-test-{mechanism}-{}", rand::random::<u32>());
+        generate_key(&module, &token, &pin, mechanism, &label);
+        let kms = pkcs11_kms(&module, &token, &pin).await;
+
+        let (ciphertext, annotations) = kms.encrypt(b"data", &label).await.expect("encrypt failed");
+        assert_eq!(annotations["mechanism"], mechanism);
+        let plaintext = kms
+            .decrypt(&ciphertext, &label, &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        let data_key = kms
+            .generate_data_key(&label, 32)
+            .await
+            .expect("generate data key failed");
+        let plaintext = kms
+            .decrypt(&data_key.ciphertext, &label, &data_key.annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, *data_key.plaintext);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs SoftHSMv2 (PKCS11_MODULE/TOKEN/PIN)"]
+    async fn set_get_secret() {
+        let (module, token, pin) = token();
+        let kms = pkcs11_kms(&module, &token, &pin).await;
+        let name = format!("cdh-test-secret-{}", rand::random::<u32>());
+
+        let annotations = kms
+            .set_secret(b"secret".to_vec(), name.clone())
+            .await
+            .expect("set secret failed");
+        let secret = kms
+            .get_secret(&name, &annotations)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"secret");
+
+        assert!(kms.get_secret("not-exist", &HashMap::new()).await.is_err());
+    }
+}
@@ -0,0 +1,923 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! # HashiCorp Vault
+//!
+//! The driver maps the secrets to the [KV v2] secrets engine, and the
+//! encryption, data keys and signing to the [Transit] secrets engine of a
+//! Vault server. The data keys can only be of 16, 32 or 64 bytes.
+//!
+//! Settings of the driver:
+//! - `address`: URL of the Vault server, e.g. `https://vault.example.io:8200`.
+//! - `namespace`: the Vault namespace, optional.
+//! - `auth`: how to log in to Vault, defaults to `{ method = "token" }`.
+//!   - `{ method = "token", token = "..." }`: a static token, defaults to the
+//!     env `VAULT_TOKEN`.
+//!   - `{ method = "approle", role_id = "...", secret_id = "..." }`: the
+//!     AppRole auth method.
+//!   - `{ method = "jwt", role = "...", jwt_path = "..." }`: the JWT auth
+//!     method, with the JWT given by `jwt` or read from `jwt_path` at each
+//!     login, e.g. a projected service account token.
+//!
+//!   The `approle` and `jwt` methods log in to the auth method mounted at
+//!   `mount`, which defaults to the name of the method, and log in again when
+//!   the token expires or is rejected.
+//! - `kv_mount`: mount of the KV v2 engine, defaults to `secret`.
+//! - `transit_mount`: mount of the Transit engine, defaults to `transit`.
+//! - `timeout`: timeout in seconds of each request, defaults to 30.
+//!
+//! The annotations of the secrets are
+//! - `mount`: mount of the KV v2 engine, overriding `kv_mount`.
+//! - `path`: path of the secret, defaults to the name of the secret.
+//! - `version`: version of the secret, defaults to the latest one.
+//! - `key`: key of the secret in the data of the path, defaults to `value`.
+//! - `encoding`: `base64` if the secret is base64 encoded in Vault.
+//!
+//! The annotations of the encryption results are
+//! - `mount`: mount of the Transit engine, overriding `transit_mount`.
+//!
+//! [KV v2]: https://developer.hashicorp.com/vault/docs/secrets/kv/kv-v2
+//! [Transit]: https://developer.hashicorp.com/vault/docs/secrets/transit
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::{
+    error::{http_error, unavailable},
+    Capability, DataKey, Error, ProviderSettings, KMS,
+};
+
+/// Name of the driver
+pub const NAME: &str = "vault";
+
+/// Env of the token used by the `token` auth method
+pub const VAULT_TOKEN_ENV: &str = "VAULT_TOKEN";
+
+const TOKEN_HEADER: &str = "X-Vault-Token";
+const NAMESPACE_HEADER: &str = "X-Vault-Namespace";
+
+const DEFAULT_TIMEOUT_SEC: u64 = 30;
+
+/// Log in again this long before the token expires
+const TOKEN_RENEW_MARGIN_SEC: u64 = 30;
+
+const MOUNT_ANNOTATION: &str = "mount";
+const PATH_ANNOTATION: &str = "path";
+const VERSION_ANNOTATION: &str = "version";
+const KEY_ANNOTATION: &str = "key";
+const ENCODING_ANNOTATION: &str = "encoding";
+
+const DEFAULT_KEY: &str = "value";
+const BASE64_ENCODING: &str = "base64";
+
+fn default_approle_mount() -> String {
+    "approle".into()
+}
+
+fn default_jwt_mount() -> String {
+    "jwt".into()
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase", deny_unknown_fields)]
+enum Auth {
+    Token {
+        token: Option<String>,
+    },
+    AppRole {
+        role_id: String,
+        secret_id: String,
+        #[serde(default = "default_approle_mount")]
+        mount: String,
+    },
+    Jwt {
+        role: String,
+        jwt: Option<String>,
+        jwt_path: Option<PathBuf>,
+        #[serde(default = "default_jwt_mount")]
+        mount: String,
+    },
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self::Token { token: None }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    address: String,
+    namespace: Option<String>,
+    #[serde(default)]
+    auth: Auth,
+    kv_mount: Option<String>,
+    transit_mount: Option<String>,
+    timeout: Option<u64>,
+}
+
+struct Token {
+    value: String,
+
+    /// `None` if the token does not expire.
+    expires_at: Option<Instant>,
+}
+
+pub struct VaultKms {
+    address: String,
+    namespace: Option<String>,
+    auth: Auth,
+    kv_mount: String,
+    transit_mount: String,
+    http_client: reqwest::Client,
+
+    /// The token got by the last login
+    token: Mutex<Option<Token>>,
+}
+
+/// Builder of the driver registered in [`crate::Registry`].
+pub fn build(settings: &ProviderSettings) -> Result<Arc<dyn KMS>> {
+    let mut settings: Settings =
+        serde_json::from_value(settings.clone().into()).context("invalid settings of vault KMS")?;
+    match &mut settings.auth {
+        Auth::Token { token } if token.is_none() => {
+            let env_token = std::env::var(VAULT_TOKEN_ENV)
+                .map_err(|_| anyhow!("vault KMS requires `auth.token` or env {VAULT_TOKEN_ENV}"))?;
+            *token = Some(env_token);
+        }
+        Auth::Jwt { jwt, jwt_path, .. } if jwt.is_some() == jwt_path.is_some() => {
+            bail!("vault KMS requires exactly one of `auth.jwt` and `auth.jwt_path`")
+        }
+        _ => {}
+    }
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(
+            settings.timeout.unwrap_or(DEFAULT_TIMEOUT_SEC),
+        ))
+        .build()
+        .context("build http client")?;
+
+    Ok(Arc::new(VaultKms {
+        address: settings.address.trim_end_matches('/').to_string(),
+        namespace: settings.namespace,
+        auth: settings.auth,
+        kv_mount: settings.kv_mount.unwrap_or_else(|| "secret".into()),
+        transit_mount: settings.transit_mount.unwrap_or_else(|| "transit".into()),
+        http_client,
+        token: Mutex::new(None),
+    }))
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    errors: Vec<String>,
+}
+
+/// Check the status of the `response` of `what` and parse its body.
+async fn parse_response(response: Response, what: &str) -> Result<Value> {
+    let status = response.status();
+    if !status.is_success() {
+        let errors = response
+            .json::<ErrorResponse>()
+            .await
+            .map(|e| e.errors.join("; "))
+            .unwrap_or_default();
+        return Err(http_error(
+            status,
+            format!("vault {what} failed with {status}: {errors}"),
+        ));
+    }
+
+    if status == StatusCode::NO_CONTENT {
+        return Ok(Value::Null);
+    }
+
+    response
+        .json()
+        .await
+        .with_context(|| format!("parse response of vault {what}"))
+}
+
+/// Get the string at `pointer` of the response `value`.
+fn str_field<'a>(value: &'a Value, pointer: &str) -> Result<&'a str> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("no `{pointer}` in the response of vault"))
+}
+
+impl VaultKms {
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{path}", self.address)
+    }
+
+    fn with_namespace(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.namespace {
+            Some(namespace) => request.header(NAMESPACE_HEADER, namespace),
+            None => request,
+        }
+    }
+
+    /// Log in with the auth method.
+    async fn login(&self) -> Result<Token> {
+        let (mount, body) = match &self.auth {
+            Auth::Token { token } => {
+                return Ok(Token {
+                    value: token.clone().unwrap_or_default(),
+                    expires_at: None,
+                })
+            }
+            Auth::AppRole {
+                role_id,
+                secret_id,
+                mount,
+            } => (mount, json!({ "role_id": role_id, "secret_id": secret_id })),
+            Auth::Jwt {
+                role,
+                jwt,
+                jwt_path,
+                mount,
+            } => {
+                let jwt = match (jwt, jwt_path) {
+                    (Some(jwt), _) => jwt.clone(),
+                    (None, Some(path)) => tokio::fs::read_to_string(path)
+                        .await
+                        .with_context(|| format!("read JWT from {}", path.display()))?
+                        .trim()
+                        .to_string(),
+                    (None, None) => bail!("no JWT to log in to vault"),
+                };
+                (mount, json!({ "role": role, "jwt": jwt }))
+            }
+        };
+
+        let request = self
+            .http_client
+            .post(self.url(&format!("auth/{mount}/login")))
+            .json(&body);
+        let response = self
+            .with_namespace(request)
+            .send()
+            .await
+            .map_err(unavailable)?;
+        // Vault rejects wrong credentials by 400 for some auth methods.
+        let status = response.status();
+        let response = parse_response(response, "login").await.map_err(|e| {
+            match status.is_client_error() {
+                true => Error::PermissionDenied(format!("{e}")).into(),
+                false => e,
+            }
+        })?;
+
+        let value = str_field(&response, "/auth/client_token")?.to_string();
+        let lease = response
+            .pointer("/auth/lease_duration")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        let expires_at = (lease > 0).then(|| {
+            Instant::now() + Duration::from_secs(lease.saturating_sub(TOKEN_RENEW_MARGIN_SEC))
+        });
+        Ok(Token { value, expires_at })
+    }
+
+    /// Get a valid token, logging in if there is none.
+    async fn token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some(token) = &*token {
+            if token.expires_at.is_none_or(|at| Instant::now() < at) {
+                return Ok(token.value.clone());
+            }
+        }
+
+        let new_token = self.login().await?;
+        let value = new_token.value.clone();
+        *token = Some(new_token);
+        Ok(value)
+    }
+
+    /// Drop the token if it is still `stale`, so that the next request logs
+    /// in again.
+    async fn drop_token(&self, stale: &str) {
+        let mut token = self.token.lock().await;
+        if token.as_ref().is_some_and(|t| t.value == stale) {
+            *token = None;
+        }
+    }
+
+    /// Call the API of `path`. A request rejected for permission is retried
+    /// once after logging in again, unless a static token is used.
+    async fn call(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value> {
+        let what = format!("{method} {path}");
+        let response = self.send(method, path, body).await?;
+        parse_response(response, &what).await
+    }
+
+    /// Send the request of [`VaultKms::call`] without checking the response.
+    async fn send(&self, method: Method, path: &str, body: Option<Value>) -> Result<Response> {
+        let renewable = !matches!(self.auth, Auth::Token { .. });
+        let mut retry = renewable;
+        loop {
+            let token = self.token().await?;
+            let mut request = self
+                .http_client
+                .request(method.clone(), self.url(path))
+                .header(TOKEN_HEADER, &token);
+            if let Some(body) = &body {
+                request = request.json(body);
+            }
+
+            let response = self
+                .with_namespace(request)
+                .send()
+                .await
+                .map_err(unavailable)?;
+            if response.status() == StatusCode::FORBIDDEN && retry {
+                retry = false;
+                self.drop_token(&token).await;
+                continue;
+            }
+
+            return Ok(response);
+        }
+    }
+}
+
+#[async_trait]
+impl KMS for VaultKms {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::Encrypt,
+            Capability::Decrypt,
+            Capability::GenerateDataKey,
+            Capability::Sign,
+            Capability::Verify,
+            Capability::GetPublicKey,
+            Capability::GetSecret,
+            Capability::SetSecret,
+            Capability::ListSecrets,
+            Capability::DeleteSecret,
+        ]
+    }
+
+    async fn encrypt(
+        &self,
+        data: &[u8],
+        keyid: &str,
+    ) -> Result<(Vec<u8>, HashMap<String, String>)> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = json!({ "plaintext": engine.encode(data) });
+        let path = format!("{}/encrypt/{keyid}", self.transit_mount);
+        let response = self.call(Method::POST, &path, Some(body)).await?;
+        let ciphertext = str_field(&response, "/data/ciphertext")?;
+
+        let annotations = HashMap::from([(MOUNT_ANNOTATION.into(), self.transit_mount.clone())]);
+        Ok((ciphertext.as_bytes().to_vec(), annotations))
+    }
+
+    async fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        keyid: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let ciphertext = std::str::from_utf8(ciphertext).context("invalid transit ciphertext")?;
+        let mount = annotations
+            .get(MOUNT_ANNOTATION)
+            .unwrap_or(&self.transit_mount);
+        let body = json!({ "ciphertext": ciphertext });
+        let path = format!("{mount}/decrypt/{keyid}");
+        let response = self.call(Method::POST, &path, Some(body)).await?;
+        base64::engine::general_purpose::STANDARD
+            .decode(str_field(&response, "/data/plaintext")?)
+            .context("decode transit plaintext")
+    }
+
+    async fn generate_data_key(&self, keyid: &str, len: usize) -> Result<DataKey> {
+        if ![16, 32, 64].contains(&len) {
+            bail!("vault does not support data keys of {len} bytes");
+        }
+
+        let body = json!({ "bits": len * 8 });
+        let path = format!("{}/datakey/plaintext/{keyid}", self.transit_mount);
+        let response = self.call(Method::POST, &path, Some(body)).await?;
+        let plaintext = Zeroizing::new(
+            base64::engine::general_purpose::STANDARD
+                .decode(str_field(&response, "/data/plaintext")?)
+                .context("decode transit data key")?,
+        );
+        let ciphertext = str_field(&response, "/data/ciphertext")?;
+
+        Ok(DataKey {
+            plaintext,
+            ciphertext: ciphertext.as_bytes().to_vec(),
+            annotations: HashMap::from([(MOUNT_ANNOTATION.into(), self.transit_mount.clone())]),
+        })
+    }
+
+    async fn sign(&self, keyid: &str, message: &[u8]) -> Result<Vec<u8>> {
+        let body = json!({ "input": base64::engine::general_purpose::STANDARD.encode(message) });
+        let path = format!("{}/sign/{keyid}", self.transit_mount);
+        let response = self.call(Method::POST, &path, Some(body)).await?;
+        Ok(str_field(&response, "/data/signature")?.as_bytes().to_vec())
+    }
+
+    async fn verify(&self, keyid: &str, message: &[u8], signature: &[u8]) -> Result<bool> {
+        let signature = std::str::from_utf8(signature).context("invalid transit signature")?;
+        let body = json!({
+            "input": base64::engine::general_purpose::STANDARD.encode(message),
+            "signature": signature,
+        });
+        let path = format!("{}/verify/{keyid}", self.transit_mount);
+        let response = self.call(Method::POST, &path, Some(body)).await?;
+        response
+            .pointer("/data/valid")
+            .and_then(Value::as_bool)
+            .ok_or_else(|| anyhow!("no `/data/valid` in the response of vault"))
+    }
+
+    async fn get_public_key(&self, keyid: &str) -> Result<String> {
+        let path = format!("{}/keys/{keyid}", self.transit_mount);
+        let response = self.call(Method::GET, &path, None).await?;
+        let version = response
+            .pointer("/data/latest_version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("no `/data/latest_version` in the response of vault"))?;
+        let public_key = str_field(&response, &format!("/data/keys/{version}/public_key"))
+            .with_context(|| format!("transit key {keyid} has no public key"))?;
+        Ok(public_key.to_string())
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let mount = annotations.get(MOUNT_ANNOTATION).unwrap_or(&self.kv_mount);
+        let secret_path = annotations.get(PATH_ANNOTATION).map_or(name, |p| &p[..]);
+        let mut path = format!("{mount}/data/{secret_path}");
+        if let Some(version) = annotations.get(VERSION_ANNOTATION) {
+            path = format!("{path}?version={version}");
+        }
+
+        let response = self.call(Method::GET, &path, None).await?;
+        let key = annotations
+            .get(KEY_ANNOTATION)
+            .map_or(DEFAULT_KEY, |k| &k[..]);
+        let secret = response
+            .pointer("/data/data")
+            .and_then(|data| data.get(key))
+            .ok_or_else(|| anyhow!("no key `{key}` in vault secret {secret_path}"))?;
+        let secret = match secret {
+            Value::String(s) => s.as_bytes().to_vec(),
+            other => serde_json::to_vec(other)?,
+        };
+
+        match annotations.get(ENCODING_ANNOTATION).map(|e| &e[..]) {
+            None => Ok(secret),
+            Some(BASE64_ENCODING) => base64::engine::general_purpose::STANDARD
+                .decode(secret)
+                .context("decode base64 vault secret"),
+            Some(other) => bail!("unsupported encoding {other} of vault secret"),
+        }
+    }
+
+    async fn set_secret(&self, content: Vec<u8>, name: String) -> Result<HashMap<String, String>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = json!({ "data": { DEFAULT_KEY: engine.encode(content) } });
+        let path = format!("{}/data/{name}", self.kv_mount);
+        let response = self.call(Method::POST, &path, Some(body)).await?;
+        let version = response
+            .pointer("/data/version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("no version in the response of vault"))?;
+
+        Ok(HashMap::from([
+            (MOUNT_ANNOTATION.into(), self.kv_mount.clone()),
+            (PATH_ANNOTATION.into(), name),
+            (VERSION_ANNOTATION.into(), version.to_string()),
+            (KEY_ANNOTATION.into(), DEFAULT_KEY.into()),
+            (ENCODING_ANNOTATION.into(), BASE64_ENCODING.into()),
+        ]))
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<String>> {
+        let list = Method::from_bytes(b"LIST").expect("LIST is a valid method");
+        let path = format!("{}/metadata", self.kv_mount);
+        let response = self.send(list, &path, None).await?;
+        // Vault responds 404 if there is no secret.
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let response = parse_response(response, "LIST secrets").await?;
+        let keys = response
+            .pointer("/data/keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("no `/data/keys` in the response of vault"))?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| key.as_str().map(String::from))
+            .collect())
+    }
+
+    async fn delete_secret(&self, name: &str, annotations: &HashMap<String, String>) -> Result<()> {
+        let mount = annotations.get(MOUNT_ANNOTATION).unwrap_or(&self.kv_mount);
+        let secret_path = annotations.get(PATH_ANNOTATION).map_or(name, |p| &p[..]);
+        let path = format!("{mount}/metadata/{secret_path}");
+        self.call(Method::DELETE, &path, None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, Mutex},
+    };
+
+    use base64::Engine;
+    use hyper::{Method, StatusCode};
+    use rstest::rstest;
+    use serde_json::{json, Value};
+
+    use crate::{test_util, Error, Registry, KMS};
+
+    const ROOT_TOKEN: &str = "root";
+
+    /// An in-process stand-in of a Vault server, with the AppRole and JWT
+    /// auth methods, and the KV v2 and Transit engines at the default mounts.
+    #[derive(Default)]
+    struct FakeVault {
+        tokens: HashSet<String>,
+        logins: usize,
+        secrets: HashMap<String, Vec<Value>>,
+    }
+
+    impl FakeVault {
+        fn login(&mut self, authorized: bool) -> (StatusCode, Value) {
+            if !authorized {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json!({ "errors": ["invalid credentials"] }),
+                );
+            }
+
+            self.logins += 1;
+            let token = format!("token-{}", self.logins);
+            self.tokens.insert(token.clone());
+            let auth = json!({ "client_token": token, "lease_duration": 3600 });
+            (StatusCode::OK, json!({ "auth": auth }))
+        }
+
+        fn handle(
+            &mut self,
+            method: &Method,
+            path: &str,
+            query: Option<&str>,
+            token: Option<&str>,
+            body: Value,
+        ) -> (StatusCode, Value) {
+            match (method, path) {
+                (&Method::POST, "/v1/auth/approle/login") => {
+                    return self.login(body == json!({ "role_id": "role", "secret_id": "secret" }))
+                }
+                (&Method::POST, "/v1/auth/jwt/login") => {
+                    return self.login(body == json!({ "role": "cdh", "jwt": "jwt" }))
+                }
+                _ => {}
+            }
+
+            if !token.is_some_and(|t| t == ROOT_TOKEN || self.tokens.contains(t)) {
+                return (
+                    StatusCode::FORBIDDEN,
+                    json!({ "errors": ["permission denied"] }),
+                );
+            }
+
+            if path == "/v1/secret/metadata" && method.as_str() == "LIST" {
+                let mut keys: Vec<_> = self.secrets.keys().cloned().collect();
+                keys.sort();
+                return match keys.is_empty() {
+                    true => (StatusCode::NOT_FOUND, json!({ "errors": [] })),
+                    false => (StatusCode::OK, json!({ "data": { "keys": keys } })),
+                };
+            }
+
+            if let Some(secret) = path.strip_prefix("/v1/secret/metadata/") {
+                return match self.secrets.remove(secret) {
+                    Some(_) => (StatusCode::NO_CONTENT, Value::Null),
+                    None => (StatusCode::NOT_FOUND, json!({ "errors": [] })),
+                };
+            }
+
+            if let Some(secret) = path.strip_prefix("/v1/secret/data/") {
+                let versions = self.secrets.entry(secret.to_string()).or_default();
+                if method == Method::POST {
+                    versions.push(body["data"].clone());
+                    return (
+                        StatusCode::OK,
+                        json!({ "data": { "version": versions.len() } }),
+                    );
+                }
+
+                let version = query
+                    .and_then(|q| q.strip_prefix("version="))
+                    .map_or(versions.len(), |v| v.parse().unwrap());
+                return match versions.get(version.wrapping_sub(1)) {
+                    Some(data) => (StatusCode::OK, json!({ "data": { "data": data } })),
+                    None => (StatusCode::NOT_FOUND, json!({ "errors": [] })),
+                };
+            }
+
+            if let Some(key) = path.strip_prefix("/v1/transit/encrypt/") {
+                let plaintext = body["plaintext"].as_str().unwrap();
+                let ciphertext = format!("vault:v1:{key}:{plaintext}");
+                return (
+                    StatusCode::OK,
+                    json!({ "data": { "ciphertext": ciphertext } }),
+                );
+            }
+
+            if let Some(key) = path.strip_prefix("/v1/transit/datakey/plaintext/") {
+                let plaintext = base64::engine::general_purpose::STANDARD.encode(vec![
+                    7u8;
+                    body["bits"].as_u64().unwrap()
+                        as usize
+                        / 8
+                ]);
+                let ciphertext = format!("vault:v1:{key}:{plaintext}");
+                let data = json!({ "plaintext": plaintext, "ciphertext": ciphertext });
+                return (StatusCode::OK, json!({ "data": data }));
+            }
+
+            if let Some(key) = path.strip_prefix("/v1/transit/sign/") {
+                let signature = format!("vault:v1:{key}:{}", body["input"].as_str().unwrap());
+                return (
+                    StatusCode::OK,
+                    json!({ "data": { "signature": signature } }),
+                );
+            }
+
+            if let Some(key) = path.strip_prefix("/v1/transit/verify/") {
+                let expected = format!("vault:v1:{key}:{}", body["input"].as_str().unwrap());
+                let valid = body["signature"] == expected;
+                return (StatusCode::OK, json!({ "data": { "valid": valid } }));
+            }
+
+            if let Some(key) = path.strip_prefix("/v1/transit/keys/") {
+                let keys = json!({ "1": { "public_key": format!("PUBLIC KEY OF {key}") } });
+                let data = json!({ "latest_version": 1, "keys": keys });
+                return (StatusCode::OK, json!({ "data": data }));
+            }
+
+            if let Some(key) = path.strip_prefix("/v1/transit/decrypt/") {
+                let prefix = format!("vault:v1:{key}:");
+                return match body["ciphertext"].as_str().unwrap().strip_prefix(&prefix) {
+                    Some(plaintext) => (
+                        StatusCode::OK,
+                        json!({ "data": { "plaintext": plaintext } }),
+                    ),
+                    None => (
+                        StatusCode::BAD_REQUEST,
+                        json!({ "errors": ["cipher: message authentication failed"] }),
+                    ),
+                };
+            }
+
+            (StatusCode::NOT_FOUND, json!({ "errors": [] }))
+        }
+
+        /// Serve the `vault` on a random local port and return its address.
+        fn start(vault: Arc<Mutex<FakeVault>>) -> String {
+            test_util::serve(move |req| {
+                let body = serde_json::from_slice(&req.body).unwrap_or_default();
+                vault.lock().unwrap().handle(
+                    &req.method,
+                    req.uri.path(),
+                    req.uri.query(),
+                    req.header("X-Vault-Token"),
+                    body,
+                )
+            })
+        }
+    }
+
+    async fn vault_kms(auth: Value) -> (Arc<dyn KMS>, Arc<Mutex<FakeVault>>) {
+        let vault = Arc::new(Mutex::new(FakeVault::default()));
+        let settings = json!({ "address": FakeVault::start(vault.clone()), "auth": auth });
+        let kms = Registry::builtin()
+            .new_client("vault", settings.as_object().unwrap())
+            .await
+            .expect("create vault kms failed");
+        (kms, vault)
+    }
+
+    #[rstest]
+    #[case(json!({ "method": "token", "token": ROOT_TOKEN }), 0)]
+    #[case(json!({ "method": "approle", "role_id": "role", "secret_id": "secret" }), 1)]
+    #[case(json!({ "method": "jwt", "role": "cdh", "jwt": "jwt" }), 1)]
+    #[tokio::test]
+    async fn set_get_secret(#[case] auth: Value, #[case] logins: usize) {
+        let (kms, vault) = vault_kms(auth).await;
+
+        let v1 = kms
+            .set_secret(b"\x00v1".to_vec(), "app/db".into())
+            .await
+            .expect("set secret failed");
+        kms.set_secret(b"v2".to_vec(), "app/db".into())
+            .await
+            .expect("set secret failed");
+        assert_eq!(v1["version"], "1");
+
+        let secret = kms
+            .get_secret("app/db", &v1)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"\x00v1");
+
+        let latest = HashMap::from([("encoding".to_string(), "base64".to_string())]);
+        let secret = kms
+            .get_secret("app/db", &latest)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"v2");
+
+        assert_eq!(vault.lock().unwrap().logins, logins);
+    }
+
+    #[tokio::test]
+    async fn get_plain_secret() {
+        let (kms, vault) = vault_kms(json!({ "method": "token", "token": ROOT_TOKEN })).await;
+        vault.lock().unwrap().secrets.insert(
+            "app/config".into(),
+            vec![json!({ "password": "plain", "ports": [80] })],
+        );
+
+        for (key, expected) in [("password", &b"plain"[..]), ("ports", b"[80]")] {
+            let annotations = HashMap::from([
+                ("path".to_string(), "app/config".to_string()),
+                ("key".to_string(), key.to_string()),
+            ]);
+            let secret = kms
+                .get_secret("any", &annotations)
+                .await
+                .expect("get secret failed");
+            assert_eq!(secret, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypt_decrypt() {
+        let (kms, _vault) = vault_kms(json!({ "method": "token", "token": ROOT_TOKEN })).await;
+
+        let (ciphertext, annotations) = kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        assert_eq!(annotations["mount"], "transit");
+        let plaintext = kms
+            .decrypt(&ciphertext, "key1", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+
+        assert!(kms
+            .decrypt(&ciphertext, "key2", &annotations)
+            .await
+            .is_err());
+
+        let data_key = kms
+            .generate_data_key("key1", 32)
+            .await
+            .expect("generate data key failed");
+        let plaintext = kms
+            .decrypt(&data_key.ciphertext, "key1", &data_key.annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, *data_key.plaintext);
+        assert!(kms.generate_data_key("key1", 20).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sign_verify() {
+        let (kms, _vault) = vault_kms(json!({ "method": "token", "token": ROOT_TOKEN })).await;
+
+        let signature = kms.sign("key1", b"data").await.expect("sign failed");
+        for (message, valid) in [(&b"data"[..], true), (b"other", false)] {
+            let verified = kms
+                .verify("key1", message, &signature)
+                .await
+                .expect("verify failed");
+            assert_eq!(verified, valid);
+        }
+
+        let public_key = kms
+            .get_public_key("key1")
+            .await
+            .expect("get public key failed");
+        assert_eq!(public_key, "PUBLIC KEY OF key1");
+    }
+
+    #[tokio::test]
+    async fn list_delete_secrets() {
+        let (kms, _vault) = vault_kms(json!({ "method": "token", "token": ROOT_TOKEN })).await;
+        assert!(kms.list_secrets().await.expect("list failed").is_empty());
+
+        let annotations = kms
+            .set_secret(b"secret".to_vec(), "db".into())
+            .await
+            .expect("set secret failed");
+        assert_eq!(kms.list_secrets().await.expect("list failed"), ["db"]);
+
+        kms.delete_secret("db", &annotations)
+            .await
+            .expect("delete secret failed");
+        let err = kms
+            .get_secret("db", &annotations)
+            .await
+            .expect_err("get deleted secret should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn login_again_when_rejected() {
+        let jwt_file = tempfile::NamedTempFile::new().expect("create jwt file failed");
+        std::fs::write(jwt_file.path(), "jwt\n").expect("write jwt failed");
+        let (kms, vault) =
+            vault_kms(json!({ "method": "jwt", "role": "cdh", "jwt_path": jwt_file.path() })).await;
+
+        kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        vault.lock().unwrap().tokens.clear();
+        kms.encrypt(b"data", "key1").await.expect("encrypt failed");
+        assert_eq!(vault.lock().unwrap().logins, 2);
+    }
+
+    #[rstest]
+    #[case(json!({ "method": "token", "token": "wrong" }))]
+    #[case(json!({ "method": "approle", "role_id": "role", "secret_id": "wrong" }))]
+    #[tokio::test]
+    async fn permission_denied(#[case] auth: Value) {
+        let (kms, _vault) = vault_kms(auth).await;
+        let err = kms
+            .encrypt(b"data", "key1")
+            .await
+            .expect_err("encrypt should fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::PermissionDenied(_))
+        ));
+    }
+
+    /// Run against a `vault server -dev` if `VAULT_ADDR` and `VAULT_TOKEN` are
+    /// given, with the Transit engine enabled by `vault secrets enable transit`.
+    #[tokio::test]
+    async fn dev_server() {
+        let Ok(address) = std::env::var("VAULT_ADDR") else {
+            return;
+        };
+        let settings = json!({ "address": address });
+        let kms = Registry::builtin()
+            .new_client("vault", settings.as_object().unwrap())
+            .await
+            .expect("create vault kms failed");
+
+        let annotations = kms
+            .set_secret(b"secret".to_vec(), "cdh/test".into())
+            .await
+            .expect("set secret failed");
+        let secret = kms
+            .get_secret("cdh/test", &annotations)
+            .await
+            .expect("get secret failed");
+        assert_eq!(secret, b"secret");
+
+        let (ciphertext, annotations) = kms.encrypt(b"data", "cdh").await.expect("encrypt failed");
+        let plaintext = kms
+            .decrypt(&ciphertext, "cdh", &annotations)
+            .await
+            .expect("decrypt failed");
+        assert_eq!(plaintext, b"data");
+    }
+}
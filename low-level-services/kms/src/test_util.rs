@@ -0,0 +1,70 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! A local HTTP server for the in-process fakes of the remote KMSes, so
+//! that each fake only defines how it answers the requests.
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    body::Bytes,
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode, Uri,
+};
+use serde_json::Value;
+
+/// A request received by a fake, with its whole body.
+pub struct FakeRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl FakeRequest {
+    /// The value of header `name`, if given and valid.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+/// Serve `handle` on a random local port until the tokio runtime of the
+/// test stops, and return the address of the server like
+/// `http://127.0.0.1:port`. A `null` response body is sent as an empty one.
+pub fn serve<F>(handle: F) -> String
+where
+    F: Fn(FakeRequest) -> (StatusCode, Value) + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let make_service = make_service_fn(move |_| {
+        let handle = handle.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let handle = handle.clone();
+                async move {
+                    let (parts, body) = req.into_parts();
+                    let request = FakeRequest {
+                        method: parts.method,
+                        uri: parts.uri,
+                        headers: parts.headers,
+                        body: hyper::body::to_bytes(body).await.unwrap(),
+                    };
+                    let (status, body) = handle(request);
+                    let body = match body {
+                        Value::Null => Body::empty(),
+                        body => Body::from(body.to_string()),
+                    };
+                    let response = Response::builder().status(status).body(body).unwrap();
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let address = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    address
+}
@@ -3,20 +3,20 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::*;
 use base64::Engine;
 use clap::Parser;
-use kbs_client::Client as KbsClient;
+use crypto::WrapType;
+use kbs_client::{AaClient, Client as KbsClient, AA_POD_DOMAIN, KBS_REQ_TIMEOUT_SEC};
 use secret::{
     secret::{
         layout::{envelope::Envelope, vault::VaultSecret},
-        SealType, Secret, SecretContent,
+        SealType, Secret, SecretContent, VERSION,
     },
     unsealer::UnSealer,
 };
-use tokio::sync::Mutex;
 
 #[derive(Parser)] // requires `derive` feature
 #[command(name = "secret")]
@@ -38,6 +38,11 @@ struct UnsealArgs {
     /// secret is sealed by a KBS.
     #[arg(short, long)]
     kbs_addr: Option<String>,
+
+    /// Endpoint of the attestation-agent, either a URI or a Unix domain
+    /// socket. Used when the secret is sealed by a KBS.
+    #[arg(long, default_value = AA_POD_DOMAIN)]
+    aa_endpoint: String,
 }
 
 #[derive(clap::Args)]
@@ -60,9 +65,19 @@ struct SealArgs {
     #[arg(short, long)]
     kbs_addr: Option<String>,
 
+    /// Endpoint of the attestation-agent, either a URI or a Unix domain
+    /// socket. Used when the secret is sealed by a KBS.
+    #[arg(long, default_value = AA_POD_DOMAIN)]
+    aa_endpoint: String,
+
     /// Type of the Secret, i.e. `vault` or `envelope`
     #[arg(short, long)]
     r#type: String,
+
+    /// Wrap type to encrypt the secret of an envelope, i.e. `A256GCM`,
+    /// `C20P`, `XC20P` or `A256GCM-SIV`
+    #[arg(long, default_value = "A256GCM")]
+    wrap_type: String,
 }
 
 #[tokio::main]
@@ -71,7 +86,8 @@ async fn main() -> Result<()> {
     match args {
         Cli::Unseal(para) => {
             let secret: Secret = serde_json::from_str(&para.blob)?;
-            let client = get_unsealer(secret.provider.clone(), para.kbs_addr).await?;
+            let client =
+                get_unsealer(secret.provider.clone(), para.kbs_addr, &para.aa_endpoint).await?;
             let content = client.unseal(secret).await?;
             let base64encoded = base64::engine::general_purpose::STANDARD.encode(content);
             println!("{base64encoded}");
@@ -79,7 +95,17 @@ async fn main() -> Result<()> {
         Cli::Seal(para) => {
             let blob = base64::engine::general_purpose::STANDARD.decode(&para.blob)?;
             let typ = SealType::try_from(&para.r#type[..])?;
-            let secret = seal(para.provider, para.keyid, para.kbs_addr, typ, blob).await?;
+            let wrap_type = WrapType::try_from(&para.wrap_type[..])?;
+            let secret = seal(
+                para.provider,
+                para.keyid,
+                para.kbs_addr,
+                &para.aa_endpoint,
+                typ,
+                wrap_type,
+                blob,
+            )
+            .await?;
             let res_str = serde_json::to_string_pretty(&secret)?;
             println!("{res_str}");
         }
@@ -89,24 +115,29 @@ async fn main() -> Result<()> {
 }
 
 const KBS_PROVIDER_NAME: &str = "kbs";
-const VERSION: &str = "0.1.0";
 
 async fn seal(
     provider: String,
     kid: String,
     kbs_addr: Option<String>,
+    aa_endpoint: &str,
     typ: SealType,
+    wrap_type: WrapType,
     data: Vec<u8>,
 ) -> Result<Secret> {
     if provider == KBS_PROVIDER_NAME {
         let kbs_addr = kbs_addr.ok_or_else(|| {
             anyhow!("If kbs is used to seal secret, `kbs_addr` parameter must be given!")
         })?;
-        let client = Arc::new(Mutex::new(KbsClient::new(kbs_addr).await?));
+        let client = Arc::new(KbsClient::new(
+            kbs_addr,
+            Arc::new(AaClient::new(aa_endpoint).await?),
+            Duration::from_secs(KBS_REQ_TIMEOUT_SEC),
+        )?);
 
         match typ {
             SealType::Envelope => {
-                let e = Envelope::seal_with_kbs(kid, data, client).await?;
+                let e = Envelope::seal_with_kbs(kid, data, wrap_type, client).await?;
                 Ok(Secret {
                     version: VERSION.into(),
                     provider,
@@ -124,7 +155,7 @@ async fn seal(
         }
     } else {
         // We need to impl KMS client init here
-        // let e = Envelope::seal_with_kms(name, data, k.clone()).await?;
+        // let e = Envelope::seal_with_kms(&provider, name, data, wrap_type, k.clone()).await?;
         // Ok(Secret {
         //     version: VERSION.into(),
         //     provider,
@@ -134,12 +165,20 @@ async fn seal(
     }
 }
 
-async fn get_unsealer(provider: String, kbs_addr: Option<String>) -> Result<UnSealer> {
+async fn get_unsealer(
+    provider: String,
+    kbs_addr: Option<String>,
+    aa_endpoint: &str,
+) -> Result<UnSealer> {
     if provider == "kbs" {
         let kbs_addr = kbs_addr.ok_or_else(|| {
             anyhow!("If kbs is used to seal secret, `kbs_addr` parameter must be given!")
         })?;
-        let client = Arc::new(Mutex::new(KbsClient::new(kbs_addr).await?));
+        let client = Arc::new(KbsClient::new(
+            kbs_addr,
+            Arc::new(AaClient::new(aa_endpoint).await?),
+            Duration::from_secs(KBS_REQ_TIMEOUT_SEC),
+        )?);
         Ok(client.into())
     } else {
         todo!()